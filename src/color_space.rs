@@ -0,0 +1,174 @@
+//! RGB color spaces used for rendering and by RGB tables, defined by their
+//! primaries, white point and transfer function
+
+use crate::color_spec::map_white_matrix;
+use crate::coord::Coord;
+use crate::matrix::Matrix;
+use crate::traits::{GammaSrgb, LinearFn};
+
+/// RGB primaries and white point
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub enum Primaries {
+    #[default]
+    Srgb = 0,
+    AdobeRgb = 1,
+    ProPhoto = 2,
+    DisplayP3 = 3,
+    Rec2020 = 4,
+}
+
+impl Primaries {
+    pub fn from_tiff(value: u32) -> Option<Self> {
+        Some(match value {
+            0 => Self::Srgb,
+            1 => Self::AdobeRgb,
+            2 => Self::ProPhoto,
+            3 => Self::DisplayP3,
+            4 => Self::Rec2020,
+            _ => return None,
+        })
+    }
+
+    /// The xy coordinates of the red, green and blue primaries, and of white
+    pub fn coords(&self) -> [Coord; 4] {
+        match self {
+            Self::Srgb => [
+                Coord::new(0.64, 0.33),
+                Coord::new(0.30, 0.60),
+                Coord::new(0.15, 0.06),
+                Coord::D65_XY_COORD,
+            ],
+            Self::AdobeRgb => [
+                Coord::new(0.64, 0.33),
+                Coord::new(0.21, 0.71),
+                Coord::new(0.15, 0.06),
+                Coord::D65_XY_COORD,
+            ],
+            Self::ProPhoto => [
+                Coord::new(0.7347, 0.2653),
+                Coord::new(0.1596, 0.8404),
+                Coord::new(0.0366, 0.0001),
+                Coord::D50_XY_COORD,
+            ],
+            Self::DisplayP3 => [
+                Coord::new(0.680, 0.320),
+                Coord::new(0.265, 0.690),
+                Coord::new(0.150, 0.060),
+                Coord::D65_XY_COORD,
+            ],
+            Self::Rec2020 => [
+                Coord::new(0.708, 0.292),
+                Coord::new(0.170, 0.797),
+                Coord::new(0.131, 0.046),
+                Coord::D65_XY_COORD,
+            ],
+        }
+    }
+
+    /// The matrix from linear RGB to the PCS (XYZ with a D50 white), with a
+    /// Bradford adaptation from the white of the primaries
+    pub fn to_pcs(&self) -> crate::Result<Matrix<3>> {
+        let [red, green, blue, white] = self.coords();
+        let mut primaries = Matrix::default();
+        for (col, primary) in [red, green, blue].into_iter().enumerate() {
+            let xyz = Coord::xy_to_xyz(primary);
+            for row in 0..3 {
+                primaries.data[row][col] = xyz[row];
+            }
+        }
+        // Scale the primaries so RGB white maps to the white point
+        let scale = primaries.invert()? * Coord::xy_to_xyz(white);
+        let to_xyz = primaries * Matrix::from_diagonal(scale);
+        Ok(map_white_matrix(white, Coord::PCS_TO_XY)? * to_xyz)
+    }
+
+    pub fn from_pcs(&self) -> crate::Result<Matrix<3>> {
+        self.to_pcs()?.invert()
+    }
+
+    /// The usual transfer function of the color space
+    pub fn transfer_function(&self) -> TransferFunction {
+        match self {
+            Self::Srgb | Self::DisplayP3 => TransferFunction::Srgb,
+            Self::AdobeRgb => TransferFunction::Gamma22,
+            Self::ProPhoto => TransferFunction::Gamma18,
+            Self::Rec2020 => TransferFunction::Rec2020,
+        }
+    }
+}
+
+/// Encoding of linear values
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub enum TransferFunction {
+    #[default]
+    Linear = 0,
+    Srgb = 1,
+    /// ProPhoto RGB, a 1.8 gamma with a linear toe
+    Gamma18 = 2,
+    /// Adobe RGB, a 563/256 gamma
+    Gamma22 = 3,
+    Rec2020 = 4,
+}
+
+impl TransferFunction {
+    pub fn from_tiff(value: u32) -> Option<Self> {
+        Some(match value {
+            0 => Self::Linear,
+            1 => Self::Srgb,
+            2 => Self::Gamma18,
+            3 => Self::Gamma22,
+            4 => Self::Rec2020,
+            _ => return None,
+        })
+    }
+
+    /// Linear to encoded, for values in 0.0..=1.0
+    pub fn encode(&self, x: f64) -> f64 {
+        match self {
+            Self::Linear => x,
+            Self::Srgb => GammaSrgb.evaluate(x),
+            Self::Gamma18 if x < 1.0 / 512.0 => x * 16.0,
+            Self::Gamma18 => x.max(0.0).powf(1.0 / 1.8),
+            Self::Gamma22 => x.max(0.0).powf(256.0 / 563.0),
+            Self::Rec2020 if x < 0.018053968510807 => x * 4.5,
+            Self::Rec2020 => 1.09929682680944 * x.powf(0.45) - 0.09929682680944,
+        }
+    }
+
+    /// Encoded to linear, the inverse of [`TransferFunction::encode`]
+    pub fn decode(&self, y: f64) -> f64 {
+        match self {
+            Self::Linear => y,
+            Self::Srgb => GammaSrgb.reverse_evaluate(y),
+            Self::Gamma18 if y < 16.0 / 512.0 => y / 16.0,
+            Self::Gamma18 => y.powf(1.8),
+            Self::Gamma22 => y.max(0.0).powf(563.0 / 256.0),
+            Self::Rec2020 if y < 0.018053968510807 * 4.5 => y / 4.5,
+            Self::Rec2020 => ((y + 0.09929682680944) / 1.09929682680944).powf(1.0 / 0.45),
+        }
+    }
+}
+
+#[test]
+fn test_primaries_to_pcs() {
+    use crate::matrix::Vector;
+
+    // Every space maps its white to the PCS white
+    for primaries in [
+        Primaries::Srgb,
+        Primaries::AdobeRgb,
+        Primaries::ProPhoto,
+        Primaries::DisplayP3,
+        Primaries::Rec2020,
+    ] {
+        let white = primaries.to_pcs().unwrap() * Vector::new([1.0; 3]);
+        let pcs = Coord::PCS_TO_XYZ();
+        for i in 0..3 {
+            assert!((white[i] - pcs[i]).abs() < 1e-9, "{primaries:?}");
+        }
+        let function = primaries.transfer_function();
+        for x in [0.001, 0.2, 0.9] {
+            assert!((function.decode(function.encode(x)) - x).abs() < 1e-12);
+        }
+    }
+}
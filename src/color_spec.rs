@@ -2,6 +2,9 @@ use crate::coord::Coord;
 use crate::errors::{Error, ErrorKind};
 use crate::illuminant::Illuminant;
 use crate::matrix::{Matrix, Vector};
+use crate::negative::Negative;
+use crate::shared::CameraProfileInfo;
+use crate::tag::values::LightSource;
 use crate::temparature::Temperature;
 
 pub struct ColorSpec<
@@ -35,7 +38,245 @@ pub struct ColorSpec<
     pub pcs_to_camera: Matrix<ROWS, COLS>,
 }
 
+/// The linearized Bradford chromatic adaptation from one white to another
+pub fn map_white_matrix(white1: Coord, white2: Coord) -> crate::Result<Matrix<3>> {
+    const BRADFORD: Matrix<3> = Matrix::new([
+        [0.8951, 0.2664, -0.1614],
+        [-0.7502, 1.7135, 0.0367],
+        [0.0389, -0.0685, 1.0296],
+    ]);
+
+    let w1 = BRADFORD * Coord::xy_to_xyz(white1);
+    let w2 = BRADFORD * Coord::xy_to_xyz(white2);
+
+    // Negative cone responses are meaningless, and the scaling is limited to
+    // something reasonable
+    let mut scale = Matrix::default();
+    for i in 0..3 {
+        let (c1, c2) = (w1[i].max(0.0), w2[i].max(0.0));
+        let ratio = if c1 > 0.0 { c2 / c1 } else { 10.0 };
+        scale.data[i][i] = ratio.clamp(0.1, 10.0);
+    }
+    Ok(BRADFORD.invert()? * scale * BRADFORD)
+}
+
 impl<const ILML: usize> ColorSpec<ILML, 3> {
+    /// Build the color spec of a negative rendered with a camera profile.
+    ///
+    /// ILML is the number of illuminants the spec interpolates between. A
+    /// single illuminant profile can be used for a dual illuminant spec, but
+    /// triple illuminant specs need triple illuminant profiles. The matrices
+    /// are computed for the "As Shot" white balance of the negative, or D55
+    /// if it has none.
+    pub fn new(profile: &CameraProfileInfo, negative: &Negative) -> crate::Result<Self> {
+        let channels = negative.color_channels();
+        if channels != 1 && channels != 3 {
+            return Err(Error::new(ErrorKind::InvalidCameraProfile));
+        }
+
+        let color_matrices = [
+            profile.color_matrix1,
+            profile.color_matrix2,
+            profile.color_matrix3,
+        ];
+        if channels == 3 && color_matrices[0].is_empty() {
+            return Err(Error::new(ErrorKind::InvalidCameraProfile));
+        }
+        let count = 1 + color_matrices[1..]
+            .iter()
+            .take_while(|matrix| !matrix.is_empty())
+            .count();
+        if ILML == 0 || count > ILML || (ILML == 3 && count != 3) {
+            return Err(Error::new(ErrorKind::InvalidColorSpec(count)));
+        }
+        // Missing illuminants repeat the last one the profile has
+        let pick = |index: usize| index.min(count - 1);
+
+        let illuminants = [
+            profile.calibration_illuminant1,
+            profile.calibration_illuminant2,
+            profile.calibration_illuminant3,
+        ];
+        let data = [
+            &profile.illuminant_data1,
+            &profile.illuminant_data2,
+            &profile.illuminant_data3,
+        ];
+        let light: [Illuminant; 3] =
+            std::array::from_fn(|index| match LightSource::from_tiff(illuminants[index]) {
+                Some(light) => Illuminant::illuminant_data(&light, data[index].clone()),
+                None => data[index].clone(),
+            });
+        let forward_matrices = [
+            profile.forward_matrix1,
+            profile.forward_matrix2,
+            profile.forward_matrix3,
+        ];
+        let reduction_matrices = [
+            profile.reduction_matrix1,
+            profile.reduction_matrix2,
+            profile.reduction_matrix3,
+        ];
+        let temperature =
+            |index: usize| Illuminant::calibration_temperature(illuminants[index], &light[index]);
+
+        // The individual calibration of the camera only applies to the
+        // profile it was made for
+        let calibrated = profile.uses_camera_calibration(negative.camera_calibration_signature());
+
+        let mut spec = Self {
+            channels,
+            temparature1: temperature(0),
+            temparature2: temperature(pick(1)),
+            light,
+            color_matrix: std::array::from_fn(|index| color_matrices[pick(index)]),
+            forward_matrix: std::array::from_fn(|index| {
+                let matrix = forward_matrices[pick(index)];
+                (!matrix.is_empty()).then(|| Self::normalize_forward_matrix(matrix))
+            }),
+            reduction_matrix: std::array::from_fn(|index| {
+                let matrix = reduction_matrices[pick(index)];
+                (!matrix.is_empty()).then_some(matrix)
+            }),
+            camera_calibration: std::array::from_fn(|index| {
+                negative
+                    .camera_calibration(pick(index) + 1)
+                    .filter(|_| calibrated)
+                    .unwrap_or(Matrix::identity())
+            }),
+            analog_balance: Matrix::from_diagonal(negative.analog_balance()),
+            white_xy: Coord::default(),
+            camera_white: Vector::new([1.0; 3]),
+            camera_to_pcs: Matrix::default(),
+            pcs_to_camera: Matrix::default(),
+        };
+
+        // Dual illuminant interpolation expects the lower temperature first
+        if ILML == 2 && spec.temparature1 > spec.temparature2 {
+            std::mem::swap(&mut spec.temparature1, &mut spec.temparature2);
+            spec.light.swap(0, 1);
+            spec.color_matrix.swap(0, 1);
+            spec.forward_matrix.swap(0, 1);
+            spec.reduction_matrix.swap(0, 1);
+            spec.camera_calibration.swap(0, 1);
+        }
+
+        let white = match (negative.camera_neutral(), negative.camera_white_xy()) {
+            (Some(neutral), _) if channels == 3 => spec.neutral_to_xy(neutral)?,
+            (_, Some(white)) => white,
+            _ => Coord::D55_XY_COORD,
+        };
+        spec.set_white_xy(white)?;
+        Ok(spec)
+    }
+
+    /// Scale the rows of a forward matrix so that camera white (all ones)
+    /// maps to the PCS white
+    fn normalize_forward_matrix(matrix: Matrix<3>) -> Matrix<3> {
+        let xyz = matrix * Vector::new([1.0; 3]);
+        let pcs = Coord::PCS_TO_XYZ();
+        let mut scale = Matrix::default();
+        for i in 0..3 {
+            scale.data[i][i] = if xyz[i] != 0.0 { pcs[i] / xyz[i] } else { 0.0 };
+        }
+        scale * matrix
+    }
+
+    pub fn white_xy(&self) -> Coord {
+        self.white_xy
+    }
+
+    /// Set the white balance and recompute the camera white and the matrices
+    /// between camera space and the PCS for it.
+    ///
+    /// With forward matrices, camera values are first normalized so the
+    /// camera white becomes the reference neutral, then mapped to the PCS by
+    /// the forward matrix. Without them, the color matrix is combined with a
+    /// Bradford adaptation from the white to the PCS white (D50) and
+    /// inverted.
+    pub fn set_white_xy(&mut self, white: Coord) -> crate::Result<()> {
+        self.white_xy = white;
+        if self.channels == 1 {
+            self.camera_white = Vector::new([1.0; 3]);
+            let pcs = Coord::PCS_TO_XYZ();
+            self.camera_to_pcs = Matrix::default();
+            for i in 0..3 {
+                self.camera_to_pcs.data[i][0] = pcs[i];
+            }
+            self.pcs_to_camera = Matrix::default();
+            return Ok(());
+        }
+
+        // Interpolate the matrices for this white
+        let mut forward_matrix = self.forward_matrix[0].map(|_| Matrix::default());
+        let mut camera_calibration = Some(Matrix::identity());
+        let color_matrix = self.find_xyz_to_camera(
+            &white,
+            &mut forward_matrix,
+            &mut None,
+            &mut camera_calibration,
+        )?;
+        let camera_calibration = camera_calibration.unwrap_or(Matrix::identity());
+        let xyz_to_camera = self.analog_balance * camera_calibration * color_matrix;
+
+        // Find the camera white values
+        let camera_white = xyz_to_camera * Coord::xy_to_xyz(white);
+        let white_scale = 1.0 / camera_white.max_entry();
+        for i in 0..3 {
+            self.camera_white[i] = (white_scale * camera_white[i]).clamp(0.001, 1.0);
+        }
+
+        // Find the PCS to camera transform, scaled so the PCS white is just
+        // reached when the first camera channel saturates
+        let pcs_to_camera = xyz_to_camera * map_white_matrix(Coord::PCS_TO_XY, white)?;
+        let scale = (pcs_to_camera * Coord::PCS_TO_XYZ()).max_entry();
+        self.pcs_to_camera = pcs_to_camera * (1.0 / scale);
+
+        self.camera_to_pcs = match forward_matrix.filter(|matrix| !matrix.is_empty()) {
+            Some(forward_matrix) => {
+                let individual_to_reference =
+                    (self.analog_balance * camera_calibration).invert()?;
+                let reference_white = individual_to_reference * self.camera_white;
+                forward_matrix
+                    * Matrix::from_diagonal(reference_white).invert()?
+                    * individual_to_reference
+            }
+            // The DNG specification only uses the reduction matrix for
+            // cameras with more than three channels, which `new` rejects, so
+            // the square PCS to camera matrix inverts as is
+            None => self.pcs_to_camera.invert()?,
+        };
+        Ok(())
+    }
+
+    /// The camera neutral (the AsShotNeutral of a file) of a white balance
+    /// given as a temperature and tint, scaled so its largest entry is 1.0
+    pub fn temperature_to_neutral(
+        &self,
+        temperature: &Temperature,
+    ) -> crate::Result<Vector<f64, 3>> {
+        if self.channels == 1 {
+            return Ok(Vector::new([1.0; 3]));
+        }
+        let white = temperature.to_xy();
+        let neutral = self.xyz_to_camera(&white)? * Coord::xy_to_xyz(white);
+        let scale = 1.0 / neutral.max_entry();
+        let mut result = Vector::new([1.0; 3]);
+        for i in 0..3 {
+            result[i] = (scale * neutral[i]).clamp(0.001, 1.0);
+        }
+        Ok(result)
+    }
+
+    /// The full XYZ to camera transform at a white, including the analog
+    /// balance and camera calibration
+    fn xyz_to_camera(&self, white: &Coord) -> crate::Result<Matrix<3>> {
+        let mut camera_calibration = Some(Matrix::identity());
+        let color_matrix =
+            self.find_xyz_to_camera(white, &mut None, &mut None, &mut camera_calibration)?;
+        Ok(self.analog_balance * camera_calibration.unwrap_or(Matrix::identity()) * color_matrix)
+    }
+
     pub fn neutral_to_xy(&self, neutral: Vector<f64, 3>) -> crate::Result<Coord> {
         const MAX_PASS: u32 = 30;
 
@@ -47,9 +288,9 @@ impl<const ILML: usize> ColorSpec<ILML, 3> {
         // in a two value oscillation.	So take the average of the last
         // two estimates and give up.
         (0..MAX_PASS).try_fold(Coord::D50_XY_COORD, |last, current| {
-            let xyz_to_camera = self.find_xyz_to_camera(&last, None, None, None)?;
+            let xyz_to_camera = self.xyz_to_camera(&last)?;
 
-            let next = Coord::xyz_to_xy(xyz_to_camera.invert() * neutral);
+            let next = Coord::xyz_to_xy(xyz_to_camera.invert()? * neutral);
 
             if (next.x - last.x).abs() + (next.y - last.y).abs() < 0.0000001 {
                 return Ok(next);
@@ -66,22 +307,22 @@ impl<const ILML: usize> ColorSpec<ILML, 3> {
     pub fn find_xyz_to_camera(
         &self,
         white: &Coord,
-        mut forward_matrix: Option<Matrix<3>>,
-        mut reduction_matrix: Option<Matrix<3>>,
-        mut camera_calibration: Option<Matrix<3>>,
+        forward_matrix: &mut Option<Matrix<3>>,
+        reduction_matrix: &mut Option<Matrix<3>>,
+        camera_calibration: &mut Option<Matrix<3>>,
     ) -> crate::Result<Matrix<3>> {
         match ILML {
             1 | 2 => self.find_xyz_to_camera_single_or_dual(
                 white,
-                &mut forward_matrix,
-                &mut reduction_matrix,
-                &mut camera_calibration,
+                forward_matrix,
+                reduction_matrix,
+                camera_calibration,
             ),
             3 => self.find_xyz_to_camera_triple(
                 white,
-                &mut forward_matrix,
-                &mut reduction_matrix,
-                &mut camera_calibration,
+                forward_matrix,
+                reduction_matrix,
+                camera_calibration,
             ),
             c => Err(Error::new(ErrorKind::InvalidColorSpec(c))),
         }
@@ -94,6 +335,9 @@ impl<const ILML: usize> ColorSpec<ILML, 3> {
         reduction_matrix: &mut Option<Matrix<3>>,
         camera_calibration: &mut Option<Matrix<3>>,
     ) -> crate::Result<Matrix<3>> {
+        // The second illuminant, which is the first one for single
+        // illuminant specs
+        let second = ILML - 1;
         let td = Temperature::from_coord(white)?;
         let g: f64 = match td.temperature {
             t if t <= self.temparature1 => 1.0,
@@ -109,13 +353,13 @@ impl<const ILML: usize> ColorSpec<ILML, 3> {
 
         let color_matrix = match g {
             g if g >= 1.0 => self.color_matrix[0],
-            g if g <= 0.0 => self.color_matrix[1],
-            g => self.color_matrix[0] * g + self.color_matrix[1] * (1.0 - g),
+            g if g <= 0.0 => self.color_matrix[second],
+            g => self.color_matrix[0] * g + self.color_matrix[second] * (1.0 - g),
         };
 
         // Interpolate the forward_matrix if any
         if let Some(ref mut forward_matrix) = forward_matrix {
-            match (self.forward_matrix[0], self.forward_matrix[1]) {
+            match (self.forward_matrix[0], self.forward_matrix[second]) {
                 (Some(fm1), Some(fm2)) => match g {
                     g if g >= 1.0 => *forward_matrix = fm1,
                     g if g <= 0.0 => *forward_matrix = fm2,
@@ -129,7 +373,7 @@ impl<const ILML: usize> ColorSpec<ILML, 3> {
 
         // Interpolate the reduction_matrix if any
         if let Some(ref mut reduction_matrix) = reduction_matrix {
-            match (self.reduction_matrix[0], self.reduction_matrix[1]) {
+            match (self.reduction_matrix[0], self.reduction_matrix[second]) {
                 (Some(rm1), Some(rm2)) => match g {
                     g if g >= 1.0 => *reduction_matrix = rm1,
                     g if g <= 0.0 => *reduction_matrix = rm2,
@@ -145,10 +389,10 @@ impl<const ILML: usize> ColorSpec<ILML, 3> {
         if let Some(ref mut camera_calibration) = camera_calibration {
             match g {
                 g if g >= 1.0 => *camera_calibration = self.camera_calibration[0],
-                g if g <= 0.0 => *camera_calibration = self.camera_calibration[1],
+                g if g <= 0.0 => *camera_calibration = self.camera_calibration[second],
                 g => {
                     *camera_calibration =
-                        self.camera_calibration[0] * g + self.camera_calibration[1] * (1.0 - g)
+                        self.camera_calibration[0] * g + self.camera_calibration[second] * (1.0 - g)
                 }
             }
         }
@@ -198,3 +442,87 @@ impl<const ILML: usize> ColorSpec<ILML, 3> {
             + self.color_matrix[2] * weights[2])
     }
 }
+
+#[test]
+fn test_new_dual_illuminant() {
+    use crate::tag::values::LightSource;
+
+    let profile = CameraProfileInfo {
+        color_planes: 3,
+        calibration_illuminant1: LightSource::D65 as u32,
+        calibration_illuminant2: LightSource::StandardLightA as u32,
+        color_matrix1: Matrix::identity(),
+        color_matrix2: Matrix::identity() * 2.0,
+        ..Default::default()
+    };
+
+    let mut negative = Negative::new("test");
+    negative.set_camera_white_xy(Some(Coord::D65_XY_COORD));
+    let spec: ColorSpec<2, 3> = negative.make_color_spec(&profile).unwrap();
+
+    // Sorted by temperature, so the D65 matrices come second
+    assert_eq!(spec.temparature1, 2850.0);
+    assert_eq!(spec.temparature2, 6500.0);
+    assert_eq!(spec.color_matrix[1], Matrix::identity());
+    assert_eq!(spec.white_xy, Coord::D65_XY_COORD);
+
+    let white = Coord::xy_to_xyz(Coord::D65_XY_COORD);
+    for i in 0..3 {
+        let expected = white[i] / white.max_entry();
+        assert!((spec.camera_white[i] - expected).abs() < 1e-9);
+    }
+    assert!(((spec.pcs_to_camera * Coord::PCS_TO_XYZ()).max_entry() - 1.0).abs() < 1e-9);
+}
+
+#[test]
+fn test_set_white_xy() {
+    let color_matrix = Matrix::new([[0.9, -0.3, -0.1], [-0.4, 1.2, 0.2], [-0.1, 0.2, 0.6]]);
+    let profile = CameraProfileInfo {
+        color_planes: 3,
+        calibration_illuminant1: crate::tag::values::LightSource::D65 as u32,
+        color_matrix1: color_matrix,
+        ..Default::default()
+    };
+    let negative = Negative::new("test");
+    let mut spec: ColorSpec<2, 3> = negative.make_color_spec(&profile).unwrap();
+
+    // Without forward matrices, the camera white maps to the PCS white
+    // through the Bradford adaptation
+    spec.set_white_xy(Coord::STD_A_XY_COORD).unwrap();
+    let pcs = Coord::xyz_to_xy(spec.camera_to_pcs * spec.camera_white);
+    assert!((pcs - Coord::PCS_TO_XY).x.abs() < 1e-6);
+    assert!((pcs - Coord::PCS_TO_XY).y.abs() < 1e-6);
+
+    // With a forward matrix, it maps exactly to the PCS white
+    let profile = CameraProfileInfo {
+        forward_matrix1: Matrix::new([[0.6, 0.3, 0.1], [0.2, 0.7, 0.1], [0.0, 0.1, 0.7]]),
+        ..profile
+    };
+    let mut spec: ColorSpec<2, 3> = negative.make_color_spec(&profile).unwrap();
+    spec.set_white_xy(Coord::D65_XY_COORD).unwrap();
+    let pcs = spec.camera_to_pcs * spec.camera_white;
+    let expected = Coord::PCS_TO_XYZ();
+    for i in 0..3 {
+        assert!((pcs[i] - expected[i]).abs() < 1e-9);
+    }
+}
+
+#[test]
+fn test_temperature_to_neutral() {
+    let profile = CameraProfileInfo {
+        color_planes: 3,
+        calibration_illuminant1: crate::tag::values::LightSource::D65 as u32,
+        color_matrix1: Matrix::new([[0.9, -0.3, -0.1], [-0.4, 1.2, 0.2], [-0.1, 0.2, 0.6]]),
+        ..Default::default()
+    };
+    let spec: ColorSpec<2, 3> = Negative::new("test").make_color_spec(&profile).unwrap();
+    let temperature = Temperature {
+        temperature: 4300.0,
+        tint: 8.0,
+    };
+    let neutral = spec.temperature_to_neutral(&temperature).unwrap();
+    assert!((neutral.max_entry() - 1.0).abs() < 1e-12);
+    let back = Temperature::from_coord(&spec.neutral_to_xy(neutral).unwrap()).unwrap();
+    assert!((back.temperature - 4300.0).abs() < 5.0);
+    assert!((back.tint - 8.0).abs() < 0.1);
+}
@@ -0,0 +1,637 @@
+//! Camera profiles stored as TIFF directories: standalone DCP files, and the
+//! extra profiles embedded in DNG files. Both start with a TIFF style header
+//! with the `ExtendedProfile` magic, and offsets inside them are relative to
+//! that header.
+
+use crate::errors::{Error, ErrorKind};
+use crate::matrix::Matrix;
+use crate::shared::CameraProfileInfo;
+use crate::stream::Stream;
+use crate::tag::codes::ExifTags;
+use crate::tag::types::{
+    TYPE_ASCII, TYPE_BYTE, TYPE_DOUBLE, TYPE_FLOAT, TYPE_IFD, TYPE_LONG, TYPE_RATIONAL, TYPE_SHORT,
+    TYPE_SLONG, TYPE_SRATIONAL, TYPE_SSHORT, TYPE_UNDEFINED,
+};
+use crate::tag::values::Magic;
+use crate::types::rational::SRational;
+use std::num::NonZeroI32;
+
+/// Matrix entries are written as rationals over this denominator
+const MATRIX_DENOMINATOR: i32 = 10000;
+
+/// Size in bytes of one value of a TIFF type
+fn type_size(tag_type: u16) -> usize {
+    match tag_type {
+        TYPE_SHORT | TYPE_SSHORT => 2,
+        TYPE_LONG | TYPE_SLONG | TYPE_FLOAT | TYPE_IFD => 4,
+        TYPE_RATIONAL | TYPE_SRATIONAL | TYPE_DOUBLE => 8,
+        _ => 1,
+    }
+}
+
+/// An entry of a TIFF directory
+struct Entry {
+    code: u16,
+    tag_type: u16,
+    count: u32,
+    /// Absolute position of the values
+    position: usize,
+}
+
+impl Entry {
+    fn stream<'s>(&self, data: &'s [u8], big_endian: bool) -> crate::Result<Stream<'s>> {
+        let mut stream = Stream::new(data, big_endian);
+        stream.set_position(self.position)?;
+        Ok(stream)
+    }
+
+    fn bytes<'s>(&self, data: &'s [u8]) -> crate::Result<&'s [u8]> {
+        let size = self.count as usize * type_size(self.tag_type);
+        self.position
+            .checked_add(size)
+            .and_then(|end| data.get(self.position..end))
+            .ok_or(Error::new(ErrorKind::EndOfStream))
+    }
+
+    fn string(&self, data: &[u8]) -> crate::Result<String> {
+        let bytes = self.bytes(data)?;
+        let bytes = bytes.split(|byte| *byte == 0).next().unwrap_or_default();
+        Ok(String::from_utf8_lossy(bytes).into_owned())
+    }
+
+    fn integers(&self, data: &[u8], big_endian: bool) -> crate::Result<Vec<u32>> {
+        let mut stream = self.stream(data, big_endian)?;
+        (0..self.count)
+            .map(|_| match self.tag_type {
+                TYPE_BYTE => stream.get_u8().map(u32::from),
+                TYPE_SHORT => stream.get_u16().map(u32::from),
+                TYPE_LONG => stream.get_u32(),
+                _ => Err(Error::new(ErrorKind::InvalidCameraProfile)),
+            })
+            .collect()
+    }
+
+    fn integer(&self, data: &[u8], big_endian: bool) -> crate::Result<u32> {
+        self.integers(data, big_endian)?
+            .first()
+            .copied()
+            .ok_or(Error::new(ErrorKind::InvalidCameraProfile))
+    }
+
+    fn reals(&self, data: &[u8], big_endian: bool) -> crate::Result<Vec<f64>> {
+        let mut stream = self.stream(data, big_endian)?;
+        let ratio = |n: f64, d: f64| if d == 0.0 { 0.0 } else { n / d };
+        (0..self.count)
+            .map(|_| match self.tag_type {
+                TYPE_RATIONAL => Ok(ratio(stream.get_u32()? as f64, stream.get_u32()? as f64)),
+                TYPE_SRATIONAL => Ok(ratio(stream.get_i32()? as f64, stream.get_i32()? as f64)),
+                TYPE_FLOAT => stream.get_f32().map(f64::from),
+                TYPE_DOUBLE => stream.get_f64(),
+                _ => Err(Error::new(ErrorKind::InvalidCameraProfile)),
+            })
+            .collect()
+    }
+
+    fn matrix(&self, data: &[u8], big_endian: bool) -> crate::Result<Matrix<3>> {
+        let values = self.reals(data, big_endian)?;
+        if values.len() != 9 {
+            return Err(Error::new(ErrorKind::InvalidCameraProfile));
+        }
+        Ok(Matrix::new(std::array::from_fn(|row| {
+            std::array::from_fn(|col| values[row * 3 + col])
+        })))
+    }
+}
+
+/// Read the entries of the directory at `position`. Value offsets are
+/// relative to `base`.
+fn read_entries(stream: &mut Stream, position: usize, base: usize) -> crate::Result<Vec<Entry>> {
+    stream.set_position(position)?;
+    let count = stream.get_u16()?;
+    (0..count)
+        .map(|_| {
+            let code = stream.get_u16()?;
+            let tag_type = stream.get_u16()?;
+            let count = stream.get_u32()?;
+            let size = (count as usize).saturating_mul(type_size(tag_type));
+            let position = if size <= 4 {
+                let position = stream.position();
+                stream.skip(4)?;
+                position
+            } else {
+                base.checked_add(stream.get_u32()? as usize)
+                    .ok_or(Error::new(ErrorKind::EndOfStream))?
+            };
+            Ok(Entry {
+                code,
+                tag_type,
+                count,
+                position,
+            })
+        })
+        .collect()
+}
+
+/// Read the contents of an ExtraCameraProfiles tag: the offsets of the extra
+/// profiles in the file, as LONGs
+pub fn extra_camera_profile_offsets(data: &[u8], big_endian: bool) -> crate::Result<Vec<u64>> {
+    let mut stream = Stream::new(data, big_endian);
+    (0..data.len() / 4)
+        .map(|_| stream.get_u32().map(u64::from))
+        .collect()
+}
+
+/// Is this code the one of a tag?
+fn is(code: u16, tag: ExifTags) -> bool {
+    code as u32 == tag as u32
+}
+
+impl CameraProfileInfo {
+    /// Read a profile stored as a TIFF directory with its own header at
+    /// `start` in `file`: a standalone DCP file, or an extra profile of a
+    /// DNG. The offsets of the tables of the profile are recorded relative to
+    /// the start of `file`.
+    pub fn parse_extended(file: &[u8], start: usize) -> crate::Result<Self> {
+        let header = start
+            .checked_add(8)
+            .and_then(|end| file.get(start..end))
+            .ok_or(Error::new(ErrorKind::EndOfStream))?;
+        let big_endian = match &header[..2] {
+            b"MM" => true,
+            b"II" => false,
+            _ => return Err(Error::new(ErrorKind::InvalidCameraProfile)),
+        };
+        let mut stream = Stream::new(file, big_endian);
+        stream.set_position(start + 2)?;
+        if stream.get_u16()? != Magic::ExtendedProfile as u16 {
+            return Err(Error::new(ErrorKind::InvalidCameraProfile));
+        }
+        let ifd = start + stream.get_u32()? as usize;
+        let mut profile = Self {
+            big_endian,
+            ..Default::default()
+        };
+        for entry in read_entries(&mut stream, ifd, start)? {
+            profile.parse_tag(file, &entry)?;
+        }
+        if !profile.color_matrix1.is_empty() {
+            profile.color_planes = 3;
+        }
+        Ok(profile)
+    }
+
+    /// Read a standalone DCP file
+    pub fn read_dcp(data: &[u8]) -> crate::Result<Self> {
+        Self::parse_extended(data, 0)
+    }
+
+    fn parse_tag(&mut self, file: &[u8], entry: &Entry) -> crate::Result<()> {
+        let big_endian = self.big_endian;
+        let code = entry.code;
+        let table = || (entry.position as u64, entry.count);
+        match code {
+            _ if is(code, ExifTags::UniqueCameraModel) => {
+                self.unique_camera_model = entry.string(file)?
+            }
+            _ if is(code, ExifTags::ProfileName) => self.profile_name = entry.string(file)?,
+            _ if is(code, ExifTags::ProfileCopyright) => {
+                self.profile_copyright = entry.string(file)?
+            }
+            _ if is(code, ExifTags::ProfileCalibrationSignature) => {
+                self.profile_calibration_signature = entry.string(file)?
+            }
+            _ if is(code, ExifTags::ProfileEmbedPolicy) => {
+                self.embed_policy = entry.integer(file, big_endian)?
+            }
+            _ if is(code, ExifTags::CalibrationIlluminant1) => {
+                self.calibration_illuminant1 = entry.integer(file, big_endian)?
+            }
+            _ if is(code, ExifTags::CalibrationIlluminant2) => {
+                self.calibration_illuminant2 = entry.integer(file, big_endian)?
+            }
+            _ if is(code, ExifTags::CalibrationIlluminant3) => {
+                self.calibration_illuminant3 = entry.integer(file, big_endian)?
+            }
+            _ if is(code, ExifTags::IlluminantData1)
+                || is(code, ExifTags::IlluminantData2)
+                || is(code, ExifTags::IlluminantData3) =>
+            {
+                self.parse_illuminant_data(code as u32, entry.bytes(file)?)?;
+            }
+            _ if is(code, ExifTags::ColorMatrix1) => {
+                self.color_matrix1 = entry.matrix(file, big_endian)?
+            }
+            _ if is(code, ExifTags::ColorMatrix2) => {
+                self.color_matrix2 = entry.matrix(file, big_endian)?
+            }
+            _ if is(code, ExifTags::ColorMatrix3) => {
+                self.color_matrix3 = entry.matrix(file, big_endian)?
+            }
+            _ if is(code, ExifTags::ForwardMatrix1) => {
+                self.forward_matrix1 = entry.matrix(file, big_endian)?
+            }
+            _ if is(code, ExifTags::ForwardMatrix2) => {
+                self.forward_matrix2 = entry.matrix(file, big_endian)?
+            }
+            _ if is(code, ExifTags::ForwardMatrix3) => {
+                self.forward_matrix3 = entry.matrix(file, big_endian)?
+            }
+            _ if is(code, ExifTags::ReductionMatrix1) => {
+                self.reduction_matrix1 = entry.matrix(file, big_endian)?
+            }
+            _ if is(code, ExifTags::ReductionMatrix2) => {
+                self.reduction_matrix2 = entry.matrix(file, big_endian)?
+            }
+            _ if is(code, ExifTags::ReductionMatrix3) => {
+                self.reduction_matrix3 = entry.matrix(file, big_endian)?
+            }
+            _ if is(code, ExifTags::ProfileHueSatMapDims) => {
+                let dims = entry.integers(file, big_endian)?;
+                if dims.len() < 2 {
+                    return Err(Error::new(ErrorKind::InvalidCameraProfile));
+                }
+                self.profile_hues = dims[0];
+                self.profile_sats = dims[1];
+                self.profile_vals = dims.get(2).copied().unwrap_or(1);
+            }
+            _ if is(code, ExifTags::ProfileHueSatMapData1) => {
+                (self.hue_sat_deltas1_offset, self.hue_sat_deltas1_count) = table()
+            }
+            _ if is(code, ExifTags::ProfileHueSatMapData2) => {
+                (self.hue_sat_deltas2_offset, self.hue_sat_deltas2_count) = table()
+            }
+            _ if is(code, ExifTags::ProfileHueSatMapData3) => {
+                (self.hue_sat_deltas3_offset, self.hue_sat_deltas3_count) = table()
+            }
+            _ if is(code, ExifTags::ProfileHueSatMapEncoding) => {
+                self.hue_sat_map_encoding = entry.integer(file, big_endian)?
+            }
+            _ if is(code, ExifTags::ProfileLookTableDims) => {
+                let dims = entry.integers(file, big_endian)?;
+                if dims.len() < 2 {
+                    return Err(Error::new(ErrorKind::InvalidCameraProfile));
+                }
+                self.look_table_hues = dims[0];
+                self.look_table_sats = dims[1];
+                self.look_table_vals = dims.get(2).copied().unwrap_or(1);
+            }
+            _ if is(code, ExifTags::ProfileLookTableData) => {
+                (self.look_table_offset, self.look_table_count) = table()
+            }
+            _ if is(code, ExifTags::ProfileLookTableEncoding) => {
+                self.look_table_encoding = entry.integer(file, big_endian)?
+            }
+            _ if is(code, ExifTags::ProfileToneCurve) => {
+                (self.tone_curve_offset, self.tone_curve_count) = table()
+            }
+            _ if is(code, ExifTags::RGBTables) => {
+                (self.rgb_tables_offset, self.rgb_tables_count) = table()
+            }
+            _ if is(code, ExifTags::BaselineExposureOffset) => {
+                let mut stream = entry.stream(file, big_endian)?;
+                let numerator = stream.get_i32()?;
+                let denominator = NonZeroI32::new(stream.get_i32()?)
+                    .ok_or(Error::new(ErrorKind::InvalidCameraProfile))?;
+                self.baseline_exposure_offset = SRational::new(numerator, denominator);
+            }
+            _ if is(code, ExifTags::DefaultBlackRender) => {
+                self.default_black_render = entry.integer(file, big_endian)?
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Write the profile as a standalone DCP file, in the byte order of the
+    /// profile. `file` holds the data the table offsets of the profile refer
+    /// to.
+    pub fn write_dcp(&self, file: &[u8]) -> crate::Result<Vec<u8>> {
+        let mut writer = DirectoryWriter::new(self.big_endian);
+        let ascii = |value: &str| {
+            let mut bytes = value.as_bytes().to_vec();
+            bytes.push(0);
+            bytes
+        };
+        let table = |offset: u64, count: u32, size: usize| -> crate::Result<Vec<u8>> {
+            let start = usize::try_from(offset).map_err(|_| Error::new(ErrorKind::EndOfStream))?;
+            start
+                .checked_add(count as usize * size)
+                .and_then(|end| file.get(start..end))
+                .map(<[u8]>::to_vec)
+                .ok_or(Error::new(ErrorKind::EndOfStream))
+        };
+
+        if !self.unique_camera_model.is_empty() {
+            writer.add_ascii(
+                ExifTags::UniqueCameraModel,
+                &ascii(&self.unique_camera_model),
+            );
+        }
+        let illuminants = [
+            (
+                ExifTags::CalibrationIlluminant1,
+                self.calibration_illuminant1,
+            ),
+            (
+                ExifTags::CalibrationIlluminant2,
+                self.calibration_illuminant2,
+            ),
+            (
+                ExifTags::CalibrationIlluminant3,
+                self.calibration_illuminant3,
+            ),
+        ];
+        for (tag, illuminant) in illuminants {
+            if illuminant != 0 {
+                writer.add_shorts(tag, &[illuminant as u16]);
+            }
+        }
+        let matrices = [
+            (ExifTags::ColorMatrix1, &self.color_matrix1),
+            (ExifTags::ColorMatrix2, &self.color_matrix2),
+            (ExifTags::ColorMatrix3, &self.color_matrix3),
+            (ExifTags::ForwardMatrix1, &self.forward_matrix1),
+            (ExifTags::ForwardMatrix2, &self.forward_matrix2),
+            (ExifTags::ForwardMatrix3, &self.forward_matrix3),
+            (ExifTags::ReductionMatrix1, &self.reduction_matrix1),
+            (ExifTags::ReductionMatrix2, &self.reduction_matrix2),
+            (ExifTags::ReductionMatrix3, &self.reduction_matrix3),
+        ];
+        for (tag, matrix) in matrices {
+            if !matrix.is_empty() {
+                writer.add_matrix(tag, matrix);
+            }
+        }
+        let data = [
+            (ExifTags::IlluminantData1, &self.illuminant_data1),
+            (ExifTags::IlluminantData2, &self.illuminant_data2),
+            (ExifTags::IlluminantData3, &self.illuminant_data3),
+        ];
+        for (tag, illuminant) in data {
+            if illuminant.derived_white.is_valid() {
+                writer.add_undefined(tag, illuminant.write(self.big_endian));
+            }
+        }
+        if !self.profile_calibration_signature.is_empty() {
+            writer.add_ascii(
+                ExifTags::ProfileCalibrationSignature,
+                &ascii(&self.profile_calibration_signature),
+            );
+        }
+        if !self.profile_name.is_empty() {
+            writer.add_ascii(ExifTags::ProfileName, &ascii(&self.profile_name));
+        }
+        if self.hue_sat_deltas1_count > 0 {
+            writer.add_longs(
+                ExifTags::ProfileHueSatMapDims,
+                &[self.profile_hues, self.profile_sats, self.profile_vals],
+            );
+            let maps = [
+                (
+                    ExifTags::ProfileHueSatMapData1,
+                    self.hue_sat_deltas1_offset,
+                    self.hue_sat_deltas1_count,
+                ),
+                (
+                    ExifTags::ProfileHueSatMapData2,
+                    self.hue_sat_deltas2_offset,
+                    self.hue_sat_deltas2_count,
+                ),
+                (
+                    ExifTags::ProfileHueSatMapData3,
+                    self.hue_sat_deltas3_offset,
+                    self.hue_sat_deltas3_count,
+                ),
+            ];
+            for (tag, offset, count) in maps {
+                if count > 0 {
+                    writer.add(tag, TYPE_FLOAT, count, table(offset, count, 4)?);
+                }
+            }
+            writer.add_longs(
+                ExifTags::ProfileHueSatMapEncoding,
+                &[self.hue_sat_map_encoding],
+            );
+        }
+        if self.tone_curve_count > 0 {
+            let curve = table(self.tone_curve_offset, self.tone_curve_count, 4)?;
+            writer.add(
+                ExifTags::ProfileToneCurve,
+                TYPE_FLOAT,
+                self.tone_curve_count,
+                curve,
+            );
+        }
+        writer.add_longs(ExifTags::ProfileEmbedPolicy, &[self.embed_policy]);
+        if !self.profile_copyright.is_empty() {
+            writer.add_ascii(ExifTags::ProfileCopyright, &ascii(&self.profile_copyright));
+        }
+        if self.look_table_count > 0 {
+            writer.add_longs(
+                ExifTags::ProfileLookTableDims,
+                &[
+                    self.look_table_hues,
+                    self.look_table_sats,
+                    self.look_table_vals,
+                ],
+            );
+            let look_table = table(self.look_table_offset, self.look_table_count, 4)?;
+            writer.add(
+                ExifTags::ProfileLookTableData,
+                TYPE_FLOAT,
+                self.look_table_count,
+                look_table,
+            );
+            writer.add_longs(
+                ExifTags::ProfileLookTableEncoding,
+                &[self.look_table_encoding],
+            );
+        }
+        if self.baseline_exposure_offset.numerator != 0 {
+            let offset = &self.baseline_exposure_offset;
+            writer.add_srationals(
+                ExifTags::BaselineExposureOffset,
+                &[(offset.numerator, offset.denominator.get())],
+            );
+        }
+        if self.default_black_render != 0 {
+            writer.add_longs(ExifTags::DefaultBlackRender, &[self.default_black_render]);
+        }
+        if self.rgb_tables_count > 0 {
+            let tables = table(self.rgb_tables_offset, self.rgb_tables_count, 1)?;
+            writer.add_undefined(ExifTags::RGBTables, tables);
+        }
+        Ok(writer.finish())
+    }
+}
+
+/// Builds a TIFF file with a single directory and an `ExtendedProfile` header
+struct DirectoryWriter {
+    big_endian: bool,
+    /// Code, type, count and values of every entry
+    entries: Vec<(u16, u16, u32, Vec<u8>)>,
+}
+
+impl DirectoryWriter {
+    fn new(big_endian: bool) -> Self {
+        Self {
+            big_endian,
+            entries: Vec::new(),
+        }
+    }
+
+    fn u16_bytes(&self, value: u16) -> [u8; 2] {
+        if self.big_endian {
+            value.to_be_bytes()
+        } else {
+            value.to_le_bytes()
+        }
+    }
+
+    fn u32_bytes(&self, value: u32) -> [u8; 4] {
+        if self.big_endian {
+            value.to_be_bytes()
+        } else {
+            value.to_le_bytes()
+        }
+    }
+
+    fn add(&mut self, tag: ExifTags, tag_type: u16, count: u32, values: Vec<u8>) {
+        self.entries
+            .push((tag as u32 as u16, tag_type, count, values));
+    }
+
+    fn add_ascii(&mut self, tag: ExifTags, bytes: &[u8]) {
+        self.add(tag, TYPE_ASCII, bytes.len() as u32, bytes.to_vec());
+    }
+
+    fn add_undefined(&mut self, tag: ExifTags, bytes: Vec<u8>) {
+        self.add(tag, TYPE_UNDEFINED, bytes.len() as u32, bytes);
+    }
+
+    fn add_shorts(&mut self, tag: ExifTags, values: &[u16]) {
+        let bytes = values
+            .iter()
+            .flat_map(|value| self.u16_bytes(*value))
+            .collect();
+        self.add(tag, TYPE_SHORT, values.len() as u32, bytes);
+    }
+
+    fn add_longs(&mut self, tag: ExifTags, values: &[u32]) {
+        let bytes = values
+            .iter()
+            .flat_map(|value| self.u32_bytes(*value))
+            .collect();
+        self.add(tag, TYPE_LONG, values.len() as u32, bytes);
+    }
+
+    fn add_srationals(&mut self, tag: ExifTags, values: &[(i32, i32)]) {
+        let bytes = values
+            .iter()
+            .flat_map(|(n, d)| [self.u32_bytes(*n as u32), self.u32_bytes(*d as u32)])
+            .flatten()
+            .collect();
+        self.add(tag, TYPE_SRATIONAL, values.len() as u32, bytes);
+    }
+
+    fn add_matrix(&mut self, tag: ExifTags, matrix: &Matrix<3>) {
+        let values = matrix
+            .data
+            .iter()
+            .flatten()
+            .map(|value| {
+                (
+                    (value * MATRIX_DENOMINATOR as f64).round() as i32,
+                    MATRIX_DENOMINATOR,
+                )
+            })
+            .collect::<Vec<_>>();
+        self.add_srationals(tag, &values);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        self.entries.sort_by_key(|(code, ..)| *code);
+        let mut data = Vec::new();
+        data.extend_from_slice(if self.big_endian { b"MM" } else { b"II" });
+        data.extend_from_slice(&self.u16_bytes(Magic::ExtendedProfile as u16));
+        data.extend_from_slice(&self.u32_bytes(8));
+
+        // Values that do not fit in an entry follow the directory, on word
+        // boundaries
+        let directory_size = 2 + self.entries.len() * 12 + 4;
+        let mut values = Vec::new();
+        let mut directory = self.u16_bytes(self.entries.len() as u16).to_vec();
+        for (code, tag_type, count, bytes) in &self.entries {
+            directory.extend_from_slice(&self.u16_bytes(*code));
+            directory.extend_from_slice(&self.u16_bytes(*tag_type));
+            directory.extend_from_slice(&self.u32_bytes(*count));
+            if bytes.len() <= 4 {
+                let mut inline = [0; 4];
+                inline[..bytes.len()].copy_from_slice(bytes);
+                directory.extend_from_slice(&inline);
+            } else {
+                let offset = 8 + directory_size + values.len();
+                directory.extend_from_slice(&self.u32_bytes(offset as u32));
+                values.extend_from_slice(bytes);
+                if values.len() % 2 == 1 {
+                    values.push(0);
+                }
+            }
+        }
+        directory.extend_from_slice(&self.u32_bytes(0));
+        data.extend_from_slice(&directory);
+        data.extend_from_slice(&values);
+        data
+    }
+}
+
+#[test]
+fn test_dcp_round_trip() {
+    use crate::coord::Coord;
+    use crate::illuminant::Illuminant;
+    use crate::tag::values::LightSource;
+
+    let mut illuminant_data2 = Illuminant::default();
+    illuminant_data2
+        .set_white_xy(Coord::new(0.4, 0.39))
+        .unwrap();
+    // A tone curve stored in the data the profile was read from
+    let mut file = vec![0_u8; 4];
+    for value in [0.0_f32, 0.0, 0.5, 0.6, 1.0, 1.0] {
+        file.extend_from_slice(&value.to_le_bytes());
+    }
+    let profile = CameraProfileInfo {
+        color_planes: 3,
+        calibration_illuminant1: LightSource::D65 as u32,
+        calibration_illuminant2: LightSource::Other as u32,
+        illuminant_data2,
+        color_matrix1: Matrix::new([[0.9, -0.3, -0.1], [-0.4, 1.2, 0.2], [-0.1, 0.2, 0.6]]),
+        color_matrix2: Matrix::identity(),
+        profile_name: "Custom Standard".into(),
+        profile_copyright: "Copyright (c) 2026".into(),
+        unique_camera_model: "Maker Model".into(),
+        embed_policy: 1,
+        tone_curve_offset: 4,
+        tone_curve_count: 6,
+        ..Default::default()
+    };
+    let dcp = profile.write_dcp(&file).unwrap();
+    assert_eq!(&dcp[..4], b"II\x52\x43");
+    let read = CameraProfileInfo::read_dcp(&dcp).unwrap();
+    assert_eq!(read.profile_name, profile.profile_name);
+    assert_eq!(read.profile_copyright, profile.profile_copyright);
+    assert_eq!(read.unique_camera_model, profile.unique_camera_model);
+    assert_eq!(read.embed_policy, 1);
+    assert_eq!(read.calibration_illuminant2, LightSource::Other as u32);
+    assert_eq!(read.illuminant_data2, profile.illuminant_data2);
+    assert_eq!(read.color_matrix1, profile.color_matrix1);
+    assert_eq!(read.color_matrix2, Matrix::identity());
+    assert_eq!(read.color_planes, 3);
+    let curve = read.tone_curve(&dcp).unwrap();
+    assert_eq!(
+        curve.points(),
+        &[(0.0, 0.0), (0.5, 0.6000000238418579), (1.0, 1.0)]
+    );
+    // Writing what was read gives the same file
+    assert_eq!(read.write_dcp(&dcp).unwrap(), dcp);
+}
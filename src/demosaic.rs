@@ -0,0 +1,210 @@
+pub mod bayer;
+pub mod directional;
+pub mod layout;
+
+use crate::errors::{Error, ErrorKind};
+use crate::ifd::Ifd;
+use crate::image::{Image, PixelType};
+use crate::limits::{MAX_CFA_PATTERN, MAX_COLOR_PLANES};
+use crate::tag::values::PhotometricInterpretation;
+
+/// How much effort to put in the demosaic
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DemosaicMode {
+    /// Average the neighbours of each color, fast but soft. Good for previews.
+    Bilinear,
+    /// Edge aware interpolation for final renders
+    #[default]
+    HighQuality,
+}
+
+/// Description of the color filter array of a mosaic (stage 2) image
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MosaicInfo {
+    /// Size of the repeating pattern
+    pub rows: usize,
+    pub cols: usize,
+    /// CFA color code of every site in the repeating pattern
+    pub pattern: [[u8; MAX_CFA_PATTERN]; MAX_CFA_PATTERN],
+    /// CFA color code of every plane of the demosaiced image
+    pub plane_color: [u8; MAX_COLOR_PLANES],
+    /// Number of planes in the demosaiced image
+    pub color_planes: usize,
+    /// Layout of the CFA sites, 1 for a rectangular grid and 2 to 9 for the
+    /// staggered layouts of the DNG specification
+    pub layout: u32,
+    /// How much the two greens of a Bayer pattern differ, from 0 (matched)
+    /// to 5000
+    pub bayer_green_split: u32,
+}
+
+impl MosaicInfo {
+    pub fn new(
+        rows: usize,
+        cols: usize,
+        pattern: [[u8; MAX_CFA_PATTERN]; MAX_CFA_PATTERN],
+        plane_color: [u8; MAX_COLOR_PLANES],
+    ) -> crate::Result<Self> {
+        if rows == 0 || cols == 0 || rows > MAX_CFA_PATTERN || cols > MAX_CFA_PATTERN {
+            return Err(Error::new(ErrorKind::InvalidCfaPattern));
+        }
+        let mut info = Self {
+            rows,
+            cols,
+            pattern,
+            plane_color,
+            color_planes: 0,
+            layout: layout::CFA_LAYOUT_RECTANGULAR,
+            bayer_green_split: 0,
+        };
+        // Every color used by the pattern must have a plane, and planes are
+        // counted up to the last one the pattern uses
+        for color in pattern
+            .iter()
+            .take(rows)
+            .flat_map(|row| row.iter().take(cols))
+        {
+            let plane = info
+                .plane_of_color(*color)
+                .ok_or(Error::new(ErrorKind::InvalidCfaPattern))?;
+            info.color_planes = info.color_planes.max(plane + 1);
+        }
+        Ok(info)
+    }
+
+    /// The mosaic of a CFA IFD, or None for any other kind of image
+    pub fn from_ifd(ifd: &Ifd) -> crate::Result<Option<Self>> {
+        if PhotometricInterpretation::from_tiff(ifd.photometric_interpretation())
+            != Some(PhotometricInterpretation::Cfa)
+        {
+            return Ok(None);
+        }
+        let (rows, cols) = ifd.cfa_repeat_pattern();
+        let mut info = Self::new(
+            rows as usize,
+            cols as usize,
+            *ifd.cfa_pattern(),
+            *ifd.cfa_plane_color(),
+        )?;
+        info.layout = ifd.cfa_layout();
+        if !layout::is_supported(info.layout) {
+            return Err(Error::new(ErrorKind::InvalidCfaPattern));
+        }
+        info.bayer_green_split = ifd.bayer_green_split();
+        Ok(Some(info))
+    }
+
+    fn plane_of_color(&self, color: u8) -> Option<usize> {
+        self.plane_color.iter().position(|c| *c == color)
+    }
+
+    /// The plane sampled at a position relative to the pattern origin
+    #[inline]
+    pub fn plane_at(&self, row: i32, col: i32) -> usize {
+        let color = self.pattern[row.rem_euclid(self.rows as i32) as usize]
+            [col.rem_euclid(self.cols as i32) as usize];
+        self.plane_of_color(color).unwrap_or(0)
+    }
+
+    /// Is this a 2x2 pattern of three colors with the repeated color on
+    /// either diagonal?
+    pub fn is_bayer(&self) -> bool {
+        if self.rows != 2 || self.cols != 2 || self.color_planes != 3 {
+            return false;
+        }
+        let p = &self.pattern;
+        let distinct = |a: u8, b: u8, c: u8| a != b && a != c && b != c;
+        (p[0][0] == p[1][1] && distinct(p[0][0], p[0][1], p[1][0]))
+            || (p[0][1] == p[1][0] && distinct(p[0][1], p[0][0], p[1][1]))
+    }
+
+    /// The plane of the repeated (green) color of a Bayer pattern
+    pub fn bayer_green(&self) -> usize {
+        if self.pattern[0][0] == self.pattern[1][1] {
+            self.plane_at(0, 0)
+        } else {
+            self.plane_at(0, 1)
+        }
+    }
+
+    /// Interpolate the single plane stage 2 image into a stage 3 image with
+    /// one plane per color. The output is floating point. Staggered layouts
+    /// are resampled onto a rectangular grid first.
+    pub fn demosaic(&self, image: &Image, mode: DemosaicMode) -> crate::Result<Image> {
+        if image.planes() != 1 {
+            return Err(Error::new(ErrorKind::InvalidCfaPattern));
+        }
+        let rectified;
+        let image = if self.layout == layout::CFA_LAYOUT_RECTANGULAR {
+            image
+        } else {
+            rectified = layout::rectify(self, image)?;
+            &rectified
+        };
+        match mode {
+            DemosaicMode::HighQuality if self.is_bayer() => bayer::rcd(self, image),
+            DemosaicMode::HighQuality => Ok(directional::demosaic(self, image)),
+            DemosaicMode::Bilinear => Ok(self.bilinear(image)),
+        }
+    }
+
+    /// Average the samples of each color over the 3x3 neighbourhood of every
+    /// pixel, widening the neighbourhood for colors missing from it until it
+    /// covers a whole 8x8 pattern. The greens of a Bayer pattern are
+    /// equilibrated first when they are known to differ.
+    pub fn bilinear(&self, image: &Image) -> Image {
+        let equilibrated;
+        let image = if self.is_bayer() && self.bayer_green_split > 0 {
+            equilibrated = bayer::equilibrated(self, image);
+            &equilibrated
+        } else {
+            image
+        };
+        let bounds = image.bounds();
+        let scale = 1.0 / image.pixel_type().max_value();
+        let planes = self.color_planes;
+        let mut output = Image::new(bounds, planes as u32, PixelType::Float);
+        for row in bounds.top..bounds.bottom {
+            for col in bounds.left..bounds.right {
+                let mut sum = [0.0_f32; MAX_COLOR_PLANES];
+                let mut count = [0_u32; MAX_COLOR_PLANES];
+                for radius in 1..=MAX_CFA_PATTERN as i32 / 2 {
+                    let missing = count.map(|count| count == 0);
+                    for dr in -radius..=radius {
+                        for dc in -radius..=radius {
+                            let (r, c) = (row + dr, col + dc);
+                            if (dr.abs() < radius && dc.abs() < radius)
+                                || r < bounds.top
+                                || r >= bounds.bottom
+                                || c < bounds.left
+                                || c >= bounds.right
+                            {
+                                continue;
+                            }
+                            let plane = self.plane_at(r - bounds.top, c - bounds.left);
+                            if missing[plane] {
+                                sum[plane] += image.get(r, c, 0);
+                                count[plane] += 1;
+                            }
+                        }
+                    }
+                    if count[..planes].iter().all(|count| *count > 0) {
+                        break;
+                    }
+                }
+                let own = self.plane_at(row - bounds.top, col - bounds.left);
+                for plane in 0..planes {
+                    let value = if plane == own {
+                        image.get(row, col, 0)
+                    } else if count[plane] > 0 {
+                        sum[plane] / count[plane] as f32
+                    } else {
+                        0.0
+                    };
+                    output.set(row, col, plane as u32, value * scale);
+                }
+            }
+        }
+        output
+    }
+}
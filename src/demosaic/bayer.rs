@@ -0,0 +1,416 @@
+//! Ratio Corrected Demosaicing (RCD) for Bayer patterns, after Luis Sanz
+//! Rodríguez's v2.3 algorithm. Any of the four 2x2 phases is supported as the
+//! pattern is looked up through the mosaic info.
+
+use crate::demosaic::MosaicInfo;
+use crate::image::{Image, PixelType};
+
+/// Padding added around the image so the filters never leave the buffer
+const PAD: usize = 8;
+
+const EPS: f32 = 1e-5;
+const EPS_SQ: f32 = 1e-10;
+
+#[inline]
+fn sqr(x: f32) -> f32 {
+    x * x
+}
+
+/// Reflect a position into 0..len without repeating the edge, which keeps
+/// the parity and so the CFA color of the mirrored site
+#[inline]
+fn mirror(position: isize, len: usize) -> usize {
+    let last = len as isize - 1;
+    if position < 0 {
+        (-position) as usize
+    } else if position > last {
+        (2 * last - position) as usize
+    } else {
+        position as usize
+    }
+}
+
+/// Bring the two greens of a Bayer pattern closer when the camera says they
+/// do not match. Every green that is within the expected mismatch of its four
+/// diagonal neighbours (the greens of the other kind) is replaced by the
+/// average of itself and them.
+fn equilibrate_greens(cfa: &mut [f32], color: &[usize], green: usize, width: usize, split: u32) {
+    let threshold = split as f32 / 100000.0;
+    let height = cfa.len() / width;
+    let source = cfa.to_vec();
+    for row in 1..height - 1 {
+        for col in 1..width - 1 {
+            let i = row * width + col;
+            if color[i] != green {
+                continue;
+            }
+            let diagonal = 0.25
+                * (source[i - width - 1]
+                    + source[i - width + 1]
+                    + source[i + width - 1]
+                    + source[i + width + 1]);
+            if (diagonal - source[i]).abs() <= 2.0 * threshold * diagonal.max(source[i]) {
+                cfa[i] = 0.5 * (source[i] + diagonal);
+            }
+        }
+    }
+}
+
+/// A copy of a Bayer image with its two greens equilibrated, for the
+/// bilinear demosaic which has no padded mosaic of its own
+pub(crate) fn equilibrated(info: &MosaicInfo, image: &Image) -> Image {
+    let mut image = image.clone();
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    if width < 3 || height < 3 {
+        return image;
+    }
+    let color = (0..height)
+        .flat_map(|row| (0..width).map(move |col| info.plane_at(row as i32, col as i32)))
+        .collect::<Vec<_>>();
+    let (green, split) = (info.bayer_green(), info.bayer_green_split);
+    equilibrate_greens(image.data_mut(), &color, green, width, split);
+    image
+}
+
+/// Demosaic a Bayer image. Images too small to be worth padding fall back to
+/// bilinear interpolation.
+pub fn rcd(info: &MosaicInfo, image: &Image) -> crate::Result<Image> {
+    let bounds = image.bounds();
+    let (image_width, image_height) = (image.width() as usize, image.height() as usize);
+    if image_width <= PAD || image_height <= PAD {
+        return Ok(info.bilinear(image));
+    }
+
+    let w1 = image_width + 2 * PAD;
+    let height = image_height + 2 * PAD;
+    let (w2, w3, w4) = (2 * w1, 3 * w1, 4 * w1);
+    let len = w1 * height;
+    let scale = 1.0 / image.pixel_type().max_value();
+    let green = info.bayer_green();
+
+    // The normalized, mirror padded mosaic and its color at every site
+    let mut cfa = vec![0.0_f32; len];
+    let mut color = vec![0_usize; len];
+    for row in 0..height {
+        let source_row = mirror(row as isize - PAD as isize, image_height);
+        for col in 0..w1 {
+            let source_col = mirror(col as isize - PAD as isize, image_width);
+            let i = row * w1 + col;
+            cfa[i] = image.get(
+                bounds.top + source_row as i32,
+                bounds.left + source_col as i32,
+                0,
+            ) * scale;
+            color[i] = info.plane_at(source_row as i32, source_col as i32);
+        }
+    }
+    if info.bayer_green_split > 0 {
+        equilibrate_greens(&mut cfa, &color, green, w1, info.bayer_green_split);
+    }
+
+    let mut rgb = [vec![0.0_f32; len], vec![0.0_f32; len], vec![0.0_f32; len]];
+    for i in 0..len {
+        rgb[color[i]][i] = cfa[i];
+    }
+
+    // Step 1: vertical and horizontal directional discrimination
+    let mut v_hpf = vec![0.0_f32; len];
+    let mut h_hpf = vec![0.0_f32; len];
+    for row in 3..height - 3 {
+        for col in 3..w1 - 3 {
+            let i = row * w1 + col;
+            v_hpf[i] = sqr((cfa[i - w3] - cfa[i - w1] - cfa[i + w1] + cfa[i + w3])
+                - 3.0 * (cfa[i - w2] + cfa[i + w2])
+                + 6.0 * cfa[i]);
+            h_hpf[i] = sqr((cfa[i - 3] - cfa[i - 1] - cfa[i + 1] + cfa[i + 3])
+                - 3.0 * (cfa[i - 2] + cfa[i + 2])
+                + 6.0 * cfa[i]);
+        }
+    }
+    let mut vh_dir = vec![0.0_f32; len];
+    for row in 4..height - 4 {
+        for col in 4..w1 - 4 {
+            let i = row * w1 + col;
+            let v_stat = (v_hpf[i - w1] + v_hpf[i] + v_hpf[i + w1]).max(EPS_SQ);
+            let h_stat = (h_hpf[i - 1] + h_hpf[i] + h_hpf[i + 1]).max(EPS_SQ);
+            vh_dir[i] = v_stat / (v_stat + h_stat);
+        }
+    }
+    drop(v_hpf);
+    drop(h_hpf);
+
+    // Step 2: low pass filter at the red and blue sites
+    let mut lpf = vec![0.0_f32; len];
+    for row in 2..height - 2 {
+        for col in 2..w1 - 2 {
+            let i = row * w1 + col;
+            if color[i] == green {
+                continue;
+            }
+            lpf[i] = cfa[i]
+                + 0.5 * (cfa[i - w1] + cfa[i + w1] + cfa[i - 1] + cfa[i + 1])
+                + 0.25 * (cfa[i - w1 - 1] + cfa[i - w1 + 1] + cfa[i + w1 - 1] + cfa[i + w1 + 1]);
+        }
+    }
+
+    // Step 3: green at the red and blue sites
+    let discriminate = |dir: &[f32], i: usize| {
+        let central = dir[i];
+        let neighbours =
+            0.25 * (dir[i - w1 - 1] + dir[i - w1 + 1] + dir[i + w1 - 1] + dir[i + w1 + 1]);
+        if (0.5 - central).abs() < (0.5 - neighbours).abs() {
+            neighbours
+        } else {
+            central
+        }
+    };
+    for row in 4..height - 4 {
+        for col in 4..w1 - 4 {
+            let i = row * w1 + col;
+            if color[i] == green {
+                continue;
+            }
+            let vh_disc = discriminate(&vh_dir, i);
+
+            let n_grad = EPS
+                + (cfa[i - w1] - cfa[i + w1]).abs()
+                + (cfa[i] - cfa[i - w2]).abs()
+                + (cfa[i - w1] - cfa[i - w3]).abs()
+                + (cfa[i - w2] - cfa[i - w4]).abs();
+            let s_grad = EPS
+                + (cfa[i + w1] - cfa[i - w1]).abs()
+                + (cfa[i] - cfa[i + w2]).abs()
+                + (cfa[i + w1] - cfa[i + w3]).abs()
+                + (cfa[i + w2] - cfa[i + w4]).abs();
+            let w_grad = EPS
+                + (cfa[i - 1] - cfa[i + 1]).abs()
+                + (cfa[i] - cfa[i - 2]).abs()
+                + (cfa[i - 1] - cfa[i - 3]).abs()
+                + (cfa[i - 2] - cfa[i - 4]).abs();
+            let e_grad = EPS
+                + (cfa[i + 1] - cfa[i - 1]).abs()
+                + (cfa[i] - cfa[i + 2]).abs()
+                + (cfa[i + 1] - cfa[i + 3]).abs()
+                + (cfa[i + 2] - cfa[i + 4]).abs();
+
+            let n_est = cfa[i - w1] * 2.0 * lpf[i] / (EPS + lpf[i] + lpf[i - w2]);
+            let s_est = cfa[i + w1] * 2.0 * lpf[i] / (EPS + lpf[i] + lpf[i + w2]);
+            let w_est = cfa[i - 1] * 2.0 * lpf[i] / (EPS + lpf[i] + lpf[i - 2]);
+            let e_est = cfa[i + 1] * 2.0 * lpf[i] / (EPS + lpf[i] + lpf[i + 2]);
+
+            let v_est = (s_grad * n_est + n_grad * s_est) / (n_grad + s_grad);
+            let h_est = (w_grad * e_est + e_grad * w_est) / (e_grad + w_grad);
+
+            rgb[green][i] = (vh_disc * h_est + (1.0 - vh_disc) * v_est).clamp(0.0, 1.0);
+        }
+    }
+    drop(lpf);
+
+    // Step 4.1: diagonal discrimination at the red and blue sites
+    let mut p_hpf = vec![0.0_f32; len];
+    let mut q_hpf = vec![0.0_f32; len];
+    for row in 3..height - 3 {
+        for col in 3..w1 - 3 {
+            let i = row * w1 + col;
+            p_hpf[i] = sqr(
+                (cfa[i - w3 - 3] - cfa[i - w1 - 1] - cfa[i + w1 + 1] + cfa[i + w3 + 3])
+                    - 3.0 * (cfa[i - w2 - 2] + cfa[i + w2 + 2])
+                    + 6.0 * cfa[i],
+            );
+            q_hpf[i] = sqr(
+                (cfa[i - w3 + 3] - cfa[i - w1 + 1] - cfa[i + w1 - 1] + cfa[i + w3 - 3])
+                    - 3.0 * (cfa[i - w2 + 2] + cfa[i + w2 - 2])
+                    + 6.0 * cfa[i],
+            );
+        }
+    }
+    let mut pq_dir = vec![0.0_f32; len];
+    for row in 4..height - 4 {
+        for col in 4..w1 - 4 {
+            let i = row * w1 + col;
+            if color[i] == green {
+                continue;
+            }
+            let p_stat = (p_hpf[i - w1 - 1] + p_hpf[i] + p_hpf[i + w1 + 1]).max(EPS_SQ);
+            let q_stat = (q_hpf[i - w1 + 1] + q_hpf[i] + q_hpf[i + w1 - 1]).max(EPS_SQ);
+            pq_dir[i] = p_stat / (p_stat + q_stat);
+        }
+    }
+    drop(p_hpf);
+    drop(q_hpf);
+
+    // Step 4.2: red at the blue sites and blue at the red sites
+    for row in 4..height - 4 {
+        for col in 4..w1 - 4 {
+            let i = row * w1 + col;
+            if color[i] == green {
+                continue;
+            }
+            let pq_disc = discriminate(&pq_dir, i);
+            let c = 3 - green - color[i];
+            let (g, other) = (&rgb[green], &rgb[c]);
+
+            let nw_grad = EPS
+                + (other[i - w1 - 1] - other[i + w1 + 1]).abs()
+                + (other[i - w1 - 1] - other[i - w3 - 3]).abs()
+                + (g[i] - g[i - w2 - 2]).abs();
+            let ne_grad = EPS
+                + (other[i - w1 + 1] - other[i + w1 - 1]).abs()
+                + (other[i - w1 + 1] - other[i - w3 + 3]).abs()
+                + (g[i] - g[i - w2 + 2]).abs();
+            let sw_grad = EPS
+                + (other[i + w1 - 1] - other[i - w1 + 1]).abs()
+                + (other[i + w1 - 1] - other[i + w3 - 3]).abs()
+                + (g[i] - g[i + w2 - 2]).abs();
+            let se_grad = EPS
+                + (other[i + w1 + 1] - other[i - w1 - 1]).abs()
+                + (other[i + w1 + 1] - other[i + w3 + 3]).abs()
+                + (g[i] - g[i + w2 + 2]).abs();
+
+            let nw_est = other[i - w1 - 1] - g[i - w1 - 1];
+            let ne_est = other[i - w1 + 1] - g[i - w1 + 1];
+            let sw_est = other[i + w1 - 1] - g[i + w1 - 1];
+            let se_est = other[i + w1 + 1] - g[i + w1 + 1];
+
+            let p_est = (nw_grad * se_est + se_grad * nw_est) / (nw_grad + se_grad);
+            let q_est = (ne_grad * sw_est + sw_grad * ne_est) / (ne_grad + sw_grad);
+
+            let value = g[i] + (1.0 - pq_disc) * p_est + pq_disc * q_est;
+            rgb[c][i] = value.clamp(0.0, 1.0);
+        }
+    }
+
+    // Step 4.3: red and blue at the green sites
+    for row in 4..height - 4 {
+        for col in 4..w1 - 4 {
+            let i = row * w1 + col;
+            if color[i] != green {
+                continue;
+            }
+            let vh_disc = discriminate(&vh_dir, i);
+            let g = &rgb[green];
+            let g0 = g[i];
+            let n1 = EPS + (g0 - g[i - w2]).abs();
+            let s1 = EPS + (g0 - g[i + w2]).abs();
+            let w1_grad = EPS + (g0 - g[i - 2]).abs();
+            let e1 = EPS + (g0 - g[i + 2]).abs();
+
+            for c in (0..3).filter(|c| *c != green) {
+                let (g, other) = (&rgb[green], &rgb[c]);
+                let sn_abs = (other[i - w1] - other[i + w1]).abs();
+                let ew_abs = (other[i - 1] - other[i + 1]).abs();
+
+                let n_grad = n1 + sn_abs + (other[i - w1] - other[i - w3]).abs();
+                let s_grad = s1 + sn_abs + (other[i + w1] - other[i + w3]).abs();
+                let w_grad = w1_grad + ew_abs + (other[i - 1] - other[i - 3]).abs();
+                let e_grad = e1 + ew_abs + (other[i + 1] - other[i + 3]).abs();
+
+                let n_est = other[i - w1] - g[i - w1];
+                let s_est = other[i + w1] - g[i + w1];
+                let w_est = other[i - 1] - g[i - 1];
+                let e_est = other[i + 1] - g[i + 1];
+
+                let v_est = (n_grad * s_est + s_grad * n_est) / (n_grad + s_grad);
+                let h_est = (e_grad * w_est + w_grad * e_est) / (e_grad + w_grad);
+
+                let value = g0 + (1.0 - vh_disc) * v_est + vh_disc * h_est;
+                rgb[c][i] = value.clamp(0.0, 1.0);
+            }
+        }
+    }
+
+    let mut output = Image::new(bounds, 3, PixelType::Float);
+    for row in 0..image_height {
+        for col in 0..image_width {
+            let i = (row + PAD) * w1 + col + PAD;
+            for (plane, values) in rgb.iter().enumerate() {
+                output.set(
+                    bounds.top + row as i32,
+                    bounds.left + col as i32,
+                    plane as u32,
+                    values[i],
+                );
+            }
+        }
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+fn check_bayer_phase(colors: [[u8; 2]; 2]) {
+    use crate::rect::Rect;
+
+    let mut pattern = [[0_u8; 8]; 8];
+    for (row, colors) in colors.iter().enumerate() {
+        pattern[row][..2].copy_from_slice(colors);
+    }
+    let info = MosaicInfo::new(2, 2, pattern, [0, 1, 2, 3]).unwrap();
+    assert!(info.is_bayer());
+    assert_eq!(info.bayer_green(), 1);
+
+    let levels = [0.25, 0.5, 0.125];
+    let mut image = Image::new(Rect::new(0, 0, 20, 24).unwrap(), 1, PixelType::Float);
+    for row in 0..20 {
+        for col in 0..24 {
+            image.set(row, col, 0, levels[info.plane_at(row, col)]);
+        }
+    }
+    let output = rcd(&info, &image).unwrap();
+    for row in 0..20 {
+        for col in 0..24 {
+            for (plane, level) in levels.iter().enumerate() {
+                assert!((output.get(row, col, plane as u32) - level).abs() < 1e-3);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_rcd_rggb() {
+    check_bayer_phase([[0, 1], [1, 2]]);
+}
+
+#[test]
+fn test_rcd_bggr() {
+    check_bayer_phase([[2, 1], [1, 0]]);
+}
+
+#[test]
+fn test_rcd_grbg() {
+    check_bayer_phase([[1, 0], [2, 1]]);
+}
+
+#[test]
+fn test_rcd_gbrg() {
+    check_bayer_phase([[1, 2], [0, 1]]);
+}
+
+#[test]
+fn test_bilinear_green_split() {
+    use crate::rect::Rect;
+
+    // RGGB with the greens of the red rows slightly darker
+    let mut pattern = [[0_u8; 8]; 8];
+    pattern[0][..2].copy_from_slice(&[0, 1]);
+    pattern[1][..2].copy_from_slice(&[1, 2]);
+    let mut info = MosaicInfo::new(2, 2, pattern, [0, 1, 2, 3]).unwrap();
+    let mut image = Image::new(Rect::new(0, 0, 8, 8).unwrap(), 1, PixelType::Float);
+    for row in 0..8 {
+        for col in 0..8 {
+            let value = match (row % 2, col % 2) {
+                (0, 0) => 0.25,
+                (0, 1) => 0.5,
+                (1, 0) => 0.52,
+                _ => 0.125,
+            };
+            image.set(row, col, 0, value);
+        }
+    }
+    assert_eq!(info.bilinear(&image).get(2, 3, 1), 0.5);
+
+    // Both greens meet halfway once the split is known
+    info.bayer_green_split = 5000;
+    let output = info.bilinear(&image);
+    assert!((output.get(2, 3, 1) - 0.51).abs() < 1e-6);
+    assert!((output.get(3, 2, 1) - 0.51).abs() < 1e-6);
+}
@@ -0,0 +1,280 @@
+//! Demosaicing for arbitrary repeating CFA patterns, such as Fujifilm's 6x6
+//! X-Trans.
+//!
+//! The most frequent color of the pattern (green for X-Trans) is
+//! interpolated first along the horizontal, vertical and both diagonal
+//! directions, each estimate weighted by how smooth the image is in that
+//! direction. The other colors are then filled in from their color
+//! differences to the full resolution plane, which keeps edges sharp and
+//! avoids most of the color fringing of plain bilinear interpolation.
+
+use crate::demosaic::MosaicInfo;
+use crate::image::{Image, PixelType};
+use crate::limits::{MAX_CFA_PATTERN, MAX_COLOR_PLANES};
+
+const DIRECTIONS: [(i32, i32); 4] = [(0, 1), (1, 0), (1, 1), (1, -1)];
+
+/// How far along a direction we look for a sample of the dominant color
+const MAX_REACH: i32 = 3;
+
+const EPS: f32 = 1e-5;
+
+/// The mosaic normalized to 0.0..=1.0 along with the plane of every site
+struct Mosaic {
+    width: i32,
+    height: i32,
+    values: Vec<f32>,
+    planes: Vec<usize>,
+}
+
+impl Mosaic {
+    fn new(info: &MosaicInfo, image: &Image) -> Self {
+        let bounds = image.bounds();
+        let (width, height) = (image.width() as i32, image.height() as i32);
+        let scale = 1.0 / image.pixel_type().max_value();
+        let mut values = Vec::with_capacity((width * height) as usize);
+        let mut planes = Vec::with_capacity((width * height) as usize);
+        for row in 0..height {
+            for col in 0..width {
+                values.push(image.get(bounds.top + row, bounds.left + col, 0) * scale);
+                planes.push(info.plane_at(row, col));
+            }
+        }
+        Self {
+            width,
+            height,
+            values,
+            planes,
+        }
+    }
+
+    #[inline]
+    fn index(&self, row: i32, col: i32) -> Option<usize> {
+        if row < 0 || col < 0 || row >= self.height || col >= self.width {
+            None
+        } else {
+            Some((row * self.width + col) as usize)
+        }
+    }
+
+    /// The first site of the plane found walking from a site in a direction,
+    /// as (index, distance)
+    fn find(&self, row: i32, col: i32, step: (i32, i32), plane: usize) -> Option<(usize, i32)> {
+        (1..=MAX_REACH).find_map(|distance| {
+            let i = self.index(row + step.0 * distance, col + step.1 * distance)?;
+            (self.planes[i] == plane).then_some((i, distance))
+        })
+    }
+}
+
+/// The plane with the most sites in the repeating pattern
+fn dominant_plane(info: &MosaicInfo) -> usize {
+    let mut counts = [0; MAX_COLOR_PLANES];
+    for row in 0..info.rows {
+        for col in 0..info.cols {
+            counts[info.plane_at(row as i32, col as i32)] += 1;
+        }
+    }
+    (0..info.color_planes)
+        .max_by_key(|plane| counts[*plane])
+        .unwrap_or(0)
+}
+
+/// Estimate the dominant color at a site of another color
+fn interpolate_dominant(mosaic: &Mosaic, dominant: usize, row: i32, col: i32) -> f32 {
+    let i = (row * mosaic.width + col) as usize;
+    let own = mosaic.planes[i];
+    let (mut sum, mut weights) = (0.0, 0.0);
+    for (dr, dc) in DIRECTIONS {
+        let forward = mosaic.find(row, col, (dr, dc), dominant);
+        let backward = mosaic.find(row, col, (-dr, -dc), dominant);
+        let (Some((f, f_distance)), Some((b, b_distance))) = (forward, backward) else {
+            continue;
+        };
+        let (f_value, b_value) = (mosaic.values[f], mosaic.values[b]);
+        let estimate = (f_value * b_distance as f32 + b_value * f_distance as f32)
+            / (f_distance + b_distance) as f32;
+
+        // The gradient of the dominant color across the site, plus the
+        // gradient of the site's own color along the same direction
+        let mut gradient = (f_value - b_value).abs();
+        for step in [(dr, dc), (-dr, -dc)] {
+            if let Some((j, _)) = mosaic.find(row, col, step, own) {
+                gradient += 0.5 * (mosaic.values[j] - mosaic.values[i]).abs();
+            }
+        }
+        let weight = 1.0 / ((EPS + gradient) * (EPS + gradient));
+        sum += estimate * weight;
+        weights += weight;
+    }
+    if weights > 0.0 {
+        return sum / weights;
+    }
+    // Near the image edges, fall back to the average of the closest sites
+    let (mut sum, mut count) = (0.0, 0);
+    for radius in 1..=MAX_CFA_PATTERN as i32 / 2 {
+        for r in row - radius..=row + radius {
+            for c in col - radius..=col + radius {
+                if let Some(j) = mosaic.index(r, c) {
+                    if mosaic.planes[j] == dominant {
+                        sum += mosaic.values[j];
+                        count += 1;
+                    }
+                }
+            }
+        }
+        if count > 0 {
+            return sum / count as f32;
+        }
+    }
+    0.0
+}
+
+/// Demosaic an image with any repeating pattern of up to 8x8 sites
+pub fn demosaic(info: &MosaicInfo, image: &Image) -> Image {
+    let mosaic = Mosaic::new(info, image);
+    let dominant = dominant_plane(info);
+    let (width, height) = (mosaic.width, mosaic.height);
+
+    let mut full = mosaic.values.clone();
+    for row in 0..height {
+        for col in 0..width {
+            let i = (row * width + col) as usize;
+            if mosaic.planes[i] != dominant {
+                full[i] = interpolate_dominant(&mosaic, dominant, row, col).clamp(0.0, 1.0);
+            }
+        }
+    }
+
+    let bounds = image.bounds();
+    let mut output = Image::new(bounds, info.color_planes as u32, PixelType::Float);
+    for row in 0..height {
+        for col in 0..width {
+            let i = (row * width + col) as usize;
+            for plane in 0..info.color_planes {
+                let value = if plane == dominant {
+                    full[i]
+                } else if mosaic.planes[i] == plane {
+                    mosaic.values[i]
+                } else {
+                    // Average the color differences of the closest sites of
+                    // this plane, favouring the ones that look like this one
+                    let (mut sum, mut weights) = (0.0, 0.0);
+                    for radius in 1..=MAX_CFA_PATTERN as i32 / 2 {
+                        for r in row - radius..=row + radius {
+                            for c in col - radius..=col + radius {
+                                let Some(j) = mosaic.index(r, c) else {
+                                    continue;
+                                };
+                                if mosaic.planes[j] != plane {
+                                    continue;
+                                }
+                                let distance2 =
+                                    ((r - row) * (r - row) + (c - col) * (c - col)) as f32;
+                                let weight = 1.0 / (distance2 * (EPS + (full[j] - full[i]).abs()));
+                                sum += (mosaic.values[j] - full[j]) * weight;
+                                weights += weight;
+                            }
+                        }
+                        if weights > 0.0 {
+                            break;
+                        }
+                    }
+                    if weights > 0.0 {
+                        (full[i] + sum / weights).clamp(0.0, 1.0)
+                    } else {
+                        0.0
+                    }
+                };
+                output.set(bounds.top + row, bounds.left + col, plane as u32, value);
+            }
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+fn xtrans_info() -> MosaicInfo {
+    const XTRANS: [[u8; 6]; 6] = [
+        [1, 1, 0, 1, 1, 2],
+        [1, 1, 2, 1, 1, 0],
+        [2, 0, 1, 0, 2, 1],
+        [1, 1, 2, 1, 1, 0],
+        [1, 1, 0, 1, 1, 2],
+        [0, 2, 1, 2, 0, 1],
+    ];
+    let mut pattern = [[0_u8; MAX_CFA_PATTERN]; MAX_CFA_PATTERN];
+    for (row, colors) in XTRANS.iter().enumerate() {
+        pattern[row][..6].copy_from_slice(colors);
+    }
+    MosaicInfo::new(6, 6, pattern, [0, 1, 2, 3]).unwrap()
+}
+
+#[test]
+fn test_xtrans_flat_field() {
+    use crate::rect::Rect;
+
+    let info = xtrans_info();
+    assert_eq!(dominant_plane(&info), 1);
+
+    let levels = [0.25, 0.5, 0.125];
+    let mut image = Image::new(Rect::new(0, 0, 18, 18).unwrap(), 1, PixelType::Float);
+    for row in 0..18 {
+        for col in 0..18 {
+            image.set(row, col, 0, levels[info.plane_at(row, col)]);
+        }
+    }
+    let output = demosaic(&info, &image);
+    for row in 0..18 {
+        for col in 0..18 {
+            for (plane, level) in levels.iter().enumerate() {
+                assert!((output.get(row, col, plane as u32) - level).abs() < 1e-4);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_xtrans_edges() {
+    use crate::rect::Rect;
+
+    // A colored surface whose brightness steps from 0.2 to 0.8 across a
+    // vertical and a diagonal edge. Red and blue are fixed fractions of
+    // green, so any fringing shows up as a change of those ratios.
+    let info = xtrans_info();
+    let ratios = [0.7, 1.0, 0.5];
+    let edges: [fn(i32, i32) -> f32; 2] = [
+        |_, col| if col < 12 { 0.2 } else { 0.8 },
+        |row, col| if row + col < 24 { 0.2 } else { 0.8 },
+    ];
+    for brightness in edges {
+        let mut image = Image::new(Rect::new(0, 0, 24, 24).unwrap(), 1, PixelType::Float);
+        for row in 0..24 {
+            for col in 0..24 {
+                let plane = info.plane_at(row, col);
+                image.set(row, col, 0, ratios[plane] * brightness(row, col));
+            }
+        }
+        let output = demosaic(&info, &image);
+        // Away from the image borders, where the directional estimates
+        // run out of samples
+        for row in 3..21 {
+            for col in 3..21 {
+                let green = output.get(row, col, 1);
+                for (plane, ratio) in ratios.iter().enumerate() {
+                    let value = output.get(row, col, plane as u32);
+                    assert!((value - ratio * brightness(row, col)).abs() < 1e-3);
+                    assert!((value / green - ratio).abs() < 1e-3);
+                }
+            }
+        }
+
+        // Bilinear interpolation smears the edge and fringes
+        let output = info.bilinear(&image);
+        let fringe = (3..21)
+            .flat_map(|row| (3..21).map(move |col| (row, col)))
+            .map(|(row, col)| (output.get(row, col, 0) / output.get(row, col, 1) - 0.7).abs())
+            .fold(0.0_f32, f32::max);
+        assert!(fringe > 0.1);
+    }
+}
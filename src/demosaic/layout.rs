@@ -0,0 +1,163 @@
+//! Staggered CFA layouts (CFALayout 2 to 9), as used by Fujifilm SuperCCD and
+//! some older sensors. Their sites are shifted by half a row or half a
+//! column from a rectangular grid, so before demosaicing each site is
+//! resampled at its nominal grid position by linear interpolation between
+//! the sites of the same color on either side of it, along the staggered
+//! axis.
+
+use crate::demosaic::MosaicInfo;
+use crate::errors::{Error, ErrorKind};
+use crate::image::Image;
+use crate::limits::MAX_CFA_PATTERN;
+
+/// Rectangular layout, no resampling needed
+pub const CFA_LAYOUT_RECTANGULAR: u32 = 1;
+
+/// Is the CFALayout value one we know how to handle?
+pub fn is_supported(layout: u32) -> bool {
+    (CFA_LAYOUT_RECTANGULAR..=9).contains(&layout)
+}
+
+/// The physical offset as (rows, cols) of a site from its nominal position.
+/// The specification counts rows and columns from one, so its "even" rows
+/// and columns have an odd zero based index.
+pub fn site_offset(layout: u32, row: i32, col: i32) -> (f64, f64) {
+    let odd_row = row.rem_euclid(2) == 1;
+    let odd_col = col.rem_euclid(2) == 1;
+    let shift = |odd: bool, amount: f64| if odd { amount } else { 0.0 };
+    match layout {
+        // A: even columns are offset down by 1/2 row
+        2 => (shift(odd_col, 0.5), 0.0),
+        // B: even columns are offset up by 1/2 row
+        3 => (shift(odd_col, -0.5), 0.0),
+        // C: even rows are offset right by 1/2 column
+        4 => (0.0, shift(odd_row, 0.5)),
+        // D: even rows are offset left by 1/2 column
+        5 => (0.0, shift(odd_row, -0.5)),
+        // E: even rows are offset up by 1/2 row, even columns left by 1/2
+        // column
+        6 => (shift(odd_row, -0.5), shift(odd_col, -0.5)),
+        // F: even rows up, even columns right
+        7 => (shift(odd_row, -0.5), shift(odd_col, 0.5)),
+        // G: even rows down, even columns left
+        8 => (shift(odd_row, 0.5), shift(odd_col, -0.5)),
+        // H: even rows down, even columns right
+        9 => (shift(odd_row, 0.5), shift(odd_col, 0.5)),
+        _ => (0.0, 0.0),
+    }
+}
+
+/// Resample a staggered mosaic onto the rectangular grid of the same CFA
+/// pattern. Columns are resampled vertically and then rows horizontally, so
+/// layouts staggered along both axes are handled too.
+pub fn rectify(info: &MosaicInfo, image: &Image) -> crate::Result<Image> {
+    if !is_supported(info.layout) {
+        return Err(Error::new(ErrorKind::InvalidCfaPattern));
+    }
+    if info.layout == CFA_LAYOUT_RECTANGULAR {
+        return Ok(image.clone());
+    }
+    let image = resample_lines(info, image, true);
+    Ok(resample_lines(info, &image, false))
+}
+
+/// Interpolate every site at its nominal position along its column (or its
+/// row), between the closest sites of the same color before and after it.
+/// Sites with a neighbour on one side only take its value.
+fn resample_lines(info: &MosaicInfo, image: &Image, vertical: bool) -> Image {
+    let bounds = image.bounds();
+    let (height, width) = (image.height() as i32, image.width() as i32);
+    let length = if vertical { height } else { width };
+    let mut output = image.clone();
+    for row in 0..height {
+        for col in 0..width {
+            let plane = info.plane_at(row, col);
+            let nominal = if vertical { row } else { col };
+            // The sites of the line with the same color, as (physical
+            // position, value) in order of position
+            let site = |index: i32| {
+                let (r, c) = if vertical { (index, col) } else { (row, index) };
+                if index < 0 || index >= length || info.plane_at(r, c) != plane {
+                    return None;
+                }
+                let (dy, dx) = site_offset(info.layout, r, c);
+                let position = index as f64 + if vertical { dy } else { dx };
+                let value = image.get(bounds.top + r, bounds.left + c, 0) as f64;
+                Some((position, value))
+            };
+            let reach = MAX_CFA_PATTERN as i32 + 1;
+            let sites = || (nominal - reach..=nominal + reach).filter_map(site);
+            let before = sites()
+                .rev()
+                .find(|(position, _)| *position <= nominal as f64);
+            let after = sites().find(|(position, _)| *position >= nominal as f64);
+            let value = match (before, after) {
+                (Some((p0, v0)), Some((p1, v1))) if p1 - p0 > 1e-6 => {
+                    v0 + (v1 - v0) * (nominal as f64 - p0) / (p1 - p0)
+                }
+                (Some((_, value)), _) | (None, Some((_, value))) => value,
+                (None, None) => continue,
+            };
+            output.set_clipped(bounds.top + row, bounds.left + col, 0, value as f32);
+        }
+    }
+    output
+}
+
+#[test]
+fn test_rectify_staggered_columns() {
+    use crate::image::PixelType;
+    use crate::rect::Rect;
+
+    // A single color sensor looking at a vertical ramp, with every other
+    // column half a row lower
+    let mut info =
+        MosaicInfo::new(1, 1, [[0; MAX_CFA_PATTERN]; MAX_CFA_PATTERN], [0, 1, 2, 3]).unwrap();
+    info.layout = 2;
+    let mut image = Image::new(Rect::new(0, 0, 8, 8).unwrap(), 1, PixelType::Float);
+    for row in 0..8 {
+        for col in 0..8 {
+            let (dy, _) = site_offset(info.layout, row, col);
+            image.set(row, col, 0, (row as f64 + dy) as f32 / 8.0);
+        }
+    }
+    let output = rectify(&info, &image).unwrap();
+    for row in 2..6 {
+        for col in 2..6 {
+            assert!((output.get(row, col, 0) - row as f32 / 8.0).abs() < 1e-6);
+        }
+    }
+}
+
+#[test]
+fn test_rectify_bayer() {
+    use crate::image::PixelType;
+    use crate::rect::Rect;
+
+    // An RGGB sensor looking at a ramp along both axes, with the sites
+    // shifted by every layout in turn
+    let mut pattern = [[0_u8; MAX_CFA_PATTERN]; MAX_CFA_PATTERN];
+    pattern[0][..2].copy_from_slice(&[0, 1]);
+    pattern[1][..2].copy_from_slice(&[1, 2]);
+    let mut info = MosaicInfo::new(2, 2, pattern, [0, 1, 2, 3]).unwrap();
+    let ramp = |row: f64, col: f64| (row + 3.0 * col) as f32 / 64.0;
+    for layout in 2..=9 {
+        info.layout = layout;
+        let mut image = Image::new(Rect::new(0, 0, 12, 12).unwrap(), 1, PixelType::Float);
+        for row in 0..12 {
+            for col in 0..12 {
+                let (dy, dx) = site_offset(layout, row, col);
+                image.set(row, col, 0, ramp(row as f64 + dy, col as f64 + dx));
+            }
+        }
+        let output = rectify(&info, &image).unwrap();
+        // Every plane is exact where it has a site of its color on both
+        // sides of every position
+        for row in 2..10 {
+            for col in 2..10 {
+                let expected = ramp(row as f64, col as f64);
+                assert!((output.get(row, col, 0) - expected).abs() < 1e-6);
+            }
+        }
+    }
+}
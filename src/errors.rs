@@ -30,4 +30,38 @@ pub enum ErrorKind {
     MissingForwardMatrices,
     #[error("Reduction matrices not present")]
     MissingReductionMatrices,
+    #[error("Crop area is empty or lies outside the image")]
+    InvalidCrop,
+    #[error("Image size is empty")]
+    InvalidImageSize,
+    #[error("Unexpected end of stream")]
+    EndOfStream,
+    #[error("Unsupported non-optional opcode :({0})")]
+    UnsupportedOpcode(u32),
+    #[error("Malformed parameters for opcode :({0})")]
+    InvalidOpcode(u32),
+    #[error("Opcode list does not match its byte count")]
+    InvalidOpcodeList,
+    #[error("Invalid or unsupported CFA pattern")]
+    InvalidCfaPattern,
+    #[error("Unsupported raw photometric interpretation :({0})")]
+    UnsupportedPhotometric(u32),
+    #[error("Camera profile does not match the negative")]
+    InvalidCameraProfile,
+    #[error("Matrix is singular and cannot be inverted")]
+    SingularMatrix,
+    #[error("Malformed illuminant data")]
+    InvalidIlluminant,
+    #[error("Malformed or out of range profile gain table map")]
+    InvalidGainTableMap,
+    #[error("Negative has no stage 3 image to render")]
+    MissingStage3Image,
+    #[error("No camera profile matches")]
+    MissingCameraProfile,
+    #[error("Unsupported output pixel type")]
+    UnsupportedPixelType,
+    #[error("Raw image stage has no opcode list to apply")]
+    InvalidRawImageStage,
+    #[error("Malformed linearization table or black level deltas")]
+    InvalidLinearization,
 }
@@ -0,0 +1,209 @@
+//! ProfileGainTableMap (DNG 1.6): a spatially varying gain, used for local
+//! tone mapping. The image is covered by a grid of points, and every point
+//! has a table of gains indexed by a weighted mix of the RGB values of a
+//! pixel, so each area of the image can lift or compress its own range.
+
+use crate::errors::{Error, ErrorKind};
+use crate::image::Image;
+use crate::limits::{
+    MAX_PROFILE_GAIN_TABLE_MAP_POINTS, PROFILE_GAIN_TABLE_MAP_MAX_GAIN_VALUE,
+    PROFILE_GAIN_TABLE_MAP_MIN_GAIN_VALUE,
+};
+use crate::rect::Rect;
+use crate::stream::Stream;
+
+/// Range of the gamma applied to the table input
+const MIN_GAMMA: f32 = 0.125;
+const MAX_GAMMA: f32 = 8.0;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GainTableMap {
+    /// Number of grid points down and across the image
+    pub points_v: usize,
+    pub points_h: usize,
+    /// Distance between grid points, relative to the image size
+    pub spacing_v: f64,
+    pub spacing_h: f64,
+    /// Position of the first grid point, relative to the image size
+    pub origin_v: f64,
+    pub origin_h: f64,
+    /// Number of entries of the table of every grid point
+    pub points_n: usize,
+    /// Weights of R, G, B, min(R, G, B) and max(R, G, B) in the table input
+    pub input_weights: [f32; 5],
+    /// Applied to the table input before the lookup
+    pub gamma: f32,
+    /// Gains by row, then column, then table entry
+    gains: Vec<f32>,
+}
+
+impl GainTableMap {
+    /// Read the contents of a ProfileGainTableMap tag, which is always
+    /// big endian: the grid size, spacing and origin, the table size, the
+    /// input weights, an optional gamma and the gains.
+    pub fn parse(data: &[u8]) -> crate::Result<Self> {
+        let invalid = || Error::new(ErrorKind::InvalidGainTableMap);
+        let mut stream = Stream::big_endian(data);
+        let points_v = stream.get_u32()? as usize;
+        let points_h = stream.get_u32()? as usize;
+        let spacing_v = stream.get_f64()?;
+        let spacing_h = stream.get_f64()?;
+        let origin_v = stream.get_f64()?;
+        let origin_h = stream.get_f64()?;
+        let points_n = stream.get_u32()? as usize;
+        let mut input_weights = [0.0; 5];
+        for weight in input_weights.iter_mut() {
+            *weight = stream.get_f32()?;
+        }
+        let count = points_v
+            .checked_mul(points_h)
+            .and_then(|count| count.checked_mul(points_n))
+            .filter(|count| *count <= MAX_PROFILE_GAIN_TABLE_MAP_POINTS)
+            .ok_or_else(invalid)?;
+        // Later revisions of the tag store a gamma between the weights and
+        // the gains
+        let gamma = match stream.remaining() {
+            remaining if remaining == (count + 1) * 4 => stream.get_f32()?,
+            remaining if remaining == count * 4 => 1.0,
+            _ => return Err(invalid()),
+        };
+        let gains = (0..count)
+            .map(|_| stream.get_f32())
+            .collect::<crate::Result<Vec<_>>>()?;
+        let map = Self {
+            points_v,
+            points_h,
+            spacing_v,
+            spacing_h,
+            origin_v,
+            origin_h,
+            points_n,
+            input_weights,
+            gamma,
+            gains,
+        };
+        map.validate()?;
+        Ok(map)
+    }
+
+    fn validate(&self) -> crate::Result<()> {
+        let spacing_valid =
+            |points: usize, spacing: f64| points == 1 || (spacing.is_finite() && spacing > 0.0);
+        let valid = self.points_v > 0
+            && self.points_h > 0
+            && self.points_n > 0
+            && spacing_valid(self.points_v, self.spacing_v)
+            && spacing_valid(self.points_h, self.spacing_h)
+            && self.origin_v.is_finite()
+            && self.origin_h.is_finite()
+            && self.input_weights.iter().all(|weight| weight.is_finite())
+            && (MIN_GAMMA..=MAX_GAMMA).contains(&self.gamma)
+            && self.gains.len() == self.points_v * self.points_h * self.points_n
+            && self.gains.iter().all(|gain| {
+                (PROFILE_GAIN_TABLE_MAP_MIN_GAIN_VALUE as f32
+                    ..=PROFILE_GAIN_TABLE_MAP_MAX_GAIN_VALUE)
+                    .contains(gain)
+            });
+        if valid {
+            Ok(())
+        } else {
+            Err(Error::new(ErrorKind::InvalidGainTableMap))
+        }
+    }
+
+    pub fn gain(&self, row: usize, col: usize, entry: usize) -> f32 {
+        self.gains[(row * self.points_h + col) * self.points_n + entry]
+    }
+
+    /// The table input of a color: the weighted mix of its channels, clipped
+    /// to 0.0..=1.0 and raised to the gamma
+    pub fn table_input(&self, [r, g, b]: [f32; 3]) -> f32 {
+        let w = &self.input_weights;
+        let mixed =
+            w[0] * r + w[1] * g + w[2] * b + w[3] * r.min(g).min(b) + w[4] * r.max(g).max(b);
+        let mixed = mixed.clamp(0.0, 1.0);
+        if self.gamma == 1.0 {
+            mixed
+        } else {
+            mixed.powf(self.gamma)
+        }
+    }
+
+    /// The gain of a color at a position relative to the image size,
+    /// interpolated bilinearly between grid points and linearly between
+    /// table entries. Positions outside the grid use its edges.
+    pub fn evaluate(&self, v: f64, h: f64, rgb: [f32; 3]) -> f32 {
+        let grid = |position: f64, origin: f64, spacing: f64, points: usize| {
+            if points == 1 {
+                return (0, 0, 0.0);
+            }
+            let scaled = ((position - origin) / spacing).clamp(0.0, (points - 1) as f64);
+            let index = (scaled as usize).min(points - 2);
+            (index, index + 1, (scaled - index as f64) as f32)
+        };
+        let (row0, row1, row_fract) = grid(v, self.origin_v, self.spacing_v, self.points_v);
+        let (col0, col1, col_fract) = grid(h, self.origin_h, self.spacing_h, self.points_h);
+
+        let input = self.table_input(rgb) * (self.points_n - 1) as f32;
+        let entry0 = (input as usize).min(self.points_n.saturating_sub(2));
+        let entry1 = (entry0 + 1).min(self.points_n - 1);
+        let entry_fract = input - entry0 as f32;
+
+        let table = |row: usize, col: usize| {
+            let low = self.gain(row, col, entry0);
+            low + (self.gain(row, col, entry1) - low) * entry_fract
+        };
+        let top = table(row0, col0) * (1.0 - col_fract) + table(row0, col1) * col_fract;
+        let bottom = table(row1, col0) * (1.0 - col_fract) + table(row1, col1) * col_fract;
+        top * (1.0 - row_fract) + bottom * row_fract
+    }
+
+    /// Apply the gains to a 3 plane floating point image. The relative
+    /// positions of the grid span `area`, usually the default crop.
+    pub fn apply_image(&self, image: &mut Image, area: Rect<i32>) -> crate::Result<()> {
+        if image.planes() != 3 {
+            return Err(Error::new(ErrorKind::InvalidImageSize));
+        }
+        let height = (area.bottom - area.top).max(1) as f64;
+        let width = (area.right - area.left).max(1) as f64;
+        let bounds = image.bounds();
+        for row in bounds.top..bounds.bottom {
+            let v = (row - area.top) as f64 / height;
+            for col in bounds.left..bounds.right {
+                let h = (col - area.left) as f64 / width;
+                let rgb = [
+                    image.get(row, col, 0),
+                    image.get(row, col, 1),
+                    image.get(row, col, 2),
+                ];
+                let gain = self.evaluate(v, h, rgb);
+                for (plane, value) in rgb.into_iter().enumerate() {
+                    image.set(row, col, plane as u32, value * gain);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_gain_table_map() {
+    // A 1x2 grid with a gain of 2 on the left and 4 on the right for dark
+    // pixels, and no gain for bright ones, indexed by the maximum channel
+    let mut data = Vec::new();
+    data.extend_from_slice(&1_u32.to_be_bytes());
+    data.extend_from_slice(&2_u32.to_be_bytes());
+    for value in [1.0_f64, 1.0, 0.0, 0.0] {
+        data.extend_from_slice(&value.to_be_bytes());
+    }
+    data.extend_from_slice(&2_u32.to_be_bytes());
+    for value in [0.0_f32, 0.0, 0.0, 0.0, 1.0, 2.0, 1.0, 4.0, 1.0] {
+        data.extend_from_slice(&value.to_be_bytes());
+    }
+    assert!(GainTableMap::parse(&data[..data.len() - 4]).is_err());
+    let map = GainTableMap::parse(&data).unwrap();
+    assert_eq!(map.evaluate(0.0, 0.0, [0.0; 3]), 2.0);
+    assert_eq!(map.evaluate(0.0, 1.0, [0.0; 3]), 4.0);
+    assert_eq!(map.evaluate(0.0, 0.5, [0.0; 3]), 3.0);
+    assert_eq!(map.evaluate(0.0, 0.5, [0.2, 1.0, 0.5]), 1.0);
+}
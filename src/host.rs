@@ -1,3 +1,5 @@
+use crate::tag::values::DNG_VERSION_CURRENT;
+
 pub struct Host {
     /// Does the host require all the image metadata (vs. just checking
     /// to see if the file is readable)?
@@ -52,3 +54,50 @@ pub struct Host {
 
     preserve_stage2: bool,
 }
+
+impl Default for Host {
+    fn default() -> Self {
+        Self {
+            needs_metadata: true,
+            needs_image: true,
+            for_preview: false,
+            minimum_size: 0,
+            preffered_size: 0,
+            maximum_size: 0,
+            crop_factor: 1.0,
+            save_dng_version: DNG_VERSION_CURRENT,
+            save_linear_dng: false,
+            keep_original_file: false,
+            ignore_enhanced_ifd: false,
+            for_fast_save_to_dng: false,
+            fast_save_to_dng_size: 0,
+            preserve_stage2: false,
+        }
+    }
+}
+
+impl Host {
+    pub fn needs_metadata(&self) -> bool {
+        self.needs_metadata
+    }
+
+    pub fn set_needs_metadata(&mut self, needs_metadata: bool) {
+        self.needs_metadata = needs_metadata;
+    }
+
+    pub fn needs_image(&self) -> bool {
+        self.needs_image
+    }
+
+    pub fn set_needs_image(&mut self, needs_image: bool) {
+        self.needs_image = needs_image;
+    }
+
+    pub fn for_preview(&self) -> bool {
+        self.for_preview
+    }
+
+    pub fn set_for_preview(&mut self, for_preview: bool) {
+        self.for_preview = for_preview;
+    }
+}
@@ -0,0 +1,341 @@
+//! Hue / saturation / value adjustment tables of camera profiles
+//! (ProfileHueSatMapData and ProfileLookTableData). Each entry of the table
+//! shifts the hue and scales the saturation and value of the colors around
+//! its grid point, and colors between grid points get a trilinear blend of
+//! the surrounding entries.
+
+use crate::errors::{Error, ErrorKind};
+use crate::image::Image;
+use crate::stream::Stream;
+use crate::traits::{GammaSrgb, LinearFn};
+
+/// How the value axis of a table is spaced
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum ValueEncoding {
+    /// Value divisions are evenly spaced in linear values
+    #[default]
+    Linear = 0,
+    /// Value divisions are evenly spaced in sRGB gamma encoded values
+    Srgb = 1,
+}
+
+impl ValueEncoding {
+    pub fn from_tiff(value: u32) -> Option<Self> {
+        match value {
+            0 => Some(Self::Linear),
+            1 => Some(Self::Srgb),
+            _ => None,
+        }
+    }
+}
+
+/// One entry of a table
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct HueSatDelta {
+    /// Hue shift in degrees
+    pub hue_shift: f32,
+    /// Saturation scale factor
+    pub sat_scale: f32,
+    /// Value scale factor
+    pub val_scale: f32,
+}
+
+impl Default for HueSatDelta {
+    fn default() -> Self {
+        Self {
+            hue_shift: 0.0,
+            sat_scale: 1.0,
+            val_scale: 1.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct HueSatMap {
+    hue_divisions: usize,
+    sat_divisions: usize,
+    val_divisions: usize,
+    /// Entries ordered by value, then hue, then saturation
+    deltas: Vec<HueSatDelta>,
+    encoding: ValueEncoding,
+}
+
+impl HueSatMap {
+    /// An identity table of the given size. A table needs at least one hue
+    /// and value division, and two saturation divisions.
+    pub fn new(
+        hue_divisions: usize,
+        sat_divisions: usize,
+        val_divisions: usize,
+        encoding: ValueEncoding,
+    ) -> crate::Result<Self> {
+        if hue_divisions == 0 || sat_divisions < 2 || val_divisions == 0 {
+            return Err(Error::new(ErrorKind::InvalidCameraProfile));
+        }
+        Ok(Self {
+            hue_divisions,
+            sat_divisions,
+            val_divisions,
+            deltas: vec![HueSatDelta::default(); hue_divisions * sat_divisions * val_divisions],
+            encoding,
+        })
+    }
+
+    /// Read the table from the FLOAT triplets of a ProfileHueSatMapData or
+    /// ProfileLookTableData tag
+    pub fn parse(
+        stream: &mut Stream,
+        hue_divisions: usize,
+        sat_divisions: usize,
+        val_divisions: usize,
+        encoding: ValueEncoding,
+    ) -> crate::Result<Self> {
+        let mut map = Self::new(hue_divisions, sat_divisions, val_divisions, encoding)?;
+        for delta in map.deltas.iter_mut() {
+            *delta = HueSatDelta {
+                hue_shift: stream.get_f32()?,
+                sat_scale: stream.get_f32()?,
+                val_scale: stream.get_f32()?,
+            };
+        }
+        Ok(map)
+    }
+
+    /// The size of the table as (hues, sats, vals)
+    pub fn divisions(&self) -> (usize, usize, usize) {
+        (self.hue_divisions, self.sat_divisions, self.val_divisions)
+    }
+
+    pub fn encoding(&self) -> ValueEncoding {
+        self.encoding
+    }
+
+    fn index(&self, hue: usize, sat: usize, val: usize) -> usize {
+        (val * self.hue_divisions + hue) * self.sat_divisions + sat
+    }
+
+    pub fn delta(&self, hue: usize, sat: usize, val: usize) -> HueSatDelta {
+        self.deltas[self.index(hue, sat, val)]
+    }
+
+    pub fn set_delta(&mut self, hue: usize, sat: usize, val: usize, delta: HueSatDelta) {
+        let index = self.index(hue, sat, val);
+        self.deltas[index] = delta;
+    }
+
+    /// Does every entry leave colors unchanged?
+    pub fn is_identity(&self) -> bool {
+        self.deltas
+            .iter()
+            .all(|delta| *delta == HueSatDelta::default())
+    }
+
+    /// The weighted sum of tables of the same size, as used to interpolate
+    /// between the tables of the calibration illuminants. Tables with a zero
+    /// weight are skipped. None if there is nothing to interpolate.
+    pub fn interpolate(maps: &[(&Self, f64)]) -> crate::Result<Option<Self>> {
+        let mut maps = maps.iter().filter(|(_, weight)| *weight > 0.0);
+        let Some((first, weight)) = maps.next() else {
+            return Ok(None);
+        };
+        let mut result = (*first).clone();
+        let scale = |delta: &mut HueSatDelta, weight: f32| {
+            delta.hue_shift *= weight;
+            delta.sat_scale *= weight;
+            delta.val_scale *= weight;
+        };
+        result
+            .deltas
+            .iter_mut()
+            .for_each(|delta| scale(delta, *weight as f32));
+        for (map, weight) in maps {
+            if map.divisions() != result.divisions() {
+                return Err(Error::new(ErrorKind::InvalidCameraProfile));
+            }
+            for (delta, other) in result.deltas.iter_mut().zip(&map.deltas) {
+                delta.hue_shift += other.hue_shift * *weight as f32;
+                delta.sat_scale += other.sat_scale * *weight as f32;
+                delta.val_scale += other.val_scale * *weight as f32;
+            }
+        }
+        Ok(Some(result))
+    }
+
+    /// The table entry of a color, interpolated from the surrounding entries.
+    /// Hue is in 0.0..6.0, saturation and value in 0.0..=1.0, with value
+    /// already in the encoding of the table.
+    fn lookup(&self, hue: f32, sat: f32, val: f32) -> HueSatDelta {
+        let hue_scale = if self.hue_divisions < 2 {
+            0.0
+        } else {
+            self.hue_divisions as f32 / 6.0
+        };
+        let sat_scale = (self.sat_divisions - 1) as f32;
+        let val_scale = (self.val_divisions - 1) as f32;
+
+        // Hue wraps around, saturation and value extrapolate from the last
+        // cell
+        let hue_scaled = hue * hue_scale;
+        let mut hue0 = hue_scaled as usize;
+        let hue_fract = hue_scaled - hue0 as f32;
+        let mut hue1 = hue0 + 1;
+        if hue0 >= self.hue_divisions - 1 {
+            hue0 = self.hue_divisions - 1;
+            hue1 = 0;
+        }
+
+        let sat_scaled = sat * sat_scale;
+        let sat0 = (sat_scaled as usize).min(self.sat_divisions - 2);
+        let sat_fract = sat_scaled - sat0 as f32;
+
+        let (val0, val1, val_fract) = if self.val_divisions < 2 {
+            (0, 0, 0.0)
+        } else {
+            let val_scaled = val * val_scale;
+            let val0 = (val_scaled as usize).min(self.val_divisions - 2);
+            (val0, val0 + 1, val_scaled - val0 as f32)
+        };
+
+        let lerp = |a: HueSatDelta, b: HueSatDelta, f: f32| HueSatDelta {
+            hue_shift: a.hue_shift * (1.0 - f) + b.hue_shift * f,
+            sat_scale: a.sat_scale * (1.0 - f) + b.sat_scale * f,
+            val_scale: a.val_scale * (1.0 - f) + b.val_scale * f,
+        };
+        let plane = |val: usize| {
+            let low = lerp(
+                self.delta(hue0, sat0, val),
+                self.delta(hue1, sat0, val),
+                hue_fract,
+            );
+            let high = lerp(
+                self.delta(hue0, sat0 + 1, val),
+                self.delta(hue1, sat0 + 1, val),
+                hue_fract,
+            );
+            lerp(low, high, sat_fract)
+        };
+        if val0 == val1 {
+            plane(val0)
+        } else {
+            lerp(plane(val0), plane(val1), val_fract)
+        }
+    }
+
+    /// Adjust a linear RGB color
+    pub fn apply(&self, rgb: [f32; 3]) -> [f32; 3] {
+        let (mut hue, mut sat, val) = rgb_to_hsv(rgb);
+        // The value encoding only matters when the table has value divisions
+        let srgb = self.val_divisions > 1 && self.encoding == ValueEncoding::Srgb;
+        let mut encoded = val;
+        if srgb {
+            encoded = GammaSrgb.evaluate(val.clamp(0.0, 1.0) as f64) as f32;
+        }
+        let delta = self.lookup(hue, sat, encoded);
+
+        hue += delta.hue_shift * (6.0 / 360.0);
+        sat = (sat * delta.sat_scale).min(1.0);
+        let val = if srgb {
+            let encoded = (encoded * delta.val_scale).clamp(0.0, 1.0);
+            GammaSrgb.reverse_evaluate(encoded as f64) as f32
+        } else {
+            (val * delta.val_scale).max(0.0)
+        };
+        hsv_to_rgb(hue, sat, val)
+    }
+
+    /// Adjust every pixel of a 3 plane floating point image
+    pub fn apply_image(&self, image: &mut Image) -> crate::Result<()> {
+        if image.planes() != 3 {
+            return Err(Error::new(ErrorKind::InvalidImageSize));
+        }
+        for pixel in image.data_mut().chunks_exact_mut(3) {
+            let rgb = self.apply([pixel[0], pixel[1], pixel[2]]);
+            pixel.copy_from_slice(&rgb);
+        }
+        Ok(())
+    }
+}
+
+/// Hue in 0.0..6.0, saturation and value
+pub fn rgb_to_hsv([r, g, b]: [f32; 3]) -> (f32, f32, f32) {
+    let val = r.max(g).max(b);
+    let gap = val - r.min(g).min(b);
+    if gap <= 0.0 {
+        return (0.0, 0.0, val);
+    }
+    let hue = if r == val {
+        let hue = (g - b) / gap;
+        if hue < 0.0 {
+            hue + 6.0
+        } else {
+            hue
+        }
+    } else if g == val {
+        2.0 + (b - r) / gap
+    } else {
+        4.0 + (r - g) / gap
+    };
+    (hue, gap / val, val)
+}
+
+/// The inverse of [`rgb_to_hsv`]. Hue wraps around.
+pub fn hsv_to_rgb(hue: f32, sat: f32, val: f32) -> [f32; 3] {
+    if sat <= 0.0 {
+        return [val; 3];
+    }
+    let hue = hue.rem_euclid(6.0);
+    let sextant = (hue as usize).min(5);
+    let f = hue - sextant as f32;
+    let p = val * (1.0 - sat);
+    let q = val * (1.0 - sat * f);
+    let t = val * (1.0 - sat * (1.0 - f));
+    match sextant {
+        0 => [val, t, p],
+        1 => [q, val, p],
+        2 => [p, val, t],
+        3 => [p, q, val],
+        4 => [t, p, val],
+        _ => [val, p, q],
+    }
+}
+
+#[test]
+fn test_hue_sat_map_apply() {
+    let mut map = HueSatMap::new(6, 2, 1, ValueEncoding::Linear).unwrap();
+    assert!(map.is_identity());
+    let rgb = [0.6, 0.3, 0.15];
+    let same = map.apply(rgb);
+    for i in 0..3 {
+        assert!((same[i] - rgb[i]).abs() < 1e-6);
+    }
+
+    // Shifting every hue by 120 degrees turns red into green
+    for hue in 0..6 {
+        for sat in 0..2 {
+            let delta = HueSatDelta {
+                hue_shift: 120.0,
+                ..Default::default()
+            };
+            map.set_delta(hue, sat, 0, delta);
+        }
+    }
+    let green = map.apply([0.5, 0.1, 0.1]);
+    assert!((green[0] - 0.1).abs() < 1e-6);
+    assert!((green[1] - 0.5).abs() < 1e-6);
+    assert!((green[2] - 0.1).abs() < 1e-6);
+}
+
+#[test]
+fn test_hue_sat_map_srgb_identity() {
+    // Without value divisions the sRGB encoding is not used, in either
+    // direction. With them, encoding and decoding cancel out.
+    let rgb = [0.5, 0.3, 0.2];
+    for val_divisions in [1, 2] {
+        let map = HueSatMap::new(6, 2, val_divisions, ValueEncoding::Srgb).unwrap();
+        assert!(map.is_identity());
+        let same = map.apply(rgb);
+        for i in 0..3 {
+            assert!((same[i] - rgb[i]).abs() < 1e-5);
+        }
+    }
+}
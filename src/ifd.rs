@@ -204,8 +204,9 @@ pub struct Ifd {
     // uint64 fOpcodeList2Offset;
     opcode_list2_offset: u64,
     // uint32 fOpcodeList3Count;
+    opcode_list3_count: u32,
     // uint64 fOpcodeList3Offset;
-
+    opcode_list3_offset: u64,
     // dng_noise_profile fNoiseProfile;
 
     // dng_string fEnhanceParams;
@@ -237,4 +238,127 @@ pub struct Ifd {
 
 impl Ifd {
     const MAX_TILE_INFO: usize = 32;
+
+    pub fn active_area(&self) -> Rect<i32> {
+        self.active_area
+    }
+
+    /// The default crop origin as (h, v)
+    pub fn default_crop_origin(&self) -> (URational, URational) {
+        (self.default_crop_origin_h, self.default_crop_origin_v)
+    }
+
+    /// The default crop size as (h, v)
+    pub fn default_crop_size(&self) -> (URational, URational) {
+        (self.default_crop_size_h, self.default_crop_size_v)
+    }
+
+    /// The default user crop as (t, l, b, r)
+    pub fn default_user_crop(&self) -> (URational, URational, URational, URational) {
+        (
+            self.default_user_crop_t,
+            self.default_user_crop_l,
+            self.default_user_crop_b,
+            self.default_user_crop_r,
+        )
+    }
+
+    /// The default scale as (h, v)
+    pub fn default_scale(&self) -> (URational, URational) {
+        (self.default_scale_h, self.default_scale_v)
+    }
+
+    pub fn best_quality_scale(&self) -> URational {
+        self.best_quality_scale
+    }
+
+    pub fn photometric_interpretation(&self) -> u32 {
+        self.photometric_interpretation
+    }
+
+    pub fn samples_per_pixel(&self) -> u32 {
+        self.samples_per_pixel
+    }
+
+    pub fn bits_per_sample(&self) -> &[u32; MAX_SAMPLES_PER_PIXEL] {
+        &self.bits_per_sample
+    }
+
+    pub fn sample_format(&self) -> &[u32; MAX_SAMPLES_PER_PIXEL] {
+        &self.sample_format
+    }
+
+    /// The size of the black level repeat pattern as (rows, cols)
+    pub fn black_level_repeat(&self) -> (u32, u32) {
+        (self.black_level_repeat_rows, self.black_level_repeat_cols)
+    }
+
+    pub fn black_level(
+        &self,
+    ) -> &[[[f64; MAX_COLOR_PLANES]; MAX_BLACK_PATTERN]; MAX_BLACK_PATTERN] {
+        &self.black_level
+    }
+
+    pub fn white_level(&self) -> &[f64; MAX_COLOR_PLANES] {
+        &self.white_level
+    }
+
+    /// The size of the CFA repeat pattern as (rows, cols)
+    pub fn cfa_repeat_pattern(&self) -> (u32, u32) {
+        (self.cfa_repeat_pattern_rows, self.cfa_repeat_pattern_cols)
+    }
+
+    pub fn cfa_pattern(&self) -> &[[u8; MAX_CFA_PATTERN]; MAX_CFA_PATTERN] {
+        &self.cfa_pattern
+    }
+
+    pub fn cfa_plane_color(&self) -> &[u8; MAX_COLOR_PLANES] {
+        &self.cfa_plane_color
+    }
+
+    pub fn cfa_layout(&self) -> u32 {
+        self.cfa_layout
+    }
+
+    pub fn bayer_green_split(&self) -> u32 {
+        self.bayer_green_split
+    }
+
+    /// The type, offset and count of the LinearizationTable tag
+    pub fn linearization_table(&self) -> Option<(u32, u64, u32)> {
+        (self.linearization_table_count > 0).then_some((
+            self.linearization_table_type,
+            self.linearization_table_offset,
+            self.linearization_table_count,
+        ))
+    }
+
+    /// The type, offset and count of the BlackLevelDeltaH tag
+    pub fn black_level_delta_h(&self) -> Option<(u32, u64, u32)> {
+        (self.black_level_delta_h_count > 0).then_some((
+            self.black_level_delta_h_type,
+            self.black_level_delta_h_offset,
+            self.black_level_delta_h_count,
+        ))
+    }
+
+    /// The type, offset and count of the BlackLevelDeltaV tag
+    pub fn black_level_delta_v(&self) -> Option<(u32, u64, u32)> {
+        (self.black_level_delta_v_count > 0).then_some((
+            self.black_level_delta_v_type,
+            self.black_level_delta_v_offset,
+            self.black_level_delta_v_count,
+        ))
+    }
+
+    /// The offset and byte count of the given opcode list (1, 2 or 3)
+    pub fn opcode_list(&self, list: u32) -> Option<(u64, u32)> {
+        let (offset, count) = match list {
+            1 => (self.opcode_list1_offset, self.opcode_list1_count),
+            2 => (self.opcode_list2_offset, self.opcode_list2_count),
+            3 => (self.opcode_list3_offset, self.opcode_list3_count),
+            _ => return None,
+        };
+        (count > 0).then_some((offset, count))
+    }
 }
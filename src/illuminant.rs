@@ -1,17 +1,109 @@
 use crate::coord::Coord;
+use crate::errors::{Error, ErrorKind};
+use crate::limits::{MAX_SPECTRUM_SAMPLES, MIN_SPECTRUM_SAMPLES};
 use crate::matrix::Vector;
 use crate::point::Point;
+use crate::stream::Stream;
 use crate::tag::values::LightSource;
 use crate::temparature::Temperature;
 use crate::traits::{LinearFn, Normalize, SmoothStep};
 use crate::types::rational::URational;
+use std::num::NonZeroU32;
 
+/// How the IlluminantData tags describe a custom calibration illuminant
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum IlluminantType {
-    WhiteXY,
-    Spectrum,
+    WhiteXY = 0,
+    Spectrum = 1,
 }
 
+/// Shortest wavelength of [`CIE_1931_CMF`], in nm
+const CIE_1931_MIN_LAMBDA: f64 = 380.0;
+/// Wavelength step of [`CIE_1931_CMF`], in nm
+const CIE_1931_LAMBDA_SPACING: f64 = 10.0;
+
+/// The CIE 1931 2° standard observer color matching functions (x̄, ȳ, z̄)
+/// from 380 nm to 780 nm
+const CIE_1931_CMF: [[f64; 3]; 41] = [
+    [0.001368, 0.000039, 0.006450],
+    [0.004243, 0.000120, 0.020050],
+    [0.014310, 0.000396, 0.067850],
+    [0.043510, 0.001210, 0.207400],
+    [0.134380, 0.004000, 0.645600],
+    [0.283900, 0.011600, 1.385600],
+    [0.348280, 0.023000, 1.747060],
+    [0.336200, 0.038000, 1.772110],
+    [0.290800, 0.060000, 1.669200],
+    [0.195360, 0.090980, 1.287640],
+    [0.095640, 0.139020, 0.812950],
+    [0.032010, 0.208020, 0.465180],
+    [0.004900, 0.323000, 0.272000],
+    [0.009300, 0.503000, 0.158200],
+    [0.063270, 0.710000, 0.078250],
+    [0.165500, 0.862000, 0.042160],
+    [0.290400, 0.954000, 0.020300],
+    [0.433450, 0.994950, 0.008750],
+    [0.594500, 0.995000, 0.003900],
+    [0.762100, 0.952000, 0.002100],
+    [0.916300, 0.870000, 0.001650],
+    [1.026300, 0.757000, 0.001100],
+    [1.062200, 0.631000, 0.000800],
+    [1.002600, 0.503000, 0.000340],
+    [0.854450, 0.381000, 0.000190],
+    [0.642400, 0.265000, 0.000050],
+    [0.447900, 0.175000, 0.000020],
+    [0.283500, 0.107000, 0.000000],
+    [0.164900, 0.061000, 0.000000],
+    [0.087400, 0.032000, 0.000000],
+    [0.046770, 0.017000, 0.000000],
+    [0.022700, 0.008210, 0.000000],
+    [0.011359, 0.004102, 0.000000],
+    [0.005790, 0.002091, 0.000000],
+    [0.002899, 0.001047, 0.000000],
+    [0.001440, 0.000520, 0.000000],
+    [0.000690, 0.000249, 0.000000],
+    [0.000332, 0.000120, 0.000000],
+    [0.000166, 0.000060, 0.000000],
+    [0.000083, 0.000030, 0.000000],
+    [0.000042, 0.000015, 0.000000],
+];
+
+/// The white point of a spectral power distribution sampled every
+/// `lambda_spacing` nm from `min_lambda` nm. The spectrum is linearly
+/// interpolated at the color matching function wavelengths and taken as zero
+/// outside its range. None if it has no power in the visible range.
+pub fn spectrum_to_xy(min_lambda: f64, lambda_spacing: f64, spectrum: &[f64]) -> Option<Coord> {
+    if lambda_spacing <= 0.0 || spectrum.len() < MIN_SPECTRUM_SAMPLES {
+        return None;
+    }
+    let last = (spectrum.len() - 1) as f64;
+    let power = |lambda: f64| {
+        let position = (lambda - min_lambda) / lambda_spacing;
+        if !(0.0..=last).contains(&position) {
+            return 0.0;
+        }
+        let index = (position as usize).min(spectrum.len() - 2);
+        let f = position - index as f64;
+        spectrum[index] * (1.0 - f) + spectrum[index + 1] * f
+    };
+    let mut xyz = Vector::new([0.0; 3]);
+    for (index, cmf) in CIE_1931_CMF.iter().enumerate() {
+        let power = power(CIE_1931_MIN_LAMBDA + index as f64 * CIE_1931_LAMBDA_SPACING);
+        for i in 0..3 {
+            xyz[i] += power * cmf[i];
+        }
+    }
+    (xyz[0] + xyz[1] + xyz[2] > 0.0).then(|| Coord::xyz_to_xy(xyz))
+}
+
+fn read_urational(stream: &mut Stream) -> crate::Result<URational> {
+    let numerator = stream.get_u32()?;
+    let denominator =
+        NonZeroU32::new(stream.get_u32()?).ok_or(Error::new(ErrorKind::InvalidIlluminant))?;
+    Ok(URational::new(numerator, denominator))
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Illuminant {
     pub illuminant_type: IlluminantType,
     pub derived_white: Coord,
@@ -25,26 +117,188 @@ pub struct Illuminant {
     pub spectrum: Vec<URational>,
 }
 
+impl Default for Illuminant {
+    fn default() -> Self {
+        let zero = URational::new(0_u32, NonZeroU32::MIN);
+        Self {
+            illuminant_type: IlluminantType::WhiteXY,
+            derived_white: Coord::default(),
+            white_x: zero,
+            white_y: zero,
+            min_lambda: zero,
+            lambda_spacing: zero,
+            spectrum: Vec::new(),
+        }
+    }
+}
+
 impl Illuminant {
-    pub fn set_white_xy(&mut self, white_xy: Coord) {
+    /// Describe the illuminant by its white point
+    pub fn set_white_xy(&mut self, white_xy: Coord) -> crate::Result<()> {
         const MIN: f64 = 0.000001;
         const MAX: f64 = 0.999999;
-        let x = URational::from(white_xy.x);
-        let y = URational::from(white_xy.y);
+        const DENOMINATOR: u32 = 1_000_000;
+        if !(MIN..=MAX).contains(&white_xy.x) || !(MIN..=MAX).contains(&white_xy.y) {
+            return Err(Error::new(ErrorKind::InvalidIlluminant));
+        }
+        let denominator = NonZeroU32::new(DENOMINATOR).unwrap();
+        let rational =
+            |value: f64| URational::new((value * DENOMINATOR as f64).round() as u32, denominator);
+        *self = Self {
+            illuminant_type: IlluminantType::WhiteXY,
+            derived_white: white_xy,
+            white_x: rational(white_xy.x),
+            white_y: rational(white_xy.y),
+            ..Self::default()
+        };
+        Ok(())
+    }
 
-        // if  < MIN || x > MAX || y < MIN || y > MAX {
-        //     panic!("Invalid white point");
-        // }
+    /// Describe the illuminant by its spectral power distribution, deriving
+    /// its white point from it
+    pub fn set_spectrum(
+        &mut self,
+        min_lambda: URational,
+        lambda_spacing: URational,
+        spectrum: Vec<URational>,
+    ) -> crate::Result<()> {
+        if !(MIN_SPECTRUM_SAMPLES..=MAX_SPECTRUM_SAMPLES).contains(&spectrum.len()) {
+            return Err(Error::new(ErrorKind::InvalidIlluminant));
+        }
+        let power = spectrum.iter().map(URational::as_f64).collect::<Vec<_>>();
+        let derived_white = spectrum_to_xy(min_lambda.as_f64(), lambda_spacing.as_f64(), &power)
+            .ok_or(Error::new(ErrorKind::InvalidIlluminant))?;
+        *self = Self {
+            illuminant_type: IlluminantType::Spectrum,
+            derived_white,
+            min_lambda,
+            lambda_spacing,
+            spectrum,
+            ..Self::default()
+        };
+        Ok(())
     }
+
+    /// Read the contents of an IlluminantData tag: a SHORT type, then either
+    /// the x and y of the white point, or the sample count, the first
+    /// wavelength, the wavelength spacing and the samples of a spectrum, all
+    /// as RATIONALs.
+    pub fn parse(stream: &mut Stream) -> crate::Result<Self> {
+        let mut illuminant = Self::default();
+        match stream.get_u16()? {
+            kind if kind == IlluminantType::WhiteXY as u16 => {
+                let x = read_urational(stream)?;
+                let y = read_urational(stream)?;
+                illuminant.set_white_xy(Coord::new(x.as_f64(), y.as_f64()))?;
+                illuminant.white_x = x;
+                illuminant.white_y = y;
+            }
+            kind if kind == IlluminantType::Spectrum as u16 => {
+                let count = stream.get_u32()? as usize;
+                if !(MIN_SPECTRUM_SAMPLES..=MAX_SPECTRUM_SAMPLES).contains(&count) {
+                    return Err(Error::new(ErrorKind::InvalidIlluminant));
+                }
+                let min_lambda = read_urational(stream)?;
+                let lambda_spacing = read_urational(stream)?;
+                let spectrum = (0..count)
+                    .map(|_| read_urational(stream))
+                    .collect::<crate::Result<Vec<_>>>()?;
+                illuminant.set_spectrum(min_lambda, lambda_spacing, spectrum)?;
+            }
+            _ => return Err(Error::new(ErrorKind::InvalidIlluminant)),
+        }
+        Ok(illuminant)
+    }
+
+    /// The contents of an IlluminantData tag, the inverse of
+    /// [`Illuminant::parse`]
+    pub fn write(&self, big_endian: bool) -> Vec<u8> {
+        let mut data = Vec::new();
+        let mut put = |bytes: [u8; 4]| data.extend_from_slice(&bytes);
+        let u32_bytes = |value: u32| {
+            if big_endian {
+                value.to_be_bytes()
+            } else {
+                value.to_le_bytes()
+            }
+        };
+        let kind = self.illuminant_type as u16;
+        let kind = if big_endian {
+            kind.to_be_bytes()
+        } else {
+            kind.to_le_bytes()
+        };
+        let rationals = match self.illuminant_type {
+            IlluminantType::WhiteXY => vec![self.white_x, self.white_y],
+            IlluminantType::Spectrum => {
+                put(u32_bytes(self.spectrum.len() as u32));
+                [self.min_lambda, self.lambda_spacing]
+                    .into_iter()
+                    .chain(self.spectrum.iter().copied())
+                    .collect()
+            }
+        };
+        for rational in rationals {
+            put(u32_bytes(rational.numerator));
+            put(u32_bytes(rational.denominator.get()));
+        }
+        [kind.to_vec(), data].concat()
+    }
+
+    /// The illuminant of an EXIF light source. Other light sources are
+    /// described by the IlluminantData tag, passed as `other`. Unknown ones
+    /// have no valid white point.
     pub fn illuminant_data(light: &LightSource, other: Self) -> Self {
-        match light {
-            LightSource::StandardLightA | LightSource::Tungsten => {
-                // Illuminant::illuminant_data_a(other)
+        let white = match light {
+            LightSource::StandardLightA | LightSource::Tungsten => Coord::STD_A_XY_COORD,
+            LightSource::ISOStudioTungsten => Temperature {
+                temperature: 3200.0,
+                tint: 0.0,
+            }
+            .to_xy(),
+            LightSource::D50 => Coord::D50_XY_COORD,
+            LightSource::D55
+            | LightSource::Daylight
+            | LightSource::FineWeather
+            | LightSource::Flash
+            | LightSource::StandardLightB => Coord::D55_XY_COORD,
+            LightSource::D65 | LightSource::StandardLightC | LightSource::CloudyWeather => {
+                Coord::D65_XY_COORD
+            }
+            LightSource::D75 | LightSource::Shade => Coord::D75_XY_COORD,
+            // The CIE F series illuminants of each fluorescent class
+            LightSource::DaylightFluorescent => Coord::new(0.31310, 0.33727),
+            LightSource::DayWhiteFluorescent => Coord::new(0.34588, 0.35875),
+            LightSource::CoolWhiteFluorescent | LightSource::Fluorescent => {
+                Coord::new(0.37208, 0.37529)
+            }
+            LightSource::WhiteFluorescent => Coord::new(0.40910, 0.39430),
+            LightSource::WarmWhiteFluorescent => Coord::new(0.44018, 0.40329),
+            LightSource::Other => return other,
+            LightSource::Unknown => return Self::default(),
+        };
+        let mut data = Self::default();
+        match data.set_white_xy(white) {
+            Ok(()) => data,
+            Err(_) => Self::default(),
+        }
+    }
+
+    /// The temperature a profile was calibrated at: the temperature of the
+    /// standard light source, or for custom (Other) light sources the one of
+    /// the white point of their illuminant data. 0.0 when unknown.
+    pub fn calibration_temperature(light: u32, data: &Self) -> f64 {
+        match LightSource::from_tiff(light) {
+            Some(LightSource::Other) if data.derived_white.is_valid() => {
+                Temperature::from_coord(&data.derived_white)
+                    .map(|temperature| temperature.temperature)
+                    .unwrap_or(0.0)
             }
-            _ => todo!(),
+            Some(light) => light.temperature().unwrap_or(0.0),
+            None => 0.0,
         }
-        todo!()
     }
+
     pub fn calculate_triple_illuminant_weights(
         white: &Coord,
         light: &[Self; 3],
@@ -142,3 +396,46 @@ impl LinearFn for MapTemperature {
         unimplemented!("Impossible to reverse evaluate MapTemperature")
     }
 }
+
+#[test]
+fn test_parse_spectrum() {
+    // Planck's law at the temperature of standard illuminant A, 2856 K,
+    // from 360 nm to 830 nm in 5 nm steps
+    let spectrum = (0..95)
+        .map(|index| {
+            let lambda = (360.0 + 5.0 * index as f64) * 1e-9;
+            let power = 1e-25 / (lambda.powi(5) * ((1.4388e-2 / (lambda * 2856.0)).exp() - 1.0));
+            (power * 10000.0).round() as u32
+        })
+        .collect::<Vec<_>>();
+    let mut data = Vec::new();
+    data.extend_from_slice(&(IlluminantType::Spectrum as u16).to_be_bytes());
+    data.extend_from_slice(&(spectrum.len() as u32).to_be_bytes());
+    for value in [360, 1, 5, 1]
+        .into_iter()
+        .chain(spectrum.iter().flat_map(|value| [*value, 10000]))
+    {
+        data.extend_from_slice(&(value as u32).to_be_bytes());
+    }
+    let illuminant = Illuminant::parse(&mut Stream::big_endian(&data)).unwrap();
+    assert_eq!(illuminant.illuminant_type, IlluminantType::Spectrum);
+    assert!((illuminant.derived_white.x - Coord::STD_A_XY_COORD.x).abs() < 0.001);
+    assert!((illuminant.derived_white.y - Coord::STD_A_XY_COORD.y).abs() < 0.001);
+}
+
+#[test]
+fn test_illuminant_data_temperatures() {
+    for value in [
+        1, 2, 3, 4, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24,
+    ] {
+        let light = LightSource::from_tiff(value).unwrap();
+        let data = Illuminant::illuminant_data(&light, Illuminant::default());
+        let temperature = Temperature::from_coord(&data.derived_white).unwrap();
+        let expected = light.temperature().unwrap();
+        assert!(
+            (temperature.temperature - expected).abs() < expected * 0.1,
+            "{light:?}: {} K",
+            temperature.temperature
+        );
+    }
+}
@@ -1,5 +1,9 @@
-use crate::rect::Rect;
+use crate::errors::{Error, ErrorKind};
+use crate::orientation::Orientation;
+use crate::point::Point;
+use crate::rect::{Contains, Rect};
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum EdgeOption {
     /// Leave edge pixels unchanged
     None,
@@ -21,6 +25,7 @@ pub enum EdgeOption {
 }
 
 #[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PixelType {
     Byte,
     Short,
@@ -29,6 +34,32 @@ pub enum PixelType {
     Float,
 }
 
+impl PixelType {
+    /// The value of a white sample for this pixel type.
+    /// Integer samples are stored unscaled, so this is the largest value the
+    /// type can hold; floating point samples are normalized to 1.0
+    pub const fn max_value(self) -> f32 {
+        match self {
+            PixelType::Byte => u8::MAX as f32,
+            PixelType::Short => u16::MAX as f32,
+            PixelType::SShort => i16::MAX as f32,
+            PixelType::Long => u32::MAX as f32,
+            PixelType::Float => 1.0,
+        }
+    }
+
+    /// Round and clip a value to what a sample of this type can hold.
+    /// Floating point samples are left alone.
+    pub fn clip(self, value: f32) -> f32 {
+        match self {
+            PixelType::Float => value,
+            PixelType::SShort => value.round().clamp(i16::MIN as f32, i16::MAX as f32),
+            _ => value.round().clamp(0.0, self.max_value()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Image {
     /// The bounds for this image
     bounds: Rect<i32>,
@@ -38,10 +69,251 @@ pub struct Image {
     pixel_type: PixelType,
     /// How to handle requests to get image areas outside the image bounds.
     edge_option: EdgeOption,
+    /// The samples, stored row by row with the planes of each pixel
+    /// interleaved. Integer pixel types keep their unscaled values.
+    data: Vec<f32>,
+}
+
+impl Image {
+    pub fn new(bounds: Rect<i32>, planes: u32, pixel_type: PixelType) -> Self {
+        let width = (bounds.right - bounds.left).max(0) as usize;
+        let height = (bounds.bottom - bounds.top).max(0) as usize;
+        Self {
+            bounds,
+            planes,
+            pixel_type,
+            edge_option: EdgeOption::None,
+            data: vec![0.0; width * height * planes as usize],
+        }
+    }
+
+    pub fn bounds(&self) -> Rect<i32> {
+        self.bounds
+    }
+
+    pub fn planes(&self) -> u32 {
+        self.planes
+    }
+
+    pub fn pixel_type(&self) -> PixelType {
+        self.pixel_type
+    }
+
+    pub fn edge_option(&self) -> EdgeOption {
+        self.edge_option
+    }
+
+    pub fn set_edge_option(&mut self, edge_option: EdgeOption) {
+        self.edge_option = edge_option;
+    }
+
+    pub fn width(&self) -> u32 {
+        (self.bounds.right - self.bounds.left).max(0) as u32
+    }
+
+    pub fn height(&self) -> u32 {
+        (self.bounds.bottom - self.bounds.top).max(0) as u32
+    }
+
+    /// The size of the image as (rows, cols)
+    pub fn size(&self) -> Point<i32> {
+        Point::new(self.height() as i32, self.width() as i32)
+    }
+
+    /// Move the image so its top left corner is at `origin`, keeping its data
+    pub fn set_origin(&mut self, origin: Point<i32>) {
+        self.bounds = self.bounds - (self.bounds.top_left() - origin);
+    }
+
+    pub fn data(&self) -> &[f32] {
+        &self.data
+    }
+
+    pub fn data_mut(&mut self) -> &mut [f32] {
+        &mut self.data
+    }
+
+    #[inline]
+    fn offset(&self, row: i32, col: i32, plane: u32) -> usize {
+        let row = (row - self.bounds.top) as usize;
+        let col = (col - self.bounds.left) as usize;
+        (row * self.width() as usize + col) * self.planes as usize + plane as usize
+    }
+
+    /// Get a sample inside the image bounds
+    #[inline]
+    pub fn get(&self, row: i32, col: i32, plane: u32) -> f32 {
+        self.data[self.offset(row, col, plane)]
+    }
+
+    /// Set a sample inside the image bounds
+    #[inline]
+    pub fn set(&mut self, row: i32, col: i32, plane: u32, value: f32) {
+        let offset = self.offset(row, col, plane);
+        self.data[offset] = value;
+    }
+
+    /// Set a sample inside the image bounds, rounding and clipping it to the
+    /// range of the pixel type
+    #[inline]
+    pub fn set_clipped(&mut self, row: i32, col: i32, plane: u32, value: f32) {
+        let value = self.pixel_type.clip(value);
+        self.set(row, col, plane, value);
+    }
+
+    /// Get a sample anywhere, using the edge option to resolve samples
+    /// outside the image bounds
+    pub fn get_edge(&self, row: i32, col: i32, plane: u32) -> f32 {
+        if self.bounds.contains(&Point::new(row, col)) {
+            return self.get(row, col, plane);
+        }
+        let (top, left) = (self.bounds.top, self.bounds.left);
+        let (height, width) = (self.height() as i32, self.width() as i32);
+        if height == 0 || width == 0 {
+            return 0.0;
+        }
+        let clamp_row = row.clamp(top, top + height - 1);
+        let clamp_col = col.clamp(left, left + width - 1);
+        let wrap_row = top + (row - top).rem_euclid(height);
+        let wrap_col = left + (col - left).rem_euclid(width);
+        match self.edge_option {
+            EdgeOption::Zero => 0.0,
+            EdgeOption::None | EdgeOption::Repeat => self.get(clamp_row, clamp_col, plane),
+            EdgeOption::EdgeRepeatZeroLast => {
+                if plane + 1 == self.planes {
+                    0.0
+                } else {
+                    self.get(clamp_row, clamp_col, plane)
+                }
+            }
+            EdgeOption::EdgeWrapHorizontal => self.get(clamp_row, wrap_col, plane),
+            EdgeOption::EdgeWrapVertical => self.get(wrap_row, clamp_col, plane),
+            EdgeOption::EdgeWrapAll => self.get(wrap_row, wrap_col, plane),
+        }
+    }
+
+    /// Sample the image at a fractional position with bilinear
+    /// interpolation, resolving the neighbours with the edge option
+    pub fn get_bilinear(&self, row: f64, col: f64, plane: u32) -> f32 {
+        let row0 = row.floor();
+        let col0 = col.floor();
+        let fv = (row - row0) as f32;
+        let fh = (col - col0) as f32;
+        let (row0, col0) = (row0 as i32, col0 as i32);
+
+        let a = self.get_edge(row0, col0, plane);
+        let b = self.get_edge(row0, col0 + 1, plane);
+        let c = self.get_edge(row0 + 1, col0, plane);
+        let d = self.get_edge(row0 + 1, col0 + 1, plane);
+
+        let top = a + (b - a) * fh;
+        let bottom = c + (d - c) * fh;
+        top + (bottom - top) * fv
+    }
+
+    /// Resample the image to a new size with bilinear interpolation,
+    /// matching pixel centres between the two grids
+    pub fn resample(&self, size: Point<i32>) -> crate::Result<Image> {
+        if size.v <= 0 || size.h <= 0 || self.width() == 0 || self.height() == 0 {
+            return Err(Error::new(ErrorKind::InvalidImageSize));
+        }
+        let mut image = Image::new(
+            Rect {
+                top: 0,
+                left: 0,
+                bottom: size.v,
+                right: size.h,
+            },
+            self.planes,
+            self.pixel_type,
+        );
+        let scale_v = self.height() as f64 / size.v as f64;
+        let scale_h = self.width() as f64 / size.h as f64;
+        for row in 0..size.v {
+            let src_row = (row as f64 + 0.5) * scale_v - 0.5 + self.bounds.top as f64;
+            for col in 0..size.h {
+                let src_col = (col as f64 + 0.5) * scale_h - 0.5 + self.bounds.left as f64;
+                for plane in 0..self.planes {
+                    image.set(row, col, plane, self.get_bilinear(src_row, src_col, plane));
+                }
+            }
+        }
+        Ok(image)
+    }
 }
 
 pub trait ImageTrait<SubPixel> {
     /// Shrink the bounds of the image to the given rectangle
-    fn trim<T>(&mut self, bounds: Rect<T>) -> &mut Self;
-    fn roatate(&mut self) -> &mut Self;
+    fn trim(&mut self, bounds: Rect<i32>) -> crate::Result<&mut Self>;
+    /// Rotate and/or flip the image, the resulting image has its origin at 0, 0
+    fn rotate(&mut self, orientation: Orientation) -> &mut Self;
+}
+
+impl ImageTrait<f32> for Image {
+    fn trim(&mut self, bounds: Rect<i32>) -> crate::Result<&mut Self> {
+        if !self.bounds.contains(&bounds)
+            || bounds.top >= bounds.bottom
+            || bounds.left >= bounds.right
+        {
+            return Err(Error::new(ErrorKind::InvalidCrop));
+        }
+        let planes = self.planes as usize;
+        let row_len = (bounds.right - bounds.left) as usize * planes;
+        let mut data = Vec::with_capacity(row_len * (bounds.bottom - bounds.top) as usize);
+        for row in bounds.top..bounds.bottom {
+            let start = self.offset(row, bounds.left, 0);
+            data.extend_from_slice(&self.data[start..start + row_len]);
+        }
+        self.data = data;
+        self.bounds = bounds;
+        Ok(self)
+    }
+
+    fn rotate(&mut self, orientation: Orientation) -> &mut Self {
+        let (flip_d, flip_h, flip_v) = (
+            orientation.flip_d(),
+            orientation.flip_h(),
+            orientation.flip_v(),
+        );
+        if !flip_d && !flip_h && !flip_v {
+            return self;
+        }
+        let (height, width) = (self.height() as i32, self.width() as i32);
+        let (out_height, out_width) = if flip_d {
+            (width, height)
+        } else {
+            (height, width)
+        };
+        let mut image = Image::new(
+            Rect {
+                top: 0,
+                left: 0,
+                bottom: out_height,
+                right: out_width,
+            },
+            self.planes,
+            self.pixel_type,
+        );
+        image.edge_option = self.edge_option;
+        for row in 0..height {
+            for col in 0..width {
+                let (mut dst_row, mut dst_col) = (row, col);
+                if flip_h {
+                    dst_col = width - 1 - dst_col;
+                }
+                if flip_v {
+                    dst_row = height - 1 - dst_row;
+                }
+                if flip_d {
+                    std::mem::swap(&mut dst_row, &mut dst_col);
+                }
+                for plane in 0..self.planes {
+                    let value = self.get(row + self.bounds.top, col + self.bounds.left, plane);
+                    image.set(dst_row, dst_col, plane, value);
+                }
+            }
+        }
+        *self = image;
+        self
+    }
 }
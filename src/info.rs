@@ -1,10 +1,10 @@
 use crate::exif::Exif;
 use crate::host::Host;
 use crate::ifd::Ifd;
+use crate::stream::Stream;
 use crate::tag::values::Magic;
 
 pub struct Shared;
-pub struct Stream;
 
 pub struct DngInfo<'info> {
     pub tiff_block_offset: u64,
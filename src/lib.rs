@@ -1,20 +1,30 @@
+pub mod color_space;
 pub mod color_spec;
 pub mod coord;
+pub mod dcp;
+pub mod demosaic;
 pub mod file;
+pub mod gain_table_map;
+pub mod hue_sat_map;
 pub mod illuminant;
 pub mod info;
 pub mod limits;
+pub mod linearize;
 mod magic;
 pub mod matrix;
 pub mod orientation;
 pub mod pixel_buffer;
 pub mod point;
+pub mod rgb_tables;
 pub mod temparature;
+pub mod tone_curve;
 pub mod traits;
 pub mod types;
 pub mod shared;
 pub mod exif;
 pub mod negative;
+pub mod opcode;
+pub mod stream;
 pub use types::Result;
 pub mod errors;
 pub mod host;
@@ -22,4 +32,5 @@ pub mod ifd;
 pub mod image;
 pub mod read_image;
 pub mod rect;
+pub mod render;
 pub mod tag;
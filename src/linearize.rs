@@ -0,0 +1,249 @@
+use crate::errors::{Error, ErrorKind};
+use crate::ifd::Ifd;
+use crate::image::{Image, PixelType};
+use crate::limits::{MAX_BLACK_PATTERN, MAX_COLOR_PLANES, MAX_SAMPLES_PER_PIXEL};
+use crate::stream::Stream;
+use crate::tag::types::{
+    TYPE_DOUBLE, TYPE_FLOAT, TYPE_LONG, TYPE_RATIONAL, TYPE_SHORT, TYPE_SRATIONAL,
+};
+
+/// SampleFormat of IEEE floating point samples
+const SAMPLE_FORMAT_FLOAT: u32 = 3;
+
+/// Largest LinearizationTable, one entry for every 16 bit sample
+const MAX_LINEARIZATION_TABLE: u32 = 0x10000;
+
+/// Read the values of a tag, given as its (type, offset, count), out of the
+/// file data. Integer and real types are all read as reals.
+fn read_tag_values(
+    file: &[u8],
+    big_endian: bool,
+    (tag_type, offset, count): (u32, u64, u32),
+) -> crate::Result<Vec<f64>> {
+    let tag_type = u16::try_from(tag_type).unwrap_or_default();
+    let size = match tag_type {
+        TYPE_SHORT => 2,
+        TYPE_LONG | TYPE_FLOAT => 4,
+        TYPE_RATIONAL | TYPE_SRATIONAL | TYPE_DOUBLE => 8,
+        _ => return Err(Error::new(ErrorKind::InvalidLinearization)),
+    };
+    let start = usize::try_from(offset).map_err(|_| Error::new(ErrorKind::EndOfStream))?;
+    let data = (count as usize)
+        .checked_mul(size)
+        .and_then(|len| start.checked_add(len))
+        .and_then(|end| file.get(start..end))
+        .ok_or(Error::new(ErrorKind::EndOfStream))?;
+    let mut stream = Stream::new(data, big_endian);
+    let ratio = |n: f64, d: f64| if d == 0.0 { 0.0 } else { n / d };
+    (0..count)
+        .map(|_| match tag_type {
+            TYPE_SHORT => stream.get_u16().map(f64::from),
+            TYPE_LONG => stream.get_u32().map(f64::from),
+            TYPE_RATIONAL => Ok(ratio(stream.get_u32()? as f64, stream.get_u32()? as f64)),
+            TYPE_SRATIONAL => Ok(ratio(stream.get_i32()? as f64, stream.get_i32()? as f64)),
+            TYPE_FLOAT => stream.get_f32().map(f64::from),
+            _ => stream.get_f64(),
+        })
+        .collect()
+}
+
+/// What it takes to turn the raw stage 1 samples into linear stage 2 samples:
+/// an optional linearization table, then black level subtraction and
+/// scaling so the white level maps to the top of the stage 2 range.
+///
+/// Integer stage 1 images become 16 bit stage 2 images spanning the whole
+/// 0..=65535 range, floating point ones stay floating point in 0.0..=1.0.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LinearizationInfo {
+    /// Maps raw samples to linear ones, applied before the black level
+    pub linearization_table: Vec<u16>,
+    /// Size of the black level repeat pattern
+    pub black_level_repeat_rows: usize,
+    pub black_level_repeat_cols: usize,
+    /// Black level for every site of the repeat pattern and every plane
+    pub black_level: [[[f64; MAX_COLOR_PLANES]; MAX_BLACK_PATTERN]; MAX_BLACK_PATTERN],
+    /// Extra black level of every column of the active area
+    pub black_level_delta_h: Vec<f64>,
+    /// Extra black level of every row of the active area
+    pub black_level_delta_v: Vec<f64>,
+    /// Clipping level of every plane
+    pub white_level: [f64; MAX_COLOR_PLANES],
+}
+
+impl LinearizationInfo {
+    /// Black at zero and white at the top of the sample range of every plane
+    pub fn new(white_level: f64) -> Self {
+        Self {
+            linearization_table: Vec::new(),
+            black_level_repeat_rows: 1,
+            black_level_repeat_cols: 1,
+            black_level: [[[0.0; MAX_COLOR_PLANES]; MAX_BLACK_PATTERN]; MAX_BLACK_PATTERN],
+            black_level_delta_h: Vec::new(),
+            black_level_delta_v: Vec::new(),
+            white_level: [white_level; MAX_COLOR_PLANES],
+        }
+    }
+
+    /// The levels stored in the main IFD, with the linearization table and
+    /// black level deltas read out of the file data. A missing white level
+    /// defaults to the largest value the samples can hold, 1.0 for floating
+    /// point.
+    pub fn from_ifd(ifd: &Ifd, file: &[u8], big_endian: bool) -> crate::Result<Self> {
+        let mut info = if ifd.sample_format()[0] == SAMPLE_FORMAT_FLOAT {
+            Self::new(1.0)
+        } else {
+            let bits = ifd.bits_per_sample()[0].clamp(1, 32);
+            Self::new(((1_u64 << bits) - 1) as f64)
+        };
+        let (rows, cols) = ifd.black_level_repeat();
+        let (rows, cols) = (rows.max(1) as usize, cols.max(1) as usize);
+        if rows > MAX_BLACK_PATTERN || cols > MAX_BLACK_PATTERN {
+            return Err(Error::new(ErrorKind::InvalidImageSize));
+        }
+        info.black_level_repeat_rows = rows;
+        info.black_level_repeat_cols = cols;
+        info.black_level = *ifd.black_level();
+        for (white, ifd_white) in info.white_level.iter_mut().zip(ifd.white_level()) {
+            if *ifd_white > 0.0 {
+                *white = *ifd_white;
+            }
+        }
+        if let Some(table) = ifd.linearization_table() {
+            let (tag_type, _, count) = table;
+            if tag_type != TYPE_SHORT as u32 || count > MAX_LINEARIZATION_TABLE {
+                return Err(Error::new(ErrorKind::InvalidLinearization));
+            }
+            info.linearization_table = read_tag_values(file, big_endian, table)?
+                .into_iter()
+                .map(|value| value as u16)
+                .collect();
+        }
+        if let Some(delta) = ifd.black_level_delta_h() {
+            info.black_level_delta_h = read_tag_values(file, big_endian, delta)?;
+        }
+        if let Some(delta) = ifd.black_level_delta_v() {
+            info.black_level_delta_v = read_tag_values(file, big_endian, delta)?;
+        }
+        Ok(info)
+    }
+
+    /// The black level of a site, relative to the active area. Planes past
+    /// the color planes (such as a transparency channel) share the levels of
+    /// the last color plane.
+    pub fn black(&self, row: i32, col: i32, plane: u32) -> f64 {
+        let plane = (plane as usize).min(MAX_COLOR_PLANES - 1);
+        let pattern = self.black_level
+            [row.rem_euclid(self.black_level_repeat_rows as i32) as usize]
+            [col.rem_euclid(self.black_level_repeat_cols as i32) as usize][plane];
+        let delta_h = self
+            .black_level_delta_h
+            .get(col as usize)
+            .copied()
+            .unwrap_or(0.0);
+        let delta_v = self
+            .black_level_delta_v
+            .get(row as usize)
+            .copied()
+            .unwrap_or(0.0);
+        pattern + delta_h + delta_v
+    }
+
+    pub fn white(&self, plane: u32) -> f64 {
+        self.white_level[(plane as usize).min(MAX_COLOR_PLANES - 1)]
+    }
+
+    /// Build the stage 2 image out of the stage 1 image
+    pub fn linearize(&self, stage1: &Image) -> crate::Result<Image> {
+        if stage1.planes() == 0 || stage1.planes() as usize > MAX_SAMPLES_PER_PIXEL {
+            return Err(Error::new(ErrorKind::InvalidImageSize));
+        }
+        let pixel_type = match stage1.pixel_type() {
+            PixelType::Float => PixelType::Float,
+            _ => PixelType::Short,
+        };
+        let range = pixel_type.max_value() as f64;
+        let bounds = stage1.bounds();
+        let mut stage2 = Image::new(bounds, stage1.planes(), pixel_type);
+        for row in bounds.top..bounds.bottom {
+            for col in bounds.left..bounds.right {
+                for plane in 0..stage1.planes() {
+                    let mut value = stage1.get(row, col, plane) as f64;
+                    if let Some(last) = self.linearization_table.len().checked_sub(1) {
+                        value =
+                            self.linearization_table[(value.max(0.0) as usize).min(last)] as f64;
+                    }
+                    let black = self.black(row - bounds.top, col - bounds.left, plane);
+                    let white = self.white(plane);
+                    let scale = if white > black {
+                        range / (white - black)
+                    } else {
+                        0.0
+                    };
+                    stage2.set_clipped(row, col, plane, ((value - black) * scale) as f32);
+                }
+            }
+        }
+        Ok(stage2)
+    }
+}
+
+#[test]
+fn test_linearize_black_pattern() {
+    use crate::rect::Rect;
+
+    let mut info = LinearizationInfo::new(4095.0);
+    info.black_level_repeat_rows = 2;
+    info.black_level[0][0][0] = 255.0;
+    info.black_level[1][0][0] = 256.0;
+    let mut image = Image::new(Rect::new(0, 0, 2, 2).unwrap(), 1, PixelType::Short);
+    image
+        .data_mut()
+        .copy_from_slice(&[255.0, 4095.0, 256.0, 2175.5]);
+    let stage2 = info.linearize(&image).unwrap();
+    assert_eq!(stage2.pixel_type(), PixelType::Short);
+    assert_eq!(stage2.data(), &[0.0, 65535.0, 0.0, 32768.0]);
+}
+
+#[test]
+fn test_linearize_tables() {
+    use crate::rect::Rect;
+
+    // A linearization table doubling the raw values, then rational black
+    // level deltas per column and per row
+    let mut file = vec![0_u8; 4];
+    for value in [0_u16, 2, 4, 6, 8, 10] {
+        file.extend_from_slice(&value.to_le_bytes());
+    }
+    for value in [1_i32, 1, -1, 2] {
+        file.extend_from_slice(&value.to_le_bytes());
+    }
+    for value in [2_u16, 0] {
+        file.extend_from_slice(&value.to_le_bytes());
+    }
+
+    let mut info = LinearizationInfo::new(10.0);
+    info.linearization_table = read_tag_values(&file, false, (TYPE_SHORT as u32, 4, 6))
+        .unwrap()
+        .into_iter()
+        .map(|value| value as u16)
+        .collect();
+    info.black_level_delta_h =
+        read_tag_values(&file, false, (TYPE_SRATIONAL as u32, 16, 2)).unwrap();
+    assert_eq!(info.black_level_delta_h, [1.0, -0.5]);
+    info.black_level_delta_v = read_tag_values(&file, false, (TYPE_SHORT as u32, 32, 2)).unwrap();
+    assert_eq!(info.black_level_delta_v, [2.0, 0.0]);
+
+    let mut image = Image::new(Rect::new(4, 4, 6, 6).unwrap(), 1, PixelType::Short);
+    image.data_mut().copy_from_slice(&[3.0, 2.0, 1.0, 9.0]);
+    let stage2 = info.linearize(&image).unwrap();
+    // Every site is scaled by its own black level up to the white level,
+    // and raw values past the end of the table use its last entry
+    assert_eq!(stage2.get(4, 4, 0), (3.0_f32 * 65535.0 / 7.0).round());
+    assert_eq!(stage2.get(4, 5, 0), (2.5_f32 * 65535.0 / 8.5).round());
+    assert_eq!(stage2.get(5, 4, 0), (1.0_f32 * 65535.0 / 9.0).round());
+    assert_eq!(stage2.get(5, 5, 0), 65535.0);
+
+    // Tables past the end of the file and unknown types are rejected
+    assert!(read_tag_values(&file, false, (TYPE_SHORT as u32, 32, 4)).is_err());
+    assert!(read_tag_values(&file, false, (2, 4, 2)).is_err());
+}
@@ -1,5 +1,10 @@
+use crate::errors::{Error, ErrorKind};
 use std::ops::Mul;
 
+/// Pivots smaller than this, relative to the largest entry, make a matrix
+/// singular
+const SINGULAR_EPSILON: f64 = 1.0e-12;
+
 #[derive(Debug, Clone, PartialEq, Copy)]
 pub struct Matrix<const ROWS: usize, const COLS: usize = ROWS> {
     pub data: [[f64; COLS]; ROWS],
@@ -26,6 +31,41 @@ impl<const ROWS: usize, const COLS: usize> Matrix<ROWS, COLS> {
     pub const fn row(&self, row: usize) -> [f64; COLS] {
         self.data[row]
     }
+    /// Is every entry zero? Profiles use this for missing matrices.
+    pub fn is_empty(&self) -> bool {
+        self.data.iter().flatten().all(|value| *value == 0.0)
+    }
+
+    pub fn max_entry(&self) -> f64 {
+        self.data
+            .iter()
+            .flatten()
+            .copied()
+            .fold(f64::NEG_INFINITY, f64::max)
+    }
+
+    pub fn transpose(&self) -> Matrix<COLS, ROWS> {
+        let mut data = [[0.0; ROWS]; COLS];
+        for (i, row) in self.data.iter().enumerate() {
+            for (j, value) in row.iter().enumerate() {
+                data[j][i] = *value;
+            }
+        }
+        Matrix { data }
+    }
+
+    /// The Moore-Penrose pseudo-inverse of a matrix of full rank, which is
+    /// the regular inverse for square matrices. Tall matrices get a left
+    /// inverse and wide matrices a right inverse.
+    pub fn pseudo_invert(&self) -> crate::Result<Matrix<COLS, ROWS>> {
+        let transpose = self.transpose();
+        if ROWS >= COLS {
+            Ok((transpose * *self).invert()? * transpose)
+        } else {
+            Ok(transpose * (*self * transpose).invert()?)
+        }
+    }
+
     pub const fn col(&self, col: usize) -> [f64; ROWS] {
         let mut data = [0.0; ROWS];
         let mut row = 0;
@@ -33,7 +73,7 @@ impl<const ROWS: usize, const COLS: usize> Matrix<ROWS, COLS> {
             if row == ROWS {
                 break data;
             }
-            data[row] = self.data[0][col];
+            data[row] = self.data[row][col];
             row += 1;
         }
     }
@@ -54,6 +94,15 @@ impl<const SIZE: usize> Matrix<SIZE> {
         Self { data }
     }
 
+    /// A matrix with the vector on its diagonal
+    pub fn from_diagonal(diagonal: Vector<f64, SIZE>) -> Self {
+        let mut matrix = Self::default();
+        for (i, value) in diagonal.data.iter().enumerate() {
+            matrix.data[i][i] = *value;
+        }
+        matrix
+    }
+
     pub fn is_diagonal(&self) -> bool {
         for i in 0..SIZE {
             for j in 0..SIZE {
@@ -79,29 +128,73 @@ impl<const SIZE: usize> Matrix<SIZE> {
             }
 
             _ => {
-                unimplemented!("Unsupported matrix size");
+                // LU decomposition with partial pivoting, the determinant
+                // is the product of the pivots
+                let mut a = self.data;
+                let mut determinant = 1.0;
+                for col in 0..SIZE {
+                    let pivot = (col..SIZE)
+                        .max_by(|i, j| a[*i][col].abs().total_cmp(&a[*j][col].abs()))
+                        .unwrap_or(col);
+                    if a[pivot][col] == 0.0 {
+                        return 0.0;
+                    }
+                    if pivot != col {
+                        a.swap(pivot, col);
+                        determinant = -determinant;
+                    }
+                    determinant *= a[col][col];
+                    let pivot_row = a[col];
+                    for row in a.iter_mut().skip(col + 1) {
+                        let factor = row[col] / pivot_row[col];
+                        for (value, pivot) in row.iter_mut().zip(pivot_row).skip(col) {
+                            *value -= factor * pivot;
+                        }
+                    }
+                }
+                determinant
             }
         }
     }
 
-    pub const fn invert(&self) -> Self {
-        let mut data = [[0.0; SIZE]; SIZE];
-        let mut i = 0;
-        loop {
-            if i >= SIZE {
-                break;
+    /// The inverse of the matrix, by Gauss-Jordan elimination with partial
+    /// pivoting. Singular (or nearly singular) matrices are an error.
+    pub fn invert(&self) -> crate::Result<Self> {
+        let mut a = self.data;
+        let mut inverse = Self::identity().data;
+        let scale = self
+            .data
+            .iter()
+            .flatten()
+            .fold(0.0_f64, |max, value| max.max(value.abs()));
+        for col in 0..SIZE {
+            let pivot = (col..SIZE)
+                .max_by(|i, j| a[*i][col].abs().total_cmp(&a[*j][col].abs()))
+                .unwrap_or(col);
+            if a[pivot][col].abs() <= SINGULAR_EPSILON * scale {
+                return Err(Error::new(ErrorKind::SingularMatrix));
             }
-            let mut j = 0;
-            loop {
-                if j >= SIZE {
-                    break;
+            a.swap(pivot, col);
+            inverse.swap(pivot, col);
+
+            let divisor = a[col][col];
+            for j in 0..SIZE {
+                a[col][j] /= divisor;
+                inverse[col][j] /= divisor;
+            }
+            for row in 0..SIZE {
+                let factor = a[row][col];
+                if row == col || factor == 0.0 {
+                    continue;
+                }
+                for j in 0..SIZE {
+                    let (pivot_a, pivot_inverse) = (a[col][j], inverse[col][j]);
+                    a[row][j] -= factor * pivot_a;
+                    inverse[row][j] -= factor * pivot_inverse;
                 }
-                data[i][j] = self.data[j][i];
-                j += 1;
             }
-            i += 1;
         }
-        Self { data }
+        Ok(Self { data: inverse })
     }
 
     pub const fn cofactor<const C_SIZE: usize>(&self, row: usize, col: usize) -> Matrix<C_SIZE> {
@@ -202,6 +295,16 @@ impl<T, const LEN: usize> Vector<T, LEN> {
     }
 }
 
+impl<const LEN: usize> Vector<f64, LEN> {
+    pub fn max_entry(&self) -> f64 {
+        self.data.iter().copied().fold(f64::NEG_INFINITY, f64::max)
+    }
+
+    pub fn min_entry(&self) -> f64 {
+        self.data.iter().copied().fold(f64::INFINITY, f64::min)
+    }
+}
+
 impl<T, const LEN: usize> From<[T; LEN]> for Vector<T, LEN> {
     #[inline]
     fn from(data: [T; LEN]) -> Self {
@@ -238,9 +341,9 @@ impl<const ROWS: usize, const COLS: usize> Mul<Matrix<ROWS, COLS>> for Vector<f6
     #[inline]
     fn mul(self, rhs: Matrix<ROWS, COLS>) -> Self::Output {
         let mut data = [0.0; COLS];
-        for elem in data.iter_mut() {
+        for (j, elem) in data.iter_mut().enumerate() {
             for (i, row) in rhs.data.iter().enumerate() {
-                *elem += self.data[i] * row[0];
+                *elem += self.data[i] * row[j];
             }
         }
         Self::Output { data }
@@ -253,11 +356,42 @@ impl<const ROWS: usize, const COLS: usize> Mul<Vector<f64, COLS>> for Matrix<ROW
     #[inline]
     fn mul(self, rhs: Vector<f64, COLS>) -> Self::Output {
         let mut data = [0.0; ROWS];
-        for elem in data.iter_mut() {
-            for (i, row) in self.data.iter().enumerate() {
-                *elem += row[i] * rhs.data[i];
+        for (elem, row) in data.iter_mut().zip(self.data.iter()) {
+            for (i, value) in row.iter().enumerate() {
+                *elem += value * rhs.data[i];
             }
         }
         Self::Output { data }
     }
 }
+
+#[test]
+fn test_invert() {
+    // Needs pivoting, the top left entry is zero
+    let matrix = Matrix::new([[0.0, 2.0, 1.0], [1.0, 1.0, 0.0], [3.0, 0.0, 1.0]]);
+    let product = matrix * matrix.invert().unwrap();
+    for i in 0..3 {
+        for j in 0..3 {
+            let expected = if i == j { 1.0 } else { 0.0 };
+            assert!((product.data[i][j] - expected).abs() < 1e-12);
+        }
+    }
+    assert!((matrix.determinant() + 5.0).abs() < 1e-12);
+
+    let singular = Matrix::new([[1.0, 2.0], [2.0, 4.0]]);
+    assert!(singular.invert().is_err());
+
+    let matrix = Matrix::new([
+        [2.0, 0.0, 0.0, 1.0],
+        [0.0, 3.0, 0.0, 0.0],
+        [0.0, 0.0, 4.0, 0.0],
+        [1.0, 0.0, 0.0, 2.0],
+    ]);
+    assert!((matrix.determinant() - 36.0).abs() < 1e-12);
+
+    // A tall matrix times its pseudo-inverse (on the left) is the identity
+    let tall = Matrix::new([[1.0, 0.0], [0.0, 1.0], [1.0, 1.0]]);
+    let product = tall.pseudo_invert().unwrap() * tall;
+    assert!((product.data[0][0] - 1.0).abs() < 1e-12);
+    assert!(product.data[0][1].abs() < 1e-12);
+}
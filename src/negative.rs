@@ -1,8 +1,24 @@
-use crate::matrix::Vector;
+use crate::color_spec::ColorSpec;
+use crate::coord::Coord;
+use crate::demosaic::{DemosaicMode, MosaicInfo};
+use crate::errors::{Error, ErrorKind};
+use crate::gain_table_map::GainTableMap;
+use crate::host::Host;
+use crate::ifd::Ifd;
+use crate::image::{Image, ImageTrait, PixelType};
+use crate::limits::{MAX_COLOR_PLANES, MAX_SAMPLES_PER_PIXEL};
+use crate::linearize::LinearizationInfo;
+use crate::matrix::{Matrix, Vector};
+use crate::opcode::list::OpcodeList;
 use crate::point::Point;
+use crate::rect::Rect;
+use crate::shared::{CameraProfileID, CameraProfileInfo};
+use crate::tag::values::{PhotometricInterpretation, ProfileEmbedPolicy};
 use crate::traits::LinearFn;
 use crate::types::non_zero_float::{NonZeroUF64, UF64};
-use crate::types::rational::URational;
+use crate::types::rational::{SRational, URational};
+use std::num::{NonZeroI32, NonZeroU32};
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum RawImageStage {
     RawImageStagePreOpcode1,
     RawImageStagePostOpcode1,
@@ -49,6 +65,8 @@ pub struct Negative {
 
     // The default crop can be smaller than the "active" area which includes
     // the padding but not the black masked pixels.
+    active_area: Rect<i32>,
+
     default_crop_size_h: URational,
     default_crop_size_v: URational,
 
@@ -121,6 +139,51 @@ pub struct Negative {
     // Enhanced images can change the applied noise reduction, so we
     // need to keep around the original value.
     raw_noise_reduction_applied: URational,
+
+    // The stage 3 image, i.e. the output of the interpolation step. The
+    // default crop is expressed in its coordinates.
+    stage3_image: Option<Image>,
+
+    // The color filter array of the stage 2 image, None when the raw data
+    // is not a mosaic and does not need to be interpolated.
+    mosaic_info: Option<MosaicInfo>,
+
+    // Black and white levels used to build the stage 2 image.
+    linearization_info: Option<LinearizationInfo>,
+
+    // Number of color channels of the stage 3 image.
+    color_channels: u32,
+
+    // Amount by which each channel has already been scaled by analog
+    // amplifiers in the camera.
+    analog_balance: Option<Vector<f64, 3>>,
+
+    // The "As Shot" white balance, either as the camera values of a neutral
+    // color or as xy coordinates. The neutral wins if both are given.
+    camera_neutral: Option<Vector<f64, 3>>,
+    camera_white_xy: Option<Coord>,
+
+    // Individual camera calibrations, only used with profiles that have the
+    // same calibration signature.
+    camera_calibration: [Option<Matrix<3>>; 3],
+    camera_calibration_signature: String,
+
+    // The camera profiles of the negative: the one of the main IFD first,
+    // then the ones listed by ExtraCameraProfiles.
+    camera_profiles: Vec<(CameraProfileID, CameraProfileInfo)>,
+
+    // Opcodes applied to the stage 1 image (raw data as read from the file),
+    // the stage 2 image (linearized) and the stage 3 image (demosaiced).
+    opcode_list1: OpcodeList,
+    opcode_list2: OpcodeList,
+    opcode_list3: OpcodeList,
+
+    // Local tone mapping gains applied to the stage 3 image while rendering.
+    profile_gain_table_map: Option<GainTableMap>,
+
+    // Zero point for the exposure compensation, in stops. This reflects how
+    // the manufacturer sets up the camera and its conversions.
+    baseline_exposure: SRational,
     // Amount of noise for this negative (see dng_noise_profile for details).
 
     // dng_noise_profile fNoiseProfile;
@@ -206,3 +269,916 @@ pub struct Negative {
 
     // // Camera data --> camera calibration --> "inverse" of color matrix
 }
+
+impl Negative {
+    pub fn new(model_name: impl Into<String>) -> Self {
+        let model_name = model_name.into();
+        let zero = URational::new(0_u32, NonZeroU32::MIN);
+        let one = URational::new(1_u32, NonZeroU32::MIN);
+        Self {
+            stage: RawImageStage::RawImageStageNone,
+            local_name: model_name.clone(),
+            model_name,
+            active_area: Rect::default(),
+            default_crop_size_h: zero,
+            default_crop_size_v: zero,
+            default_crop_origin_h: zero,
+            default_crop_origin_v: zero,
+            raw_default_crop_size_h: zero,
+            raw_default_crop_size_v: zero,
+            raw_default_crop_origin_h: zero,
+            raw_default_crop_origin_v: zero,
+            default_user_crop_t: zero,
+            default_user_crop_l: zero,
+            default_user_crop_b: one,
+            default_user_crop_r: one,
+            default_scale_h: one,
+            default_scale_v: one,
+            raw_default_scale_h: one,
+            raw_default_scale_v: one,
+            best_quality_scale: one,
+            raw_best_quality_scale: one,
+            original_default_final_size: Point::new(0, 0),
+            original_best_quality_final_size: Point::new(0, 0),
+            original_default_crop_size_h: zero,
+            original_default_crop_size_v: zero,
+            raw_to_full_scale_h: one,
+            raw_to_full_scale_v: one,
+            baseline_noise: one,
+            noise_reduction_applied: zero,
+            raw_noise_reduction_applied: zero,
+            stage3_image: None,
+            mosaic_info: None,
+            linearization_info: None,
+            color_channels: 3,
+            analog_balance: None,
+            camera_neutral: None,
+            camera_white_xy: None,
+            camera_calibration: [None; 3],
+            camera_calibration_signature: String::new(),
+            camera_profiles: Vec::new(),
+            opcode_list1: OpcodeList::new(1),
+            opcode_list2: OpcodeList::new(2),
+            opcode_list3: OpcodeList::new(3),
+            profile_gain_table_map: None,
+            baseline_exposure: SRational::new(0, NonZeroI32::new(1).unwrap()),
+        }
+    }
+
+    pub fn model_name(&self) -> &str {
+        &self.model_name
+    }
+
+    pub fn local_name(&self) -> &str {
+        &self.local_name
+    }
+
+    /// Copy the crop, scale and active area tags of the main IFD
+    pub fn set_geometry_from_ifd(&mut self, ifd: &Ifd) {
+        self.set_active_area(ifd.active_area());
+        let (origin_h, origin_v) = ifd.default_crop_origin();
+        self.set_default_crop_origin(origin_h, origin_v);
+        let (size_h, size_v) = ifd.default_crop_size();
+        self.set_default_crop_size(size_h, size_v);
+        let (t, l, b, r) = ifd.default_user_crop();
+        self.set_default_user_crop(t, l, b, r);
+        let (scale_h, scale_v) = ifd.default_scale();
+        self.set_default_scale(scale_h, scale_v);
+        self.set_best_quality_scale(ifd.best_quality_scale());
+    }
+
+    /// The area of the stage 1 image holding valid image data, stage 2 and
+    /// stage 3 coordinates are relative to its top left corner
+    pub fn active_area(&self) -> Rect<i32> {
+        self.active_area
+    }
+
+    pub fn set_active_area(&mut self, area: Rect<i32>) {
+        self.active_area = area;
+    }
+
+    pub fn set_default_crop_size(&mut self, size_h: URational, size_v: URational) {
+        self.default_crop_size_h = size_h;
+        self.default_crop_size_v = size_v;
+        self.raw_default_crop_size_h = size_h;
+        self.raw_default_crop_size_v = size_v;
+    }
+
+    pub fn set_default_crop_origin(&mut self, origin_h: URational, origin_v: URational) {
+        self.default_crop_origin_h = origin_h;
+        self.default_crop_origin_v = origin_v;
+        self.raw_default_crop_origin_h = origin_h;
+        self.raw_default_crop_origin_v = origin_v;
+    }
+
+    /// Set the default user crop, in coordinates relative to the default crop
+    pub fn set_default_user_crop(
+        &mut self,
+        top: URational,
+        left: URational,
+        bottom: URational,
+        right: URational,
+    ) {
+        self.default_user_crop_t = top;
+        self.default_user_crop_l = left;
+        self.default_user_crop_b = bottom;
+        self.default_user_crop_r = right;
+    }
+
+    pub fn set_default_scale(&mut self, scale_h: URational, scale_v: URational) {
+        self.default_scale_h = scale_h;
+        self.default_scale_v = scale_v;
+        self.raw_default_scale_h = scale_h;
+        self.raw_default_scale_v = scale_v;
+    }
+
+    pub fn set_best_quality_scale(&mut self, scale: URational) {
+        self.best_quality_scale = scale;
+        self.raw_best_quality_scale = scale;
+    }
+
+    pub fn set_raw_to_full_scale(&mut self, scale_h: URational, scale_v: URational) {
+        self.raw_to_full_scale_h = scale_h;
+        self.raw_to_full_scale_v = scale_v;
+    }
+
+    /// Remember the final sizes of the original image this proxy was derived from
+    pub fn set_original_sizes(
+        &mut self,
+        default_final_size: Point<i32>,
+        best_quality_final_size: Point<i32>,
+    ) {
+        self.original_default_final_size = default_final_size;
+        self.original_best_quality_final_size = best_quality_final_size;
+    }
+
+    pub fn stage3_image(&self) -> Option<&Image> {
+        self.stage3_image.as_ref()
+    }
+
+    pub fn set_stage3_image(&mut self, image: Image) {
+        self.stage3_image = Some(image);
+    }
+
+    pub fn mosaic_info(&self) -> Option<&MosaicInfo> {
+        self.mosaic_info.as_ref()
+    }
+
+    pub fn set_mosaic_info(&mut self, info: Option<MosaicInfo>) {
+        self.mosaic_info = info;
+    }
+
+    /// Read the CFA description of the main IFD
+    pub fn read_mosaic_info(&mut self, ifd: &Ifd) -> crate::Result<()> {
+        self.mosaic_info = MosaicInfo::from_ifd(ifd)?;
+        Ok(())
+    }
+
+    pub fn linearization_info(&self) -> Option<&LinearizationInfo> {
+        self.linearization_info.as_ref()
+    }
+
+    pub fn set_linearization_info(&mut self, info: Option<LinearizationInfo>) {
+        self.linearization_info = info;
+    }
+
+    /// Number of color channels of the stage 3 image
+    pub fn color_channels(&self) -> u32 {
+        self.color_channels
+    }
+
+    pub fn set_color_channels(&mut self, channels: u32) {
+        self.color_channels = channels;
+    }
+
+    /// The analog balance of every channel, 1.0 when the camera did none
+    pub fn analog_balance(&self) -> Vector<f64, 3> {
+        self.analog_balance.unwrap_or(Vector::new([1.0; 3]))
+    }
+
+    pub fn set_analog_balance(&mut self, balance: Option<Vector<f64, 3>>) {
+        self.analog_balance = balance;
+    }
+
+    pub fn camera_neutral(&self) -> Option<Vector<f64, 3>> {
+        self.camera_neutral
+    }
+
+    pub fn set_camera_neutral(&mut self, neutral: Option<Vector<f64, 3>>) {
+        self.camera_neutral = neutral;
+    }
+
+    pub fn camera_white_xy(&self) -> Option<Coord> {
+        self.camera_white_xy
+    }
+
+    pub fn set_camera_white_xy(&mut self, white: Option<Coord>) {
+        self.camera_white_xy = white;
+    }
+
+    /// The camera calibration for the given illuminant (1, 2 or 3)
+    pub fn camera_calibration(&self, illuminant: usize) -> Option<Matrix<3>> {
+        self.camera_calibration
+            .get(illuminant.wrapping_sub(1))
+            .copied()
+            .flatten()
+    }
+
+    pub fn set_camera_calibration(&mut self, illuminant: usize, calibration: Option<Matrix<3>>) {
+        if let Some(slot) = self.camera_calibration.get_mut(illuminant.wrapping_sub(1)) {
+            *slot = calibration;
+        }
+    }
+
+    pub fn camera_calibration_signature(&self) -> &str {
+        &self.camera_calibration_signature
+    }
+
+    pub fn set_camera_calibration_signature(&mut self, signature: impl Into<String>) {
+        self.camera_calibration_signature = signature.into();
+    }
+
+    /// Build the color spec that renders this negative with a profile
+    pub fn make_color_spec<const ILML: usize>(
+        &self,
+        profile: &CameraProfileInfo,
+    ) -> crate::Result<ColorSpec<ILML, 3>> {
+        ColorSpec::new(profile, self)
+    }
+
+    /// Add a profile read from `file`. Profiles without color matrices are
+    /// ignored unless the negative is monochrome, where only their name and
+    /// tone curve apply. Duplicates of profiles already added are ignored.
+    pub fn add_profile(&mut self, profile: CameraProfileInfo, file: &[u8]) -> crate::Result<()> {
+        let id = profile.id(file)?;
+        let duplicate = self
+            .camera_profiles
+            .iter()
+            .any(|(other, _)| match id.fingerprint {
+                Some(_) => other.fingerprint == id.fingerprint,
+                None => *other == id,
+            });
+        if (id.fingerprint.is_none() && self.color_channels != 1) || duplicate {
+            return Ok(());
+        }
+        self.camera_profiles.push((id, profile));
+        Ok(())
+    }
+
+    /// Add the profile of the main IFD, then the profiles at the offsets of
+    /// the ExtraCameraProfiles tag
+    pub fn read_camera_profiles(
+        &mut self,
+        main: CameraProfileInfo,
+        extra_offsets: &[u64],
+        file: &[u8],
+    ) -> crate::Result<()> {
+        self.add_profile(main, file)?;
+        for offset in extra_offsets {
+            let start = usize::try_from(*offset).map_err(|_| Error::new(ErrorKind::EndOfStream))?;
+            self.add_profile(CameraProfileInfo::parse_extended(file, start)?, file)?;
+        }
+        Ok(())
+    }
+
+    pub fn profile_count(&self) -> usize {
+        self.camera_profiles.len()
+    }
+
+    pub fn profile_by_index(&self, index: usize) -> Option<&CameraProfileInfo> {
+        self.camera_profiles.get(index).map(|(_, profile)| profile)
+    }
+
+    pub fn profile_id(&self, index: usize) -> Option<&CameraProfileID> {
+        self.camera_profiles.get(index).map(|(id, _)| id)
+    }
+
+    /// Find a profile by ID, like the SDK: first by name and fingerprint,
+    /// then by name alone, preferring the profiles made for the individual
+    /// calibration of the camera, then by fingerprint alone. The default
+    /// profile is the first one, returned for the default ID, or for
+    /// unmatched IDs when `use_default_if_no_match` is set.
+    pub fn profile_by_id(
+        &self,
+        id: &CameraProfileID,
+        use_default_if_no_match: bool,
+    ) -> Option<&CameraProfileInfo> {
+        let default = || self.profile_by_index(0);
+        if *id == CameraProfileID::default() {
+            return default();
+        }
+        let by_id = id
+            .fingerprint
+            .filter(|_| !id.name.is_empty())
+            .and_then(|_| self.camera_profiles.iter().find(|(other, _)| other == id));
+        let by_fingerprint = || {
+            id.fingerprint.and_then(|fingerprint| {
+                self.camera_profiles
+                    .iter()
+                    .find(|(other, _)| other.fingerprint == Some(fingerprint))
+            })
+        };
+        let signature = self.camera_calibration_signature();
+        let by_name = || {
+            let mut named = self
+                .camera_profiles
+                .iter()
+                .filter(|(other, _)| !id.name.is_empty() && other.name == id.name);
+            let first = named.clone().next();
+            named
+                .find(|(_, profile)| profile.uses_camera_calibration(signature))
+                .or(first)
+        };
+        match by_id.or_else(by_name).or_else(by_fingerprint) {
+            Some((_, profile)) => Some(profile),
+            None if use_default_if_no_match => default(),
+            None => None,
+        }
+    }
+
+    pub fn profile_by_name(&self, name: &str) -> Option<&CameraProfileInfo> {
+        self.profile_by_id(&CameraProfileID::new(name, None), false)
+    }
+
+    /// The profiles that may be embedded in a file rendered with the
+    /// profile `used`, following their embed policies
+    pub fn profiles_to_embed<'n>(
+        &'n self,
+        used: &'n CameraProfileID,
+    ) -> impl Iterator<Item = &'n CameraProfileInfo> + 'n {
+        self.camera_profiles
+            .iter()
+            .filter(move |(id, profile)| match profile.profile_embed_policy() {
+                ProfileEmbedPolicy::AllowCopying | ProfileEmbedPolicy::NoRestrictions => true,
+                ProfileEmbedPolicy::EmbedIfUsed => id == used,
+                ProfileEmbedPolicy::EmbedNever => false,
+            })
+            .map(|(_, profile)| profile)
+    }
+
+    /// Is the raw data already demosaiced (LinearRaw)?
+    pub fn is_linear_raw(&self) -> bool {
+        self.mosaic_info.is_none()
+    }
+
+    /// Read how to turn the raw data of the main IFD into a stage 3 image:
+    /// its CFA for mosaic data, its levels and its number of channels. The
+    /// linearization tables are read out of the file data.
+    pub fn read_raw_info(&mut self, ifd: &Ifd, file: &[u8], big_endian: bool) -> crate::Result<()> {
+        let photometric = ifd.photometric_interpretation();
+        match PhotometricInterpretation::from_tiff(photometric) {
+            Some(PhotometricInterpretation::Cfa) => {
+                self.read_mosaic_info(ifd)?;
+                self.color_channels = self
+                    .mosaic_info
+                    .as_ref()
+                    .map_or(3, |info| info.color_planes as u32);
+            }
+            Some(PhotometricInterpretation::LinearRaw) => {
+                let samples = ifd.samples_per_pixel();
+                if samples == 0 || samples as usize > MAX_SAMPLES_PER_PIXEL {
+                    return Err(Error::new(ErrorKind::UnsupportedPhotometric(photometric)));
+                }
+                self.mosaic_info = None;
+                // Samples past the color planes, such as transparency, are
+                // carried along but are not colors
+                self.color_channels = samples.min(MAX_COLOR_PLANES as u32);
+            }
+            _ => return Err(Error::new(ErrorKind::UnsupportedPhotometric(photometric))),
+        }
+        self.linearization_info = Some(LinearizationInfo::from_ifd(ifd, file, big_endian)?);
+        Ok(())
+    }
+
+    /// Build the stage 2 image out of the stage 1 image. Without
+    /// linearization info the samples are only rescaled to the stage 2
+    /// range.
+    pub fn linearize(&self, stage1: &Image) -> crate::Result<Image> {
+        match &self.linearization_info {
+            Some(info) => info.linearize(stage1),
+            None => {
+                LinearizationInfo::new(stage1.pixel_type().max_value() as f64).linearize(stage1)
+            }
+        }
+    }
+
+    /// Run the whole raw pipeline on the stage 1 image and keep the result
+    /// as the stage 3 image: opcode list 1, trim to the active area,
+    /// linearization, opcode list 2, demosaic (skipped for LinearRaw data)
+    /// and opcode list 3.
+    pub fn build_stage3_image(
+        &mut self,
+        host: &Host,
+        mut stage1: Image,
+        mode: DemosaicMode,
+    ) -> crate::Result<()> {
+        self.stage = RawImageStage::RawImageStagePreOpcode1;
+        self.apply_opcodes(host, &mut stage1)?;
+        // Only the active area is linearized, and it becomes the origin of
+        // the stage 2 and stage 3 images
+        let area = self.active_area;
+        if area.bottom > area.top && area.right > area.left {
+            if area != stage1.bounds() {
+                stage1.trim(area)?;
+            }
+            stage1.set_origin(Point::new(0, 0));
+        }
+        let mut stage2 = self.linearize(&stage1)?;
+        drop(stage1);
+        self.apply_opcodes(host, &mut stage2)?;
+        let mut stage3 = self.interpolate(&stage2, mode)?;
+        drop(stage2);
+        self.apply_opcodes(host, &mut stage3)?;
+        self.stage3_image = Some(stage3);
+        Ok(())
+    }
+
+    /// Turn the stage 2 image into the stage 3 image. Mosaic images are
+    /// demosaiced, anything else is converted to floating point as is.
+    pub fn interpolate(&mut self, stage2: &Image, mode: DemosaicMode) -> crate::Result<Image> {
+        let image = match &self.mosaic_info {
+            Some(info) => info.demosaic(stage2, mode)?,
+            None => {
+                let scale = 1.0 / stage2.pixel_type().max_value();
+                let mut image = Image::new(stage2.bounds(), stage2.planes(), PixelType::Float);
+                for (output, input) in image.data_mut().iter_mut().zip(stage2.data()) {
+                    *output = input * scale;
+                }
+                image
+            }
+        };
+        if self.stage == RawImageStage::RawImageStagePostOpcode2 {
+            self.stage = RawImageStage::RawImageStagePreOpcode3;
+        }
+        Ok(image)
+    }
+
+    /// Parse the opcode lists referenced by the main IFD out of the file data
+    pub fn read_opcode_lists(&mut self, ifd: &Ifd, file: &[u8]) -> crate::Result<()> {
+        for list in 1..=3 {
+            let parsed = match ifd.opcode_list(list) {
+                Some((offset, count)) => {
+                    let start =
+                        usize::try_from(offset).map_err(|_| Error::new(ErrorKind::EndOfStream))?;
+                    let data = start
+                        .checked_add(count as usize)
+                        .and_then(|end| file.get(start..end))
+                        .ok_or(Error::new(ErrorKind::EndOfStream))?;
+                    OpcodeList::parse(data, list)?
+                }
+                None => OpcodeList::new(list),
+            };
+            match list {
+                1 => self.opcode_list1 = parsed,
+                2 => self.opcode_list2 = parsed,
+                _ => self.opcode_list3 = parsed,
+            }
+        }
+        Ok(())
+    }
+
+    pub fn opcode_list1(&self) -> &OpcodeList {
+        &self.opcode_list1
+    }
+
+    pub fn opcode_list2(&self) -> &OpcodeList {
+        &self.opcode_list2
+    }
+
+    pub fn opcode_list3(&self) -> &OpcodeList {
+        &self.opcode_list3
+    }
+
+    pub fn set_opcode_list1(&mut self, list: OpcodeList) {
+        self.opcode_list1 = list;
+    }
+
+    pub fn set_opcode_list2(&mut self, list: OpcodeList) {
+        self.opcode_list2 = list;
+    }
+
+    pub fn set_opcode_list3(&mut self, list: OpcodeList) {
+        self.opcode_list3 = list;
+    }
+
+    pub fn profile_gain_table_map(&self) -> Option<&GainTableMap> {
+        self.profile_gain_table_map.as_ref()
+    }
+
+    pub fn set_profile_gain_table_map(&mut self, map: Option<GainTableMap>) {
+        self.profile_gain_table_map = map;
+    }
+
+    /// Parse the contents of a ProfileGainTableMap tag
+    pub fn read_profile_gain_table_map(&mut self, data: &[u8]) -> crate::Result<()> {
+        self.profile_gain_table_map = Some(GainTableMap::parse(data)?);
+        Ok(())
+    }
+
+    pub fn baseline_exposure(&self) -> f64 {
+        self.baseline_exposure.as_f64()
+    }
+
+    pub fn set_baseline_exposure(&mut self, exposure: SRational) {
+        self.baseline_exposure = exposure;
+    }
+
+    /// Apply the opcode list that belongs to the current stage to the image
+    /// of that stage and move on to the next stage. Opcode list 1 runs on
+    /// the raw stage 1 image, list 2 on the linearized stage 2 image and list
+    /// 3 on the demosaiced stage 3 image. Fails in any other stage.
+    pub fn apply_opcodes(&mut self, host: &Host, image: &mut Image) -> crate::Result<()> {
+        let (list, next) = match self.stage {
+            RawImageStage::RawImageStagePreOpcode1 => {
+                (&self.opcode_list1, RawImageStage::RawImageStagePostOpcode1)
+            }
+            RawImageStage::RawImageStagePostOpcode1 => {
+                (&self.opcode_list2, RawImageStage::RawImageStagePostOpcode2)
+            }
+            RawImageStage::RawImageStagePreOpcode3 => {
+                (&self.opcode_list3, RawImageStage::RawImageStagePostOpcode3)
+            }
+            _ => return Err(Error::new(ErrorKind::InvalidRawImageStage)),
+        };
+        list.apply(host, self, image)?;
+        self.stage = next;
+        Ok(())
+    }
+
+    /// The default crop size as (h, v), falling back to the whole active
+    /// area when the file does not specify one
+    pub fn default_crop_size(&self) -> (f64, f64) {
+        let size_h = match self.default_crop_size_h.as_f64() {
+            size if size > 0.0 => size,
+            _ => (self.active_area.right - self.active_area.left) as f64,
+        };
+        let size_v = match self.default_crop_size_v.as_f64() {
+            size if size > 0.0 => size,
+            _ => (self.active_area.bottom - self.active_area.top) as f64,
+        };
+        (size_h, size_v)
+    }
+
+    pub fn default_scale(&self) -> (f64, f64) {
+        (self.default_scale_h.as_f64(), self.default_scale_v.as_f64())
+    }
+
+    pub fn best_quality_scale(&self) -> f64 {
+        self.best_quality_scale.as_f64()
+    }
+
+    /// The default crop area in stage 3 coordinates, without any rounding
+    pub fn default_crop_area_f64(&self) -> Rect<f64> {
+        let (size_h, size_v) = self.default_crop_size();
+        let scale_h = self.raw_to_full_scale_h.as_f64();
+        let scale_v = self.raw_to_full_scale_v.as_f64();
+        let origin_h = self.default_crop_origin_h.as_f64();
+        let origin_v = self.default_crop_origin_v.as_f64();
+        Rect {
+            top: origin_v * scale_v,
+            left: origin_h * scale_h,
+            bottom: (origin_v + size_v) * scale_v,
+            right: (origin_h + size_h) * scale_h,
+        }
+    }
+
+    /// The default crop area in stage 3 coordinates
+    pub fn default_crop_area(&self) -> Rect<i32> {
+        // The origin and the size are rounded separately, like the SDK, so
+        // the width doesn't depend on the fractional part of the origin
+        let (size_h, size_v) = self.default_crop_size();
+        let scale_h = self.raw_to_full_scale_h.as_f64();
+        let scale_v = self.raw_to_full_scale_v.as_f64();
+        let top = (self.default_crop_origin_v.as_f64() * scale_v).round() as i32;
+        let left = (self.default_crop_origin_h.as_f64() * scale_h).round() as i32;
+        let mut result = Rect {
+            top,
+            left,
+            bottom: top + (size_v * scale_v).round() as i32,
+            right: left + (size_h * scale_h).round() as i32,
+        };
+
+        // Sometimes the simple rounding causes the resulting default crop
+        // area to slide off the scaled image area. So we force this not
+        // to happen.
+        if let Some(image) = &self.stage3_image {
+            let size = image.size();
+            if result.right > size.h {
+                result.left -= result.right - size.h;
+                result.right = size.h;
+            }
+            if result.bottom > size.v {
+                result.top -= result.bottom - size.v;
+                result.bottom = size.v;
+            }
+        }
+        result
+    }
+
+    /// The default user crop, relative to the default crop, with invalid
+    /// values replaced by the full default crop
+    pub fn default_user_crop(&self) -> Rect<f64> {
+        let top = self.default_user_crop_t.as_f64().clamp(0.0, 1.0);
+        let left = self.default_user_crop_l.as_f64().clamp(0.0, 1.0);
+        let bottom = self.default_user_crop_b.as_f64().clamp(0.0, 1.0);
+        let right = self.default_user_crop_r.as_f64().clamp(0.0, 1.0);
+        if top >= bottom || left >= right {
+            return Rect {
+                top: 0.0,
+                left: 0.0,
+                bottom: 1.0,
+                right: 1.0,
+            };
+        }
+        Rect {
+            top,
+            left,
+            bottom,
+            right,
+        }
+    }
+
+    /// The final crop area (default crop followed by the default user crop)
+    /// in stage 3 coordinates, without any rounding
+    pub fn final_crop_area_f64(&self) -> Rect<f64> {
+        let area = self.default_crop_area_f64();
+        let user = self.default_user_crop();
+        let height = area.bottom - area.top;
+        let width = area.right - area.left;
+        Rect {
+            top: area.top + user.top * height,
+            left: area.left + user.left * width,
+            bottom: area.top + user.bottom * height,
+            right: area.left + user.right * width,
+        }
+    }
+
+    /// The final crop area (default crop followed by the default user crop)
+    /// in stage 3 coordinates
+    pub fn final_crop_area(&self) -> Rect<i32> {
+        let area = self.default_crop_area();
+        let user = self.default_user_crop();
+        let height = (area.bottom - area.top) as f64;
+        let width = (area.right - area.left) as f64;
+        Rect {
+            top: area.top + (user.top * height).round() as i32,
+            left: area.left + (user.left * width).round() as i32,
+            bottom: area.top + (user.bottom * height).round() as i32,
+            right: area.left + (user.right * width).round() as i32,
+        }
+    }
+
+    /// Width of the default crop in square pixels
+    pub fn square_width(&self) -> f64 {
+        self.default_crop_size().0
+    }
+
+    /// Height of the default crop in square pixels
+    pub fn square_height(&self) -> f64 {
+        self.default_crop_size().1 * self.default_scale_v.as_f64() / self.default_scale_h.as_f64()
+    }
+
+    pub fn aspect_ratio(&self) -> f64 {
+        self.square_width() / self.square_height()
+    }
+
+    pub fn pixel_aspect_ratio(&self) -> f64 {
+        self.default_scale_h.as_f64() / self.default_scale_v.as_f64()
+    }
+
+    /// Size of the image after the default crop at the given scale, as (rows, cols)
+    pub fn final_size(&self, scale: f64) -> Point<i32> {
+        Point::new(
+            (self.square_height() * scale).round() as i32,
+            (self.square_width() * scale).round() as i32,
+        )
+    }
+
+    /// Size of the image after the default crop and default scale, as (rows, cols)
+    pub fn default_final_size(&self) -> Point<i32> {
+        self.final_size(self.default_scale_h.as_f64())
+    }
+
+    /// Size of the image after the default crop at the best quality scale,
+    /// as (rows, cols)
+    pub fn best_quality_final_size(&self) -> Point<i32> {
+        // Scaled from the rounded default final size, as the SDK does
+        let size = self.default_final_size();
+        let scale = self.best_quality_scale.as_f64();
+        Point::new(
+            (size.v as f64 * scale).round() as i32,
+            (size.h as f64 * scale).round() as i32,
+        )
+    }
+
+    /// The default final size of the original image if this negative is a proxy
+    pub fn original_default_final_size(&self) -> Point<i32> {
+        match self.original_default_final_size {
+            Point { v: 0, h: 0 } => self.default_final_size(),
+            size => size,
+        }
+    }
+
+    /// The best quality final size of the original image if this negative is a proxy
+    pub fn original_best_quality_final_size(&self) -> Point<i32> {
+        match self.original_best_quality_final_size {
+            Point { v: 0, h: 0 } => self.best_quality_final_size(),
+            size => size,
+        }
+    }
+
+    /// Crop a stage 3 image to the final crop area and scale it to the size
+    /// given by the default scale, applying the user crop to that size
+    pub fn apply_default_geometry(&self, image: &mut Image) -> crate::Result<()> {
+        let crop = self.final_crop_area();
+        image.trim(crop)?;
+
+        let user = self.default_user_crop();
+        let final_size = self.default_final_size();
+        let size = Point::new(
+            (final_size.v as f64 * (user.bottom - user.top)).round() as i32,
+            (final_size.h as f64 * (user.right - user.left)).round() as i32,
+        );
+        if size.v <= 0 || size.h <= 0 {
+            return Err(Error::new(ErrorKind::InvalidCrop));
+        }
+        if size != image.size() {
+            *image = image.resample(size)?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_default_final_size() {
+    let mut negative = Negative::new("test");
+    negative.set_active_area(Rect::new(0, 0, 100, 200).unwrap());
+    negative.set_default_crop_origin(URational::from(8.0), URational::from(4.0));
+    negative.set_default_crop_size(URational::from(180.0), URational::from(90.0));
+    negative.set_default_scale(URational::from(1.0), URational::from(2.0));
+
+    assert_eq!(
+        negative.default_crop_area(),
+        Rect::new(4, 8, 94, 188).unwrap()
+    );
+    assert_eq!(negative.default_final_size(), Point::new(180, 180));
+
+    // Rounded from the default final size, not from the unrounded size
+    let one = NonZeroU32::MIN;
+    let half = URational::new(1_u32, NonZeroU32::new(2).unwrap());
+    negative.set_default_crop_size(URational::new(101_u32, one), URational::new(51_u32, one));
+    negative.set_default_scale(half, half);
+    negative.set_best_quality_scale(URational::new(3_u32, one));
+    assert_eq!(negative.default_final_size(), Point::new(26, 51));
+    assert_eq!(negative.best_quality_final_size(), Point::new(78, 153));
+}
+
+#[test]
+fn test_default_crop_area_fractional_origin() {
+    let mut negative = Negative::new("test");
+    negative.set_active_area(Rect::new(0, 0, 100, 100).unwrap());
+    let one = NonZeroU32::MIN;
+    let two = NonZeroU32::new(2).unwrap();
+    negative.set_default_crop_origin(URational::new(1_u32, two), URational::new(1_u32, two));
+    negative.set_default_crop_size(URational::new(21_u32, two), URational::new(21_u32, two));
+    negative.set_default_scale(URational::new(1_u32, one), URational::new(1_u32, one));
+
+    // round(0.5) + round(10.5), not round(0.5 + 10.5)
+    assert_eq!(
+        negative.default_crop_area(),
+        Rect::new(1, 1, 12, 12).unwrap()
+    );
+}
+
+#[test]
+fn test_linear_raw_stage3() {
+    let mut negative = Negative::new("test");
+    let mut info = LinearizationInfo::new(1023.0);
+    info.black_level[0][0] = [64.0; MAX_COLOR_PLANES];
+    negative.set_linearization_info(Some(info));
+    negative.set_color_channels(3);
+    assert!(negative.is_linear_raw());
+
+    let mut stage1 = Image::new(Rect::new(0, 0, 2, 2).unwrap(), 3, PixelType::Short);
+    stage1.data_mut().fill(64.0);
+    stage1.set(1, 1, 2, 1023.0);
+    negative
+        .build_stage3_image(&Host::default(), stage1, DemosaicMode::default())
+        .unwrap();
+    let stage3 = negative.stage3_image().unwrap();
+    assert_eq!(stage3.planes(), 3);
+    assert_eq!(stage3.pixel_type(), PixelType::Float);
+    assert_eq!(stage3.get(1, 1, 2), 1.0);
+    assert_eq!(stage3.get(1, 1, 1), 0.0);
+    assert_eq!(negative.stage, RawImageStage::RawImageStagePostOpcode3);
+
+    // No stage is left with an opcode list to apply
+    let mut stage3 = stage3.clone();
+    assert!(negative
+        .apply_opcodes(&Host::default(), &mut stage3)
+        .is_err());
+    assert!(Negative::new("test")
+        .apply_opcodes(&Host::default(), &mut stage3)
+        .is_err());
+
+    // Masked pixels outside of the active area are dropped before
+    // linearization
+    negative.set_active_area(Rect::new(1, 1, 3, 4).unwrap());
+    let mut stage1 = Image::new(Rect::new(0, 0, 4, 4).unwrap(), 3, PixelType::Short);
+    stage1.data_mut().fill(64.0);
+    stage1.set(1, 1, 0, 1023.0);
+    negative
+        .build_stage3_image(&Host::default(), stage1, DemosaicMode::default())
+        .unwrap();
+    let stage3 = negative.stage3_image().unwrap();
+    assert_eq!(stage3.bounds(), Rect::new(0, 0, 2, 3).unwrap());
+    assert_eq!(stage3.get(0, 0, 0), 1.0);
+    assert_eq!(stage3.get(1, 2, 0), 0.0);
+}
+
+#[test]
+fn test_camera_profiles() {
+    let profile =
+        |name: &str, scale: f64, signature: &str, policy: ProfileEmbedPolicy| CameraProfileInfo {
+            color_planes: 3,
+            calibration_illuminant1: 21,
+            color_matrix1: Matrix::new([[scale, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]]),
+            profile_name: name.into(),
+            profile_calibration_signature: signature.into(),
+            embed_policy: policy as u32,
+            ..Default::default()
+        };
+    // Two extra profiles stored as DCP data after the main one
+    let mut file = vec![0_u8; 16];
+    let mut offsets = Vec::new();
+    for extra in [
+        profile("Standard", 1.1, "", ProfileEmbedPolicy::EmbedNever),
+        profile("Standard", 1.2, "ABC", ProfileEmbedPolicy::EmbedIfUsed),
+    ] {
+        offsets.push(file.len() as u64);
+        file.extend(extra.write_dcp(&[]).unwrap());
+    }
+    let mut negative = Negative::new("test");
+    negative.set_camera_calibration_signature("ABC");
+    let main = profile("Default", 1.0, "", ProfileEmbedPolicy::AllowCopying);
+    negative
+        .read_camera_profiles(main, &offsets, &file)
+        .unwrap();
+    // A duplicate is ignored
+    let duplicate = profile("Copy", 1.0, "", ProfileEmbedPolicy::AllowCopying);
+    negative.add_profile(duplicate, &file).unwrap();
+    // So is a profile without color matrices, for a color negative
+    let gray = CameraProfileInfo {
+        profile_name: "Gray".into(),
+        ..Default::default()
+    };
+    negative.add_profile(gray.clone(), &file).unwrap();
+    assert_eq!(negative.profile_count(), 3);
+
+    let default = CameraProfileID::default();
+    assert_eq!(
+        negative
+            .profile_by_id(&default, false)
+            .unwrap()
+            .profile_name,
+        "Default"
+    );
+    // The profile for the camera calibration wins a match by name
+    let standard = negative.profile_by_name("Standard").unwrap();
+    assert_eq!(standard.profile_calibration_signature, "ABC");
+    let first = negative.profile_id(1).unwrap().clone();
+    assert_eq!(first.name, "Standard");
+    assert_eq!(
+        negative
+            .profile_by_id(&first, false)
+            .unwrap()
+            .color_matrix1
+            .data[0][0],
+        1.1
+    );
+    assert!(negative.profile_by_name("Vivid").is_none());
+    let unknown = CameraProfileID::new("Vivid", None);
+    assert!(negative.profile_by_id(&unknown, true).is_some());
+
+    // A fingerprint without a name still finds its profile, and a name
+    // match comes before a fingerprint match
+    let second = negative.profile_id(2).unwrap().clone();
+    let fingerprint_only = CameraProfileID::new("", second.fingerprint);
+    let found = negative.profile_by_id(&fingerprint_only, false).unwrap();
+    assert_eq!(found.profile_calibration_signature, "ABC");
+    let renamed = CameraProfileID::new("Default", second.fingerprint);
+    let found = negative.profile_by_id(&renamed, false).unwrap();
+    assert_eq!(found.profile_name, "Default");
+
+    let embedded = |used: &CameraProfileID| negative.profiles_to_embed(used).count();
+    assert_eq!(embedded(&default), 1);
+    assert_eq!(embedded(negative.profile_id(2).unwrap()), 2);
+
+    // A monochrome negative keeps it, once
+    let mut negative = Negative::new("test");
+    negative.set_color_channels(1);
+    negative.add_profile(gray.clone(), &file).unwrap();
+    negative.add_profile(gray, &file).unwrap();
+    assert_eq!(negative.profile_count(), 1);
+    assert!(negative.profile_by_name("Gray").is_some());
+}
@@ -0,0 +1,319 @@
+pub mod bad_pixels;
+pub mod gain_map;
+pub mod list;
+pub mod misc;
+pub mod vignette;
+pub mod warp;
+
+use crate::errors::{Error, ErrorKind};
+use crate::host::Host;
+use crate::image::Image;
+use crate::negative::Negative;
+use crate::opcode::bad_pixels::{FixBadPixelsConstant, FixBadPixelsList};
+use crate::opcode::gain_map::GainMapOpcode;
+use crate::opcode::misc::{MapPolynomial, MapTable, PerLineTable, TrimBounds};
+use crate::opcode::vignette::FixVignetteRadial;
+use crate::opcode::warp::{WarpFisheye, WarpRectilinear};
+use crate::rect::Rect;
+use crate::stream::Stream;
+use crate::tag::values::DNG_VERSION_CURRENT;
+
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum OpcodeId {
+    WarpRectilinear = 1,
+    WarpFisheye = 2,
+    FixVignetteRadial = 3,
+    FixBadPixelsConstant = 4,
+    FixBadPixelsList = 5,
+    TrimBounds = 6,
+    MapTable = 7,
+    MapPolynomial = 8,
+    GainMap = 9,
+    DeltaPerRow = 10,
+    DeltaPerColumn = 11,
+    ScalePerRow = 12,
+    ScalePerColumn = 13,
+    WarpRectilinear2 = 14,
+}
+
+impl TryFrom<u32> for OpcodeId {
+    type Error = Error;
+    fn try_from(id: u32) -> crate::Result<Self> {
+        Ok(match id {
+            1 => OpcodeId::WarpRectilinear,
+            2 => OpcodeId::WarpFisheye,
+            3 => OpcodeId::FixVignetteRadial,
+            4 => OpcodeId::FixBadPixelsConstant,
+            5 => OpcodeId::FixBadPixelsList,
+            6 => OpcodeId::TrimBounds,
+            7 => OpcodeId::MapTable,
+            8 => OpcodeId::MapPolynomial,
+            9 => OpcodeId::GainMap,
+            10 => OpcodeId::DeltaPerRow,
+            11 => OpcodeId::DeltaPerColumn,
+            12 => OpcodeId::ScalePerRow,
+            13 => OpcodeId::ScalePerColumn,
+            14 => OpcodeId::WarpRectilinear2,
+            id => return Err(Error::new(ErrorKind::UnsupportedOpcode(id))),
+        })
+    }
+}
+
+/// The header every opcode in an opcode list starts with
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct OpcodeInfo {
+    /// The raw opcode id, kept even when the id is unknown to us
+    pub id: u32,
+    /// The minimum DNG version required to apply this opcode
+    pub min_version: u32,
+    pub flags: u32,
+    /// The opcode list (1, 2 or 3) this opcode was read from
+    pub stage: u32,
+}
+
+impl OpcodeInfo {
+    pub const FLAG_NONE: u32 = 0;
+    /// The opcode may be skipped if the reader does not support it
+    pub const FLAG_OPTIONAL: u32 = 1;
+    /// The opcode may be skipped when rendering a preview
+    pub const FLAG_SKIP_IF_PREVIEW: u32 = 2;
+
+    pub const fn optional(&self) -> bool {
+        self.flags & Self::FLAG_OPTIONAL != 0
+    }
+
+    pub const fn skip_if_preview(&self) -> bool {
+        self.flags & Self::FLAG_SKIP_IF_PREVIEW != 0
+    }
+}
+
+/// The part of the image an opcode operates on: a rectangle, a range of
+/// planes and the pitch between the rows and columns that are processed.
+/// The pitches make it possible to target a single color of a CFA.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct AreaSpec {
+    pub area: Rect<i32>,
+    pub plane: u32,
+    pub planes: u32,
+    pub row_pitch: u32,
+    pub col_pitch: u32,
+}
+
+impl AreaSpec {
+    pub fn parse(id: u32, stream: &mut Stream) -> crate::Result<Self> {
+        let area = Rect {
+            top: stream.get_i32()?,
+            left: stream.get_i32()?,
+            bottom: stream.get_i32()?,
+            right: stream.get_i32()?,
+        };
+        let spec = Self {
+            area,
+            plane: stream.get_u32()?,
+            planes: stream.get_u32()?,
+            row_pitch: stream.get_u32()?,
+            col_pitch: stream.get_u32()?,
+        };
+        if spec.planes == 0
+            || spec.row_pitch == 0
+            || spec.col_pitch == 0
+            || spec.row_pitch > i32::MAX as u32
+            || spec.col_pitch > i32::MAX as u32
+            || area.top > area.bottom
+            || area.left > area.right
+        {
+            return Err(Error::new(ErrorKind::InvalidOpcode(id)));
+        }
+        Ok(spec)
+    }
+
+    /// The rows inside both the area and the image bounds, honouring the
+    /// row pitch (which is relative to the top of the area)
+    pub fn rows(&self, bounds: Rect<i32>) -> impl Iterator<Item = i32> {
+        Self::pitched(
+            self.area.top,
+            self.area.bottom,
+            bounds.top,
+            bounds.bottom,
+            self.row_pitch,
+        )
+    }
+
+    /// The columns inside both the area and the image bounds, honouring the
+    /// column pitch (which is relative to the left of the area)
+    pub fn cols(&self, bounds: Rect<i32>) -> impl Iterator<Item = i32> {
+        Self::pitched(
+            self.area.left,
+            self.area.right,
+            bounds.left,
+            bounds.right,
+            self.col_pitch,
+        )
+    }
+
+    /// The planes inside both the spec and the image
+    pub fn plane_range(&self, image_planes: u32) -> std::ops::Range<u32> {
+        self.plane.min(image_planes)..self.plane.saturating_add(self.planes).min(image_planes)
+    }
+
+    fn pitched(start: i32, end: i32, min: i32, max: i32, pitch: u32) -> impl Iterator<Item = i32> {
+        let pitch = i32::try_from(pitch).unwrap_or(i32::MAX);
+        // The first pitched value at or after min. Overflowing means there is
+        // no such value within the i32 range, so nothing gets processed.
+        let first = if start >= min {
+            Some(start)
+        } else {
+            let distance = i64::from(min) - i64::from(start);
+            let steps = (distance + i64::from(pitch) - 1) / i64::from(pitch);
+            i32::try_from(i64::from(start) + steps * i64::from(pitch)).ok()
+        };
+        let range = match first {
+            Some(first) => first..end.min(max),
+            None => 0..0,
+        };
+        range.step_by(pitch as usize)
+    }
+}
+
+pub trait Opcode {
+    fn info(&self) -> &OpcodeInfo;
+
+    /// Does this opcode leave the image unchanged?
+    fn is_nop(&self) -> bool {
+        false
+    }
+
+    /// Can this opcode be applied to the given negative?
+    fn is_valid_for_negative(&self, _negative: &Negative) -> bool {
+        true
+    }
+
+    /// The bounds of the image after this opcode has been applied
+    fn modified_bounds(&self, image_bounds: Rect<i32>) -> Rect<i32> {
+        image_bounds
+    }
+
+    fn apply(&self, host: &Host, negative: &Negative, image: &mut Image) -> crate::Result<()>;
+
+    /// Decide if the opcode should be applied, or fail if it is required
+    /// but can not be applied
+    fn about_to_apply(&self, host: &Host, negative: &Negative) -> crate::Result<bool> {
+        let info = self.info();
+        if self.is_nop() || (info.skip_if_preview() && host.for_preview()) {
+            return Ok(false);
+        }
+        if !self.is_valid_for_negative(negative) {
+            if info.optional() {
+                return Ok(false);
+            }
+            return Err(Error::new(ErrorKind::InvalidOpcode(info.id)));
+        }
+        Ok(true)
+    }
+}
+
+/// An opcode we can not apply, either because the id is unknown or because
+/// it requires a newer DNG version. Optional ones are skipped, the rest make
+/// the list fail to apply.
+pub struct UnknownOpcode {
+    info: OpcodeInfo,
+    data: Vec<u8>,
+}
+
+impl UnknownOpcode {
+    pub fn new(info: OpcodeInfo, data: &[u8]) -> Self {
+        Self {
+            info,
+            data: data.to_vec(),
+        }
+    }
+
+    /// The raw parameter bytes of the opcode
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl Opcode for UnknownOpcode {
+    fn info(&self) -> &OpcodeInfo {
+        &self.info
+    }
+
+    fn about_to_apply(&self, _host: &Host, _negative: &Negative) -> crate::Result<bool> {
+        if self.info.optional() {
+            return Ok(false);
+        }
+        Err(Error::new(ErrorKind::UnsupportedOpcode(self.info.id)))
+    }
+
+    fn apply(&self, _host: &Host, _negative: &Negative, _image: &mut Image) -> crate::Result<()> {
+        Err(Error::new(ErrorKind::UnsupportedOpcode(self.info.id)))
+    }
+}
+
+/// Build the opcode for the given header from its (big endian) parameters
+pub fn make_opcode(info: OpcodeInfo, params: &[u8]) -> crate::Result<Box<dyn Opcode>> {
+    let id = match OpcodeId::try_from(info.id) {
+        Ok(id) if info.min_version <= DNG_VERSION_CURRENT => id,
+        _ => return Ok(Box::new(UnknownOpcode::new(info, params))),
+    };
+    match parse_opcode(id, info, params) {
+        // Optional opcodes we can not parse are kept as unknown ones, so they
+        // get skipped instead of failing the whole list
+        Err(_) if info.optional() => Ok(Box::new(UnknownOpcode::new(info, params))),
+        result => result,
+    }
+}
+
+fn parse_opcode(id: OpcodeId, info: OpcodeInfo, params: &[u8]) -> crate::Result<Box<dyn Opcode>> {
+    let mut stream = Stream::big_endian(params);
+    let opcode: Box<dyn Opcode> = match id {
+        OpcodeId::WarpRectilinear | OpcodeId::WarpRectilinear2 => {
+            Box::new(WarpRectilinear::parse(info, &mut stream)?)
+        }
+        OpcodeId::FixVignetteRadial => Box::new(FixVignetteRadial::parse(info, &mut stream)?),
+        OpcodeId::GainMap => Box::new(GainMapOpcode::parse(info, &mut stream)?),
+        OpcodeId::FixBadPixelsConstant => Box::new(FixBadPixelsConstant::parse(info, &mut stream)?),
+        OpcodeId::FixBadPixelsList => Box::new(FixBadPixelsList::parse(info, &mut stream)?),
+        OpcodeId::TrimBounds => Box::new(TrimBounds::parse(info, &mut stream)?),
+        OpcodeId::MapTable => Box::new(MapTable::parse(info, &mut stream)?),
+        OpcodeId::MapPolynomial => Box::new(MapPolynomial::parse(info, &mut stream)?),
+        OpcodeId::DeltaPerRow
+        | OpcodeId::DeltaPerColumn
+        | OpcodeId::ScalePerRow
+        | OpcodeId::ScalePerColumn => Box::new(PerLineTable::parse(info, &mut stream)?),
+        OpcodeId::WarpFisheye => Box::new(WarpFisheye::parse(info, &mut stream)?),
+    };
+    if stream.remaining() != 0 {
+        return Err(Error::new(ErrorKind::InvalidOpcode(info.id)));
+    }
+    Ok(opcode)
+}
+
+#[test]
+fn test_pitched_overflow() {
+    let spec = AreaSpec {
+        area: Rect {
+            top: i32::MIN,
+            left: i32::MIN,
+            bottom: i32::MAX,
+            right: i32::MAX,
+        },
+        plane: 0,
+        planes: 1,
+        row_pitch: i32::MAX as u32,
+        col_pitch: 3,
+    };
+    let bounds = Rect::new(0, 0, 4, 8).unwrap();
+    // i32::MIN + 2 * i32::MAX is i32::MAX - 1, outside the bounds
+    assert_eq!(spec.rows(bounds).count(), 0);
+    // i32::MIN is a multiple of 3 away from 1
+    assert_eq!(spec.cols(bounds).collect::<Vec<_>>(), vec![1, 4, 7]);
+
+    let mut params = Vec::new();
+    for value in [0_u32, 0, 4, 4, 0, 1, 0x8000_0000, 1] {
+        params.extend_from_slice(&value.to_be_bytes());
+    }
+    assert!(AreaSpec::parse(0, &mut Stream::big_endian(&params)).is_err());
+}
@@ -0,0 +1,317 @@
+use std::collections::HashSet;
+
+use crate::errors::{Error, ErrorKind};
+use crate::host::Host;
+use crate::image::Image;
+use crate::negative::Negative;
+use crate::opcode::{Opcode, OpcodeInfo};
+use crate::point::Point;
+use crate::rect::{Contains, Rect};
+use crate::stream::Stream;
+
+/// How far (in steps of the CFA pattern) we look for good pixels of the same
+/// color before giving up on a bad pixel
+const MAX_SEARCH_RADIUS: i32 = 4;
+
+/// Is the pixel green in a Bayer pattern with the given phase?
+///
+/// The phase gives the color of the top left pixel of the image:
+/// 0 is red, 1 is green with red to its right, 2 is green with blue to its
+/// right and 3 is blue.
+fn is_green(bayer_phase: u32, bounds: Rect<i32>, row: i32, col: i32) -> bool {
+    let parity = ((row - bounds.top) + (col - bounds.left)).rem_euclid(2);
+    match bayer_phase {
+        1 | 2 => parity == 0,
+        _ => parity == 1,
+    }
+}
+
+/// Replace a bad pixel by the average of the closest good pixels of the same
+/// color. Green pixels can use their diagonal neighbours, red and blue pixels
+/// only the ones two pixels away.
+fn interpolate<F>(image: &Image, bayer_phase: u32, row: i32, col: i32, is_bad: F) -> Option<f32>
+where
+    F: Fn(i32, i32) -> bool,
+{
+    let bounds = image.bounds();
+    let green = is_green(bayer_phase, bounds, row, col);
+    for radius in 1..=MAX_SEARCH_RADIUS {
+        let reach = 2 * radius;
+        let inner = if green { reach - 1 } else { reach };
+        let (mut sum, mut count) = (0.0, 0);
+        for dr in -reach..=reach {
+            for dc in -reach..=reach {
+                let distance = dr.abs().max(dc.abs());
+                let same_color = if green {
+                    (dr + dc) % 2 == 0
+                } else {
+                    dr % 2 == 0 && dc % 2 == 0
+                };
+                if distance < inner || !same_color {
+                    continue;
+                }
+                let (r, c) = (row + dr, col + dc);
+                if bounds.contains(&Point::new(r, c)) && !is_bad(r, c) {
+                    sum += image.get(r, c, 0);
+                    count += 1;
+                }
+            }
+        }
+        if count > 0 {
+            return Some(sum / count as f32);
+        }
+    }
+    None
+}
+
+/// Bad pixel opcodes only make sense for single plane CFA images. Optional
+/// ones are skipped on anything else.
+fn check_cfa(info: &OpcodeInfo, image: &Image) -> crate::Result<bool> {
+    if image.planes() == 1 {
+        return Ok(true);
+    }
+    if info.optional() {
+        return Ok(false);
+    }
+    Err(Error::new(ErrorKind::InvalidOpcode(info.id)))
+}
+
+/// Replace every pixel of a Bayer image with a given value, which the camera
+/// uses to mark bad pixels, by interpolating from its neighbours.
+#[derive(Debug, Clone)]
+pub struct FixBadPixelsConstant {
+    info: OpcodeInfo,
+    pub constant: u32,
+    pub bayer_phase: u32,
+}
+
+impl FixBadPixelsConstant {
+    pub fn parse(info: OpcodeInfo, stream: &mut Stream) -> crate::Result<Self> {
+        let constant = stream.get_u32()?;
+        let bayer_phase = stream.get_u32()?;
+        if bayer_phase > 3 {
+            return Err(Error::new(ErrorKind::InvalidOpcode(info.id)));
+        }
+        Ok(Self {
+            info,
+            constant,
+            bayer_phase,
+        })
+    }
+}
+
+impl Opcode for FixBadPixelsConstant {
+    fn info(&self) -> &OpcodeInfo {
+        &self.info
+    }
+
+    fn apply(&self, _host: &Host, _negative: &Negative, image: &mut Image) -> crate::Result<()> {
+        if !check_cfa(&self.info, image)? {
+            return Ok(());
+        }
+        let source = image.clone();
+        let constant = self.constant as f32;
+        let is_bad = |row, col| source.get(row, col, 0) == constant;
+        let bounds = image.bounds();
+        for row in bounds.top..bounds.bottom {
+            for col in bounds.left..bounds.right {
+                if !is_bad(row, col) {
+                    continue;
+                }
+                if let Some(value) = interpolate(&source, self.bayer_phase, row, col, is_bad) {
+                    image.set_clipped(row, col, 0, value);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Interpolate over a list of known bad pixels and bad rectangles (usually
+/// whole bad rows or columns) of a Bayer image.
+#[derive(Debug, Clone)]
+pub struct FixBadPixelsList {
+    info: OpcodeInfo,
+    pub bayer_phase: u32,
+    pub bad_points: Vec<Point<i32>>,
+    pub bad_rects: Vec<Rect<i32>>,
+}
+
+impl FixBadPixelsList {
+    pub fn parse(info: OpcodeInfo, stream: &mut Stream) -> crate::Result<Self> {
+        let bayer_phase = stream.get_u32()?;
+        let point_count = stream.get_u32()? as usize;
+        let rect_count = stream.get_u32()? as usize;
+        if bayer_phase > 3 || point_count * 8 + rect_count * 16 != stream.remaining() {
+            return Err(Error::new(ErrorKind::InvalidOpcode(info.id)));
+        }
+        let bad_points = (0..point_count)
+            .map(|_| Ok(Point::new(stream.get_i32()?, stream.get_i32()?)))
+            .collect::<crate::Result<Vec<_>>>()?;
+        let bad_rects = (0..rect_count)
+            .map(|_| {
+                let rect = Rect {
+                    top: stream.get_i32()?,
+                    left: stream.get_i32()?,
+                    bottom: stream.get_i32()?,
+                    right: stream.get_i32()?,
+                };
+                if rect.top > rect.bottom || rect.left > rect.right {
+                    return Err(Error::new(ErrorKind::InvalidOpcode(info.id)));
+                }
+                Ok(rect)
+            })
+            .collect::<crate::Result<Vec<_>>>()?;
+        Ok(Self {
+            info,
+            bayer_phase,
+            bad_points,
+            bad_rects,
+        })
+    }
+}
+
+impl Opcode for FixBadPixelsList {
+    fn info(&self) -> &OpcodeInfo {
+        &self.info
+    }
+
+    fn is_nop(&self) -> bool {
+        self.bad_points.is_empty() && self.bad_rects.is_empty()
+    }
+
+    fn apply(&self, _host: &Host, _negative: &Negative, image: &mut Image) -> crate::Result<()> {
+        if !check_cfa(&self.info, image)? {
+            return Ok(());
+        }
+        let source = image.clone();
+        let bounds = image.bounds();
+        let bad_points: HashSet<Point<i32>> = self.bad_points.iter().copied().collect();
+        // Only the part of a rect inside the image matters, and the rects
+        // come from the file so they can be arbitrarily large
+        let bad_rects: Vec<Rect<i32>> = self
+            .bad_rects
+            .iter()
+            .filter_map(|rect| rect.intersection(&bounds))
+            .collect();
+        let is_bad = |row, col| {
+            let point = Point::new(row, col);
+            bad_points.contains(&point) || bad_rects.iter().any(|rect| rect.contains(&point))
+        };
+        let rect_points = bad_rects.iter().flat_map(|rect| {
+            (rect.top..rect.bottom)
+                .flat_map(move |row| (rect.left..rect.right).map(move |col| Point::new(row, col)))
+        });
+        for point in self.bad_points.iter().copied().chain(rect_points) {
+            if !bounds.contains(&point) {
+                continue;
+            }
+            if let Some(value) = interpolate(&source, self.bayer_phase, point.v, point.h, is_bad) {
+                image.set_clipped(point.v, point.h, 0, value);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_fix_bad_pixels_constant() {
+    use crate::image::PixelType;
+
+    let mut image = Image::new(Rect::new(0, 0, 6, 6).unwrap(), 1, PixelType::Short);
+    for row in 0..6 {
+        for col in 0..6 {
+            // Red/blue at 100, green at 200
+            let value = if (row + col) % 2 == 0 { 100.0 } else { 200.0 };
+            image.set(row, col, 0, value);
+        }
+    }
+    image.set(2, 2, 0, 0.0);
+    image.set(2, 3, 0, 0.0);
+    let info = OpcodeInfo {
+        id: 4,
+        min_version: 0,
+        flags: 0,
+        stage: 1,
+    };
+    let opcode = FixBadPixelsConstant {
+        info,
+        constant: 0,
+        bayer_phase: 0,
+    };
+    opcode
+        .apply(&Host::default(), &Negative::new("test"), &mut image)
+        .unwrap();
+    assert_eq!(image.get(2, 2, 0), 100.0);
+    assert_eq!(image.get(2, 3, 0), 200.0);
+}
+
+#[test]
+fn test_fix_bad_pixels_list() {
+    use crate::image::PixelType;
+    use crate::opcode::OpcodeId;
+
+    // One bad point and a bad column, with phase 1 (green at the top left)
+    let mut data = Vec::new();
+    for value in [1_i32, 1, 1, 2, 2, 0, 4, 8, 5] {
+        data.extend_from_slice(&value.to_be_bytes());
+    }
+    let info = OpcodeInfo {
+        id: OpcodeId::FixBadPixelsList as u32,
+        min_version: 0,
+        flags: 0,
+        stage: 1,
+    };
+    let opcode = FixBadPixelsList::parse(info, &mut Stream::big_endian(&data)).unwrap();
+    assert!(!opcode.is_nop());
+
+    let mut image = Image::new(Rect::new(0, 0, 8, 8).unwrap(), 1, PixelType::Short);
+    for row in 0..8 {
+        for col in 0..8 {
+            // Green at 200, red/blue at 100, except for the bad pixels
+            let value = if (row + col) % 2 == 0 { 200.0 } else { 100.0 };
+            image.set(row, col, 0, value);
+        }
+    }
+    image.set(2, 2, 0, 0.0);
+    for row in 0..8 {
+        image.set(row, 4, 0, 0.0);
+    }
+    opcode
+        .apply(&Host::default(), &Negative::new("test"), &mut image)
+        .unwrap();
+    assert_eq!(image.get(2, 2, 0), 200.0);
+    for row in 0..8 {
+        let expected = if row % 2 == 0 { 200.0 } else { 100.0 };
+        assert_eq!(image.get(row, 4, 0), expected);
+    }
+    // Pixels outside of the list are left alone
+    assert_eq!(image.get(3, 3, 0), 200.0);
+    assert_eq!(image.get(0, 1, 0), 100.0);
+
+    data.truncate(data.len() - 4);
+    assert!(FixBadPixelsList::parse(info, &mut Stream::big_endian(&data)).is_err());
+
+    // A rect covering far more than the image is clipped to it instead of
+    // being walked point by point
+    let opcode = FixBadPixelsList {
+        info,
+        bayer_phase: 1,
+        bad_points: Vec::new(),
+        bad_rects: vec![Rect::new(6, 0, i32::MAX, i32::MAX).unwrap()],
+    };
+    let mut image = Image::new(Rect::new(0, 0, 8, 8).unwrap(), 1, PixelType::Short);
+    for row in 0..8 {
+        for col in 0..8 {
+            let value = if (row + col) % 2 == 0 { 200.0 } else { 100.0 };
+            image.set(row, col, 0, if row < 6 { value } else { 0.0 });
+        }
+    }
+    opcode
+        .apply(&Host::default(), &Negative::new("test"), &mut image)
+        .unwrap();
+    for col in 0..8 {
+        let expected = if col % 2 == 0 { 200.0 } else { 100.0 };
+        assert_eq!(image.get(6, col, 0), expected);
+    }
+}
@@ -0,0 +1,184 @@
+use crate::errors::{Error, ErrorKind};
+use crate::host::Host;
+use crate::image::Image;
+use crate::negative::Negative;
+use crate::opcode::{AreaSpec, Opcode, OpcodeInfo};
+use crate::point::Point;
+use crate::rect::Rect;
+use crate::stream::Stream;
+
+/// A grid of gains covering the image, with one or more planes. Positions
+/// are relative to the image bounds, (0, 0) being the top left corner and
+/// (1, 1) the bottom right corner.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GainMap {
+    /// Number of grid points as (v, h)
+    pub points: Point<u32>,
+    /// Distance between grid points as (v, h)
+    pub spacing: Point<f64>,
+    /// Position of the first grid point as (v, h)
+    pub origin: Point<f64>,
+    pub planes: u32,
+    /// Gains stored by row, then column, then plane
+    pub gains: Vec<f32>,
+}
+
+impl GainMap {
+    pub fn parse(id: u32, stream: &mut Stream) -> crate::Result<Self> {
+        let points = Point::new(stream.get_u32()?, stream.get_u32()?);
+        let spacing = Point::new(stream.get_f64()?, stream.get_f64()?);
+        let origin = Point::new(stream.get_f64()?, stream.get_f64()?);
+        let planes = stream.get_u32()?;
+        let count = (points.v as usize)
+            .checked_mul(points.h as usize)
+            .and_then(|count| count.checked_mul(planes as usize))
+            .filter(|count| {
+                *count > 0
+                    && count
+                        .checked_mul(4)
+                        .is_some_and(|bytes| bytes <= stream.remaining())
+            })
+            .ok_or(Error::new(ErrorKind::InvalidOpcode(id)))?;
+        if (points.v > 1 && spacing.v <= 0.0) || (points.h > 1 && spacing.h <= 0.0) {
+            return Err(Error::new(ErrorKind::InvalidOpcode(id)));
+        }
+        let gains = (0..count)
+            .map(|_| stream.get_f32())
+            .collect::<crate::Result<Vec<_>>>()?;
+        Ok(Self {
+            points,
+            spacing,
+            origin,
+            planes,
+            gains,
+        })
+    }
+
+    #[inline]
+    fn entry(&self, row: u32, col: u32, plane: u32) -> f32 {
+        self.gains[((row * self.points.h + col) * self.planes + plane) as usize]
+    }
+
+    /// Find the grid cell and the fractional position inside it along one axis
+    #[inline]
+    fn locate(position: f64, origin: f64, spacing: f64, points: u32) -> (u32, u32, f32) {
+        if points == 1 {
+            return (0, 0, 0.0);
+        }
+        let index = ((position - origin) / spacing).clamp(0.0, (points - 1) as f64);
+        let first = (index.floor() as u32).min(points - 2);
+        (first, first + 1, (index - first as f64) as f32)
+    }
+
+    /// Bilinearly interpolate the gain at a position relative to the bounds
+    pub fn interpolate(&self, position: Point<f64>, plane: u32) -> f32 {
+        let plane = plane.min(self.planes - 1);
+        let (r0, r1, fv) = Self::locate(position.v, self.origin.v, self.spacing.v, self.points.v);
+        let (c0, c1, fh) = Self::locate(position.h, self.origin.h, self.spacing.h, self.points.h);
+        let top = self.entry(r0, c0, plane) * (1.0 - fh) + self.entry(r0, c1, plane) * fh;
+        let bottom = self.entry(r1, c0, plane) * (1.0 - fh) + self.entry(r1, c1, plane) * fh;
+        top * (1.0 - fv) + bottom * fv
+    }
+
+    /// Bilinearly interpolate the gain for a pixel of an image with the
+    /// given bounds
+    pub fn pixel_gain(&self, bounds: Rect<i32>, row: i32, col: i32, plane: u32) -> f32 {
+        let position = Point::new(
+            (row - bounds.top) as f64 + 0.5,
+            (col - bounds.left) as f64 + 0.5,
+        );
+        let height = (bounds.bottom - bounds.top).max(1) as f64;
+        let width = (bounds.right - bounds.left).max(1) as f64;
+        self.interpolate(Point::new(position.v / height, position.h / width), plane)
+    }
+}
+
+/// Multiply an area of the image by a gain map. This is typically used for
+/// lens shading correction, with one map per CFA color selected with the
+/// row and column pitches of the area.
+#[derive(Debug, Clone)]
+pub struct GainMapOpcode {
+    info: OpcodeInfo,
+    pub area_spec: AreaSpec,
+    pub gain_map: GainMap,
+}
+
+impl GainMapOpcode {
+    pub fn parse(info: OpcodeInfo, stream: &mut Stream) -> crate::Result<Self> {
+        Ok(Self {
+            info,
+            area_spec: AreaSpec::parse(info.id, stream)?,
+            gain_map: GainMap::parse(info.id, stream)?,
+        })
+    }
+}
+
+impl Opcode for GainMapOpcode {
+    fn info(&self) -> &OpcodeInfo {
+        &self.info
+    }
+
+    fn apply(&self, _host: &Host, _negative: &Negative, image: &mut Image) -> crate::Result<()> {
+        let bounds = image.bounds();
+        for plane in self.area_spec.plane_range(image.planes()) {
+            let map_plane = plane - self.area_spec.plane;
+            for row in self.area_spec.rows(bounds) {
+                for col in self.area_spec.cols(bounds) {
+                    let gain = self.gain_map.pixel_gain(bounds, row, col, map_plane);
+                    let value = image.get(row, col, plane) * gain;
+                    image.set_clipped(row, col, plane, value);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_gain_map_pitch() {
+    use crate::image::PixelType;
+    use crate::opcode::OpcodeId;
+
+    // Double the top left sample of every 2x2 CFA block with a 1x1 map
+    let mut data = Vec::new();
+    for value in [0_u32, 0, 4, 4, 0, 1, 2, 2, 1, 1] {
+        data.extend_from_slice(&value.to_be_bytes());
+    }
+    for value in [1.0_f64, 1.0, 0.0, 0.0] {
+        data.extend_from_slice(&value.to_be_bytes());
+    }
+    data.extend_from_slice(&1_u32.to_be_bytes());
+    data.extend_from_slice(&2.0_f32.to_be_bytes());
+
+    let info = OpcodeInfo {
+        id: OpcodeId::GainMap as u32,
+        min_version: 0,
+        flags: 0,
+        stage: 2,
+    };
+    let opcode = GainMapOpcode::parse(info, &mut Stream::big_endian(&data)).unwrap();
+    let mut image = Image::new(Rect::new(0, 0, 4, 4).unwrap(), 1, PixelType::Short);
+    image.data_mut().fill(100.0);
+    opcode
+        .apply(&Host::default(), &Negative::new("test"), &mut image)
+        .unwrap();
+    assert_eq!(image.get(0, 0, 0), 200.0);
+    assert_eq!(image.get(2, 2, 0), 200.0);
+    assert_eq!(image.get(0, 1, 0), 100.0);
+    assert_eq!(image.get(1, 0, 0), 100.0);
+}
+
+#[test]
+fn test_gain_map_overflow() {
+    // A grid whose byte count overflows is rejected instead of panicking
+    let mut data = Vec::new();
+    for value in [u32::MAX, 1 << 30] {
+        data.extend_from_slice(&value.to_be_bytes());
+    }
+    for value in [1.0_f64, 1.0, 0.0, 0.0] {
+        data.extend_from_slice(&value.to_be_bytes());
+    }
+    data.extend_from_slice(&1_u32.to_be_bytes());
+    data.extend_from_slice(&1.0_f32.to_be_bytes());
+    assert!(GainMap::parse(0, &mut Stream::big_endian(&data)).is_err());
+}
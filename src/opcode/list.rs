@@ -0,0 +1,145 @@
+use crate::errors::{Error, ErrorKind};
+use crate::host::Host;
+use crate::image::Image;
+use crate::negative::Negative;
+use crate::opcode::{make_opcode, Opcode, OpcodeInfo};
+use crate::rect::Rect;
+use crate::stream::Stream;
+
+/// An ordered list of opcodes, as stored in the OpcodeList1, OpcodeList2
+/// and OpcodeList3 tags
+#[derive(Default)]
+pub struct OpcodeList {
+    opcodes: Vec<Box<dyn Opcode>>,
+    /// The opcode list (1, 2 or 3) this is
+    stage: u32,
+}
+
+impl OpcodeList {
+    pub fn new(stage: u32) -> Self {
+        Self {
+            opcodes: Vec::new(),
+            stage,
+        }
+    }
+
+    /// Parse an opcode list. The data is always big endian, whatever the
+    /// byte order of the file.
+    pub fn parse(data: &[u8], stage: u32) -> crate::Result<Self> {
+        let mut list = Self::new(stage);
+        if data.is_empty() {
+            return Ok(list);
+        }
+        let mut stream = Stream::big_endian(data);
+        let count = stream.get_u32()?;
+        for _ in 0..count {
+            let id = stream.get_u32()?;
+            let min_version = stream.get_u32()?;
+            let flags = stream.get_u32()?;
+            let size = stream.get_u32()? as usize;
+            let params = stream.get_bytes(size)?;
+            let info = OpcodeInfo {
+                id,
+                min_version,
+                flags,
+                stage,
+            };
+            list.opcodes.push(make_opcode(info, params)?);
+        }
+        if stream.remaining() != 0 {
+            return Err(Error::new(ErrorKind::InvalidOpcodeList));
+        }
+        Ok(list)
+    }
+
+    pub fn stage(&self) -> u32 {
+        self.stage
+    }
+
+    pub fn len(&self) -> usize {
+        self.opcodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.opcodes.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &dyn Opcode> {
+        self.opcodes.iter().map(|opcode| opcode.as_ref())
+    }
+
+    pub fn push(&mut self, opcode: Box<dyn Opcode>) {
+        self.opcodes.push(opcode);
+    }
+
+    /// The minimum DNG version needed to apply this list
+    pub fn min_version(&self, include_optional: bool) -> u32 {
+        self.iter()
+            .filter(|opcode| include_optional || !opcode.info().optional())
+            .map(|opcode| opcode.info().min_version)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// The bounds of the image after every opcode in the list has been applied
+    pub fn modified_bounds(&self, image_bounds: Rect<i32>) -> Rect<i32> {
+        self.iter().fold(image_bounds, |bounds, opcode| {
+            opcode.modified_bounds(bounds)
+        })
+    }
+
+    /// Apply the opcodes in order, skipping the ones that are optional or
+    /// preview-only where allowed
+    pub fn apply(&self, host: &Host, negative: &Negative, image: &mut Image) -> crate::Result<()> {
+        for opcode in self.iter() {
+            if opcode.about_to_apply(host, negative)? {
+                opcode.apply(host, negative, image)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_unknown_opcodes() {
+    // One optional and one required opcode with an id we don't know
+    let mut data = 2_u32.to_be_bytes().to_vec();
+    for flags in [OpcodeInfo::FLAG_OPTIONAL, OpcodeInfo::FLAG_NONE] {
+        for value in [1000_u32, 0x01030000, flags, 4, 0] {
+            data.extend_from_slice(&value.to_be_bytes());
+        }
+    }
+    let list = OpcodeList::parse(&data, 1).unwrap();
+    assert_eq!(list.len(), 2);
+
+    let host = Host::default();
+    let negative = Negative::new("test");
+    let mut image = Image::new(
+        Rect::new(0, 0, 2, 2).unwrap(),
+        1,
+        crate::image::PixelType::Short,
+    );
+    let optional = list.iter().next().unwrap();
+    assert!(!optional.about_to_apply(&host, &negative).unwrap());
+    assert!(list.apply(&host, &negative, &mut image).is_err());
+
+    data.push(0);
+    assert!(OpcodeList::parse(&data, 1).is_err());
+}
+
+#[test]
+fn test_malformed_optional_opcode() {
+    // A MapTable without any parameters
+    let mut data = 1_u32.to_be_bytes().to_vec();
+    for value in [7_u32, 0x01030000, OpcodeInfo::FLAG_OPTIONAL, 0] {
+        data.extend_from_slice(&value.to_be_bytes());
+    }
+    let list = OpcodeList::parse(&data, 1).unwrap();
+    let host = Host::default();
+    let negative = Negative::new("test");
+    let opcode = list.iter().next().unwrap();
+    assert!(!opcode.about_to_apply(&host, &negative).unwrap());
+
+    data[12..16].copy_from_slice(&OpcodeInfo::FLAG_NONE.to_be_bytes());
+    assert!(OpcodeList::parse(&data, 1).is_err());
+}
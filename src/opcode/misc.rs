@@ -0,0 +1,530 @@
+use crate::errors::{Error, ErrorKind};
+use crate::host::Host;
+use crate::image::{Image, ImageTrait};
+use crate::negative::Negative;
+use crate::opcode::{AreaSpec, Opcode, OpcodeId, OpcodeInfo};
+use crate::rect::Rect;
+use crate::stream::Stream;
+
+/// Crop the image to a rectangle
+#[derive(Debug, Clone)]
+pub struct TrimBounds {
+    info: OpcodeInfo,
+    pub bounds: Rect<i32>,
+}
+
+impl TrimBounds {
+    pub fn parse(info: OpcodeInfo, stream: &mut Stream) -> crate::Result<Self> {
+        let bounds = Rect {
+            top: stream.get_i32()?,
+            left: stream.get_i32()?,
+            bottom: stream.get_i32()?,
+            right: stream.get_i32()?,
+        };
+        if bounds.top >= bounds.bottom || bounds.left >= bounds.right {
+            return Err(Error::new(ErrorKind::InvalidOpcode(info.id)));
+        }
+        Ok(Self { info, bounds })
+    }
+}
+
+impl Opcode for TrimBounds {
+    fn info(&self) -> &OpcodeInfo {
+        &self.info
+    }
+
+    fn modified_bounds(&self, image_bounds: Rect<i32>) -> Rect<i32> {
+        Rect {
+            top: image_bounds.top.max(self.bounds.top),
+            left: image_bounds.left.max(self.bounds.left),
+            bottom: image_bounds.bottom.min(self.bounds.bottom),
+            right: image_bounds.right.min(self.bounds.right),
+        }
+    }
+
+    fn apply(&self, _host: &Host, _negative: &Negative, image: &mut Image) -> crate::Result<()> {
+        let bounds = self.modified_bounds(image.bounds());
+        if bounds != image.bounds() {
+            image.trim(bounds)?;
+        }
+        Ok(())
+    }
+}
+
+/// Map the samples of an area through a lookup table. Values past the end of
+/// the table map to its last entry.
+#[derive(Debug, Clone)]
+pub struct MapTable {
+    info: OpcodeInfo,
+    pub area_spec: AreaSpec,
+    pub table: Vec<u16>,
+}
+
+impl MapTable {
+    pub fn parse(info: OpcodeInfo, stream: &mut Stream) -> crate::Result<Self> {
+        let area_spec = AreaSpec::parse(info.id, stream)?;
+        let count = stream.get_u32()? as usize;
+        if count == 0 || count > 0x10000 {
+            return Err(Error::new(ErrorKind::InvalidOpcode(info.id)));
+        }
+        let table = (0..count)
+            .map(|_| stream.get_u16())
+            .collect::<crate::Result<Vec<_>>>()?;
+        Ok(Self {
+            info,
+            area_spec,
+            table,
+        })
+    }
+}
+
+impl Opcode for MapTable {
+    fn info(&self) -> &OpcodeInfo {
+        &self.info
+    }
+
+    fn is_nop(&self) -> bool {
+        self.table.len() == 0x10000
+            && self
+                .table
+                .iter()
+                .enumerate()
+                .all(|(index, value)| *value as usize == index)
+    }
+
+    fn apply(&self, _host: &Host, _negative: &Negative, image: &mut Image) -> crate::Result<()> {
+        // The table is 16 bit, so other pixel types are scaled to and from it
+        let scale = u16::MAX as f32 / image.pixel_type().max_value();
+        let last = self.table.len() - 1;
+        let bounds = image.bounds();
+        for plane in self.area_spec.plane_range(image.planes()) {
+            for row in self.area_spec.rows(bounds) {
+                for col in self.area_spec.cols(bounds) {
+                    let index = (image.get(row, col, plane) * scale).round().max(0.0) as usize;
+                    let value = self.table[index.min(last)] as f32 / scale;
+                    image.set_clipped(row, col, plane, value);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Map the samples of an area through a polynomial, evaluated on values
+/// normalized to 0.0..=1.0
+#[derive(Debug, Clone)]
+pub struct MapPolynomial {
+    info: OpcodeInfo,
+    pub area_spec: AreaSpec,
+    /// Coefficients from the constant term up
+    pub coefficients: Vec<f64>,
+}
+
+impl MapPolynomial {
+    /// The highest polynomial degree the DNG specification allows
+    pub const MAX_DEGREE: usize = 8;
+
+    pub fn parse(info: OpcodeInfo, stream: &mut Stream) -> crate::Result<Self> {
+        let area_spec = AreaSpec::parse(info.id, stream)?;
+        let degree = stream.get_u32()? as usize;
+        if degree > Self::MAX_DEGREE {
+            return Err(Error::new(ErrorKind::InvalidOpcode(info.id)));
+        }
+        let coefficients = (0..=degree)
+            .map(|_| stream.get_f64())
+            .collect::<crate::Result<Vec<_>>>()?;
+        Ok(Self {
+            info,
+            area_spec,
+            coefficients,
+        })
+    }
+
+    pub fn evaluate(&self, x: f64) -> f64 {
+        self.coefficients
+            .iter()
+            .rev()
+            .fold(0.0, |acc, coefficient| acc * x + coefficient)
+    }
+}
+
+impl Opcode for MapPolynomial {
+    fn info(&self) -> &OpcodeInfo {
+        &self.info
+    }
+
+    fn is_nop(&self) -> bool {
+        self.coefficients
+            .iter()
+            .enumerate()
+            .all(|(i, k)| *k == if i == 1 { 1.0 } else { 0.0 })
+    }
+
+    fn apply(&self, _host: &Host, _negative: &Negative, image: &mut Image) -> crate::Result<()> {
+        let scale = image.pixel_type().max_value() as f64;
+        let bounds = image.bounds();
+        for plane in self.area_spec.plane_range(image.planes()) {
+            for row in self.area_spec.rows(bounds) {
+                for col in self.area_spec.cols(bounds) {
+                    let x = image.get(row, col, plane) as f64 / scale;
+                    image.set_clipped(row, col, plane, (self.evaluate(x) * scale) as f32);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Offset or scale every row (or column) of an area by its own value. This is
+/// how cameras describe fixed pattern banding.
+///
+/// Used by the DeltaPerRow, DeltaPerColumn, ScalePerRow and ScalePerColumn
+/// opcodes. Deltas are in units of normalized (0.0..=1.0) sample values.
+#[derive(Debug, Clone)]
+pub struct PerLineTable {
+    info: OpcodeInfo,
+    pub area_spec: AreaSpec,
+    /// One value per processed row (or column), taking the pitch into account
+    pub values: Vec<f32>,
+}
+
+impl PerLineTable {
+    pub fn parse(info: OpcodeInfo, stream: &mut Stream) -> crate::Result<Self> {
+        let area_spec = AreaSpec::parse(info.id, stream)?;
+        let (length, pitch) = if Self::is_per_row(info.id) {
+            (
+                area_spec.area.bottom - area_spec.area.top,
+                area_spec.row_pitch,
+            )
+        } else {
+            (
+                area_spec.area.right - area_spec.area.left,
+                area_spec.col_pitch,
+            )
+        };
+        let count = stream.get_u32()? as usize;
+        if count != (length as u32).div_ceil(pitch) as usize {
+            return Err(Error::new(ErrorKind::InvalidOpcode(info.id)));
+        }
+        let values = (0..count)
+            .map(|_| stream.get_f32())
+            .collect::<crate::Result<Vec<_>>>()?;
+        Ok(Self {
+            info,
+            area_spec,
+            values,
+        })
+    }
+
+    fn is_per_row(id: u32) -> bool {
+        id == OpcodeId::DeltaPerRow as u32 || id == OpcodeId::ScalePerRow as u32
+    }
+
+    fn is_scale(id: u32) -> bool {
+        id == OpcodeId::ScalePerRow as u32 || id == OpcodeId::ScalePerColumn as u32
+    }
+}
+
+impl Opcode for PerLineTable {
+    fn info(&self) -> &OpcodeInfo {
+        &self.info
+    }
+
+    fn is_nop(&self) -> bool {
+        let identity = if Self::is_scale(self.info.id) {
+            1.0
+        } else {
+            0.0
+        };
+        self.values.iter().all(|value| *value == identity)
+    }
+
+    fn apply(&self, _host: &Host, _negative: &Negative, image: &mut Image) -> crate::Result<()> {
+        let per_row = Self::is_per_row(self.info.id);
+        let scale = Self::is_scale(self.info.id);
+        let white = image.pixel_type().max_value();
+        let area = self.area_spec.area;
+        let bounds = image.bounds();
+        for plane in self.area_spec.plane_range(image.planes()) {
+            for row in self.area_spec.rows(bounds) {
+                for col in self.area_spec.cols(bounds) {
+                    let index = if per_row {
+                        (row - area.top) as u32 / self.area_spec.row_pitch
+                    } else {
+                        (col - area.left) as u32 / self.area_spec.col_pitch
+                    };
+                    let value = self.values[index as usize];
+                    let sample = image.get(row, col, plane);
+                    let sample = if scale {
+                        sample * value
+                    } else {
+                        sample + value * white
+                    };
+                    image.set_clipped(row, col, plane, sample);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_delta_per_column() {
+    use crate::image::PixelType;
+
+    let info = OpcodeInfo {
+        id: OpcodeId::DeltaPerColumn as u32,
+        min_version: 0,
+        flags: 0,
+        stage: 3,
+    };
+    let area_spec = AreaSpec {
+        area: Rect::new(0, 0, 2, 4).unwrap(),
+        plane: 0,
+        planes: 1,
+        row_pitch: 1,
+        col_pitch: 2,
+    };
+    let opcode = PerLineTable {
+        info,
+        area_spec,
+        values: vec![0.25, -0.25],
+    };
+    let mut image = Image::new(Rect::new(0, 0, 2, 4).unwrap(), 1, PixelType::Float);
+    image.data_mut().fill(0.5);
+    opcode
+        .apply(&Host::default(), &Negative::new("test"), &mut image)
+        .unwrap();
+    assert_eq!(image.get(1, 0, 0), 0.75);
+    assert_eq!(image.get(1, 1, 0), 0.5);
+    assert_eq!(image.get(1, 2, 0), 0.25);
+}
+
+#[cfg(test)]
+fn per_line_params(area_spec: [u32; 8], values: &[f32]) -> Vec<u8> {
+    let mut data = Vec::new();
+    for value in area_spec.into_iter().chain([values.len() as u32]) {
+        data.extend_from_slice(&value.to_be_bytes());
+    }
+    for value in values {
+        data.extend_from_slice(&value.to_be_bytes());
+    }
+    data
+}
+
+#[test]
+fn test_delta_per_row() {
+    use crate::image::PixelType;
+
+    let info = OpcodeInfo {
+        id: OpcodeId::DeltaPerRow as u32,
+        min_version: 0,
+        flags: 0,
+        stage: 2,
+    };
+    // Rows 1 and 3 of the second plane, deltas are relative to white
+    let data = per_line_params([1, 0, 5, 2, 1, 1, 2, 1], &[0.25, -1.0]);
+    let opcode = PerLineTable::parse(info, &mut Stream::big_endian(&data)).unwrap();
+    let mut image = Image::new(Rect::new(0, 0, 5, 2).unwrap(), 2, PixelType::Short);
+    image.data_mut().fill(40000.0);
+    opcode
+        .apply(&Host::default(), &Negative::new("test"), &mut image)
+        .unwrap();
+    for col in 0..2 {
+        assert_eq!(image.get(1, col, 1), 56384.0);
+        assert_eq!(image.get(2, col, 1), 40000.0);
+        assert_eq!(image.get(3, col, 1), 0.0);
+        assert_eq!(image.get(1, col, 0), 40000.0);
+    }
+    assert_eq!(image.get(0, 0, 1), 40000.0);
+
+    // One value per pitched row
+    let data = per_line_params([1, 0, 5, 2, 1, 1, 2, 1], &[0.25, -1.0, 0.0]);
+    assert!(PerLineTable::parse(info, &mut Stream::big_endian(&data)).is_err());
+}
+
+#[test]
+fn test_scale_per_row() {
+    use crate::image::PixelType;
+
+    let info = OpcodeInfo {
+        id: OpcodeId::ScalePerRow as u32,
+        min_version: 0,
+        flags: 0,
+        stage: 2,
+    };
+    // Rows 0 and 2 of every plane, every other column
+    let data = per_line_params([0, 0, 4, 4, 0, 2, 2, 2], &[2.0, 0.5]);
+    let opcode = PerLineTable::parse(info, &mut Stream::big_endian(&data)).unwrap();
+    assert!(!opcode.is_nop());
+    let mut image = Image::new(Rect::new(0, 0, 4, 4).unwrap(), 2, PixelType::Float);
+    image.data_mut().fill(0.25);
+    opcode
+        .apply(&Host::default(), &Negative::new("test"), &mut image)
+        .unwrap();
+    for plane in 0..2 {
+        assert_eq!(image.get(0, 0, plane), 0.5);
+        assert_eq!(image.get(0, 2, plane), 0.5);
+        assert_eq!(image.get(0, 1, plane), 0.25);
+        assert_eq!(image.get(1, 0, plane), 0.25);
+        assert_eq!(image.get(2, 0, plane), 0.125);
+        assert_eq!(image.get(3, 0, plane), 0.25);
+    }
+
+    let data = per_line_params([0, 0, 4, 4, 0, 2, 2, 2], &[1.0, 1.0]);
+    let opcode = PerLineTable::parse(info, &mut Stream::big_endian(&data)).unwrap();
+    assert!(opcode.is_nop());
+}
+
+#[test]
+fn test_scale_per_column() {
+    use crate::image::PixelType;
+
+    let info = OpcodeInfo {
+        id: OpcodeId::ScalePerColumn as u32,
+        min_version: 0,
+        flags: 0,
+        stage: 2,
+    };
+    // Columns 1 and 3 of the first two planes, the pitch starts at the left
+    // of the area
+    let data = per_line_params([0, 1, 2, 5, 0, 2, 1, 2], &[3.0, 0.5]);
+    let opcode = PerLineTable::parse(info, &mut Stream::big_endian(&data)).unwrap();
+    let mut image = Image::new(Rect::new(0, 0, 2, 5).unwrap(), 3, PixelType::Float);
+    image.data_mut().fill(0.25);
+    opcode
+        .apply(&Host::default(), &Negative::new("test"), &mut image)
+        .unwrap();
+    for row in 0..2 {
+        for plane in 0..2 {
+            assert_eq!(image.get(row, 0, plane), 0.25);
+            assert_eq!(image.get(row, 1, plane), 0.75);
+            assert_eq!(image.get(row, 2, plane), 0.25);
+            assert_eq!(image.get(row, 3, plane), 0.125);
+            assert_eq!(image.get(row, 4, plane), 0.25);
+        }
+        // The third plane is outside the area spec
+        assert_eq!(image.get(row, 1, 2), 0.25);
+    }
+}
+
+#[test]
+fn test_trim_bounds() {
+    use crate::image::PixelType;
+
+    let info = OpcodeInfo {
+        id: OpcodeId::TrimBounds as u32,
+        min_version: 0,
+        flags: 0,
+        stage: 1,
+    };
+    let mut data = Vec::new();
+    for value in [1_i32, 2, 3, 10] {
+        data.extend_from_slice(&value.to_be_bytes());
+    }
+    let opcode = TrimBounds::parse(info, &mut Stream::big_endian(&data)).unwrap();
+
+    // The trim is limited to the image bounds
+    let mut image = Image::new(Rect::new(0, 0, 4, 6).unwrap(), 1, PixelType::Short);
+    image.set(1, 2, 0, 7.0);
+    assert_eq!(
+        opcode.modified_bounds(image.bounds()),
+        Rect::new(1, 2, 3, 6).unwrap()
+    );
+    opcode
+        .apply(&Host::default(), &Negative::new("test"), &mut image)
+        .unwrap();
+    assert_eq!(image.bounds(), Rect::new(1, 2, 3, 6).unwrap());
+    assert_eq!(image.get(1, 2, 0), 7.0);
+
+    // Empty rectangles are rejected
+    data[8..12].copy_from_slice(&1_i32.to_be_bytes());
+    assert!(TrimBounds::parse(info, &mut Stream::big_endian(&data)).is_err());
+}
+
+#[test]
+fn test_map_table() {
+    use crate::image::PixelType;
+
+    let info = OpcodeInfo {
+        id: OpcodeId::MapTable as u32,
+        min_version: 0,
+        flags: 0,
+        stage: 2,
+    };
+    let mut data = Vec::new();
+    for value in [0_u32, 0, 1, 4, 0, 1, 1, 1, 3] {
+        data.extend_from_slice(&value.to_be_bytes());
+    }
+    for value in [10_u16, 20, 30] {
+        data.extend_from_slice(&value.to_be_bytes());
+    }
+    let opcode = MapTable::parse(info, &mut Stream::big_endian(&data)).unwrap();
+    assert!(!opcode.is_nop());
+
+    let mut image = Image::new(Rect::new(0, 0, 1, 4).unwrap(), 1, PixelType::Short);
+    for (col, value) in [0.0, 1.0, 2.0, 1000.0].into_iter().enumerate() {
+        image.set(0, col as i32, 0, value);
+    }
+    opcode
+        .apply(&Host::default(), &Negative::new("test"), &mut image)
+        .unwrap();
+    assert_eq!(image.get(0, 0, 0), 10.0);
+    assert_eq!(image.get(0, 1, 0), 20.0);
+    assert_eq!(image.get(0, 2, 0), 30.0);
+    // Indices past the end of the table clamp to its last entry
+    assert_eq!(image.get(0, 3, 0), 30.0);
+
+    // Tables need at least one entry
+    data[32..36].copy_from_slice(&0_u32.to_be_bytes());
+    assert!(MapTable::parse(info, &mut Stream::big_endian(&data)).is_err());
+}
+
+#[test]
+fn test_map_polynomial() {
+    use crate::image::PixelType;
+
+    let info = OpcodeInfo {
+        id: OpcodeId::MapPolynomial as u32,
+        min_version: 0,
+        flags: 0,
+        stage: 2,
+    };
+    let polynomial = |coefficients: &[f64]| {
+        let mut data = Vec::new();
+        for value in [0_u32, 0, 1, 2, 0, 1, 1, 1] {
+            data.extend_from_slice(&value.to_be_bytes());
+        }
+        data.extend_from_slice(&(coefficients.len() as u32 - 1).to_be_bytes());
+        for value in coefficients {
+            data.extend_from_slice(&value.to_be_bytes());
+        }
+        data
+    };
+
+    // x^2
+    let data = polynomial(&[0.0, 0.0, 1.0]);
+    let opcode = MapPolynomial::parse(info, &mut Stream::big_endian(&data)).unwrap();
+    assert!(!opcode.is_nop());
+    assert_eq!(opcode.evaluate(0.5), 0.25);
+    let mut image = Image::new(Rect::new(0, 0, 1, 2).unwrap(), 1, PixelType::Float);
+    image.set(0, 0, 0, 0.5);
+    image.set(0, 1, 0, 1.0);
+    opcode
+        .apply(&Host::default(), &Negative::new("test"), &mut image)
+        .unwrap();
+    assert_eq!(image.get(0, 0, 0), 0.25);
+    assert_eq!(image.get(0, 1, 0), 1.0);
+
+    // The identity is a nop
+    let data = polynomial(&[0.0, 1.0]);
+    let opcode = MapPolynomial::parse(info, &mut Stream::big_endian(&data)).unwrap();
+    assert!(opcode.is_nop());
+
+    // The degree is limited to 8
+    let data = polynomial(&[0.0; MapPolynomial::MAX_DEGREE + 1]);
+    let opcode = MapPolynomial::parse(info, &mut Stream::big_endian(&data)).unwrap();
+    assert_eq!(opcode.coefficients.len(), MapPolynomial::MAX_DEGREE + 1);
+    let data = polynomial(&[0.0; MapPolynomial::MAX_DEGREE + 2]);
+    assert!(MapPolynomial::parse(info, &mut Stream::big_endian(&data)).is_err());
+}
@@ -0,0 +1,121 @@
+use crate::errors::{Error, ErrorKind};
+use crate::host::Host;
+use crate::image::Image;
+use crate::negative::Negative;
+use crate::opcode::{Opcode, OpcodeInfo};
+use crate::point::Point;
+use crate::stream::Stream;
+
+/// Number of coefficients of the FixVignetteRadial polynomial
+pub const VIGNETTE_RADIAL_COUNT: usize = 5;
+
+/// Radially symmetric vignetting correction. Every sample is multiplied by
+/// 1 + k0 * r^2 + k1 * r^4 + k2 * r^6 + k3 * r^8 + k4 * r^10, where r is the
+/// distance to the optical centre normalized by the distance to the
+/// farthest corner of the image.
+#[derive(Debug, Clone)]
+pub struct FixVignetteRadial {
+    info: OpcodeInfo,
+    pub coefficients: [f64; VIGNETTE_RADIAL_COUNT],
+    /// The optical centre in normalized (h, v) coordinates, 0.5 is the middle
+    pub center: Point<f64>,
+}
+
+impl FixVignetteRadial {
+    pub fn parse(info: OpcodeInfo, stream: &mut Stream) -> crate::Result<Self> {
+        let mut coefficients = [0.0; VIGNETTE_RADIAL_COUNT];
+        for coefficient in coefficients.iter_mut() {
+            *coefficient = stream.get_f64()?;
+        }
+        let h = stream.get_f64()?;
+        let v = stream.get_f64()?;
+        if !(0.0..=1.0).contains(&h) || !(0.0..=1.0).contains(&v) {
+            return Err(Error::new(ErrorKind::InvalidOpcode(info.id)));
+        }
+        Ok(Self {
+            info,
+            coefficients,
+            center: Point::new(v, h),
+        })
+    }
+
+    /// The gain for the squared normalized radius
+    pub fn gain(&self, r2: f64) -> f64 {
+        1.0 + r2
+            * self
+                .coefficients
+                .iter()
+                .rev()
+                .fold(0.0, |acc, k| acc * r2 + k)
+    }
+}
+
+impl Opcode for FixVignetteRadial {
+    fn info(&self) -> &OpcodeInfo {
+        &self.info
+    }
+
+    fn is_nop(&self) -> bool {
+        self.coefficients.iter().all(|k| *k == 0.0)
+    }
+
+    fn apply(&self, _host: &Host, _negative: &Negative, image: &mut Image) -> crate::Result<()> {
+        let bounds = image.bounds();
+        let center_v = bounds.top as f64 + self.center.v * (bounds.bottom - bounds.top) as f64;
+        let center_h = bounds.left as f64 + self.center.h * (bounds.right - bounds.left) as f64;
+        let max_v = (center_v - bounds.top as f64).max(bounds.bottom as f64 - center_v);
+        let max_h = (center_h - bounds.left as f64).max(bounds.right as f64 - center_h);
+        let norm_radius2 = max_v * max_v + max_h * max_h;
+        if norm_radius2 <= 0.0 {
+            return Ok(());
+        }
+
+        for row in bounds.top..bounds.bottom {
+            let dy = row as f64 + 0.5 - center_v;
+            for col in bounds.left..bounds.right {
+                let dx = col as f64 + 0.5 - center_h;
+                let gain = self.gain((dx * dx + dy * dy) / norm_radius2) as f32;
+                for plane in 0..image.planes() {
+                    let value = image.get(row, col, plane) * gain;
+                    image.set_clipped(row, col, plane, value);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_fix_vignette_radial() {
+    use crate::image::PixelType;
+    use crate::opcode::OpcodeId;
+    use crate::rect::Rect;
+
+    let info = OpcodeInfo {
+        id: OpcodeId::FixVignetteRadial as u32,
+        min_version: 0,
+        flags: 0,
+        stage: 2,
+    };
+    let mut data = Vec::new();
+    for value in [1.0_f64, 0.0, 0.0, 0.0, 0.0, 0.5, 0.5] {
+        data.extend_from_slice(&value.to_be_bytes());
+    }
+    let opcode = FixVignetteRadial::parse(info, &mut Stream::big_endian(&data)).unwrap();
+    assert!(!opcode.is_nop());
+
+    // In a 4x4 image the corner pixel centres are at r^2 = 4.5 / 8
+    let mut image = Image::new(Rect::new(0, 0, 4, 4).unwrap(), 1, PixelType::Short);
+    image.data_mut().fill(1000.0);
+    image.set(3, 3, 0, 60000.0);
+    opcode
+        .apply(&Host::default(), &Negative::new("test"), &mut image)
+        .unwrap();
+    assert_eq!(image.get(0, 0, 0), 1563.0);
+    assert_eq!(image.get(1, 1, 0), 1063.0);
+    // Gains clip to the range of the pixel type
+    assert_eq!(image.get(3, 3, 0), 65535.0);
+
+    data[40..48].copy_from_slice(&1.5_f64.to_be_bytes());
+    assert!(FixVignetteRadial::parse(info, &mut Stream::big_endian(&data)).is_err());
+}
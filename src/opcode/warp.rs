@@ -0,0 +1,460 @@
+use crate::errors::{Error, ErrorKind};
+use crate::host::Host;
+use crate::image::{EdgeOption, Image};
+use crate::limits::MAX_COLOR_PLANES;
+use crate::negative::Negative;
+use crate::opcode::{Opcode, OpcodeId, OpcodeInfo};
+use crate::point::Point;
+use crate::rect::Rect;
+use crate::stream::Stream;
+
+/// Number of radial coefficients stored by WarpRectilinear
+pub const WARP_RECTILINEAR_RADIAL_COUNT: usize = 4;
+/// Number of radial coefficients stored by WarpRectilinear2
+pub const WARP_RECTILINEAR2_RADIAL_COUNT: usize = 15;
+
+/// Lens model of one image plane
+#[derive(Debug, Clone, PartialEq)]
+pub struct WarpPlane {
+    /// Coefficients of the radial polynomial in even powers of the
+    /// normalized radius: kr0 + kr1 * r^2 + kr2 * r^4 + ...
+    pub radial: Vec<f64>,
+    /// Tangential coefficients kt0 and kt1
+    pub tangential: [f64; 2],
+}
+
+impl WarpPlane {
+    pub fn is_nop(&self) -> bool {
+        self.radial
+            .iter()
+            .enumerate()
+            .all(|(i, k)| *k == if i == 0 { 1.0 } else { 0.0 })
+            && self.tangential == [0.0, 0.0]
+    }
+
+    /// Evaluate the radial scale factor for the squared normalized radius
+    pub fn radial_scale(&self, r2: f64, reciprocal: bool) -> f64 {
+        let scale = self.radial.iter().rev().fold(0.0, |acc, k| acc * r2 + k);
+        if reciprocal {
+            1.0 / scale
+        } else {
+            scale
+        }
+    }
+}
+
+/// The optical centre of a warp in pixel coordinates and the radius used to
+/// normalize distances from it. As in the SDK the centre is interpolated
+/// between the first and the last pixel, and horizontal distances are
+/// scaled by the pixel aspect ratio so the lens model sees square pixels.
+struct WarpGeometry {
+    center_v: f64,
+    center_h: f64,
+    pixel_aspect: f64,
+    norm_radius: f64,
+}
+
+impl WarpGeometry {
+    fn new(center: Point<f64>, bounds: Rect<i32>, negative: &Negative) -> Self {
+        let pixel_aspect = match negative.pixel_aspect_ratio() {
+            aspect if aspect.is_finite() && aspect > 0.0 => aspect,
+            _ => 1.0,
+        };
+        let (top, left) = (bounds.top as f64, bounds.left as f64);
+        let (last_v, last_h) = ((bounds.bottom - 1) as f64, (bounds.right - 1) as f64);
+        let center_v = top + center.v * (last_v - top);
+        let center_h = left + center.h * (last_h - left);
+        // Distance to the farthest corner pixel, which is at radius 1.0
+        let max_v = (center_v - top).max(last_v - center_v);
+        let max_h = (center_h - left).max(last_h - center_h);
+        Self {
+            center_v,
+            center_h,
+            pixel_aspect,
+            norm_radius: max_v.hypot(max_h * pixel_aspect),
+        }
+    }
+
+    /// The normalized (dx, dy) offset of a pixel from the centre
+    fn normalize(&self, row: i32, col: i32) -> (f64, f64) {
+        (
+            (col as f64 - self.center_h) * self.pixel_aspect / self.norm_radius,
+            (row as f64 - self.center_v) / self.norm_radius,
+        )
+    }
+
+    /// The (row, col) pixel position of a normalized offset
+    fn denormalize(&self, dx: f64, dy: f64) -> (f64, f64) {
+        (
+            self.center_v + dy * self.norm_radius,
+            self.center_h + dx * self.norm_radius / self.pixel_aspect,
+        )
+    }
+}
+
+/// Rectilinear lens distortion and lateral chromatic aberration correction,
+/// used by both the WarpRectilinear and WarpRectilinear2 opcodes.
+///
+/// For every (undistorted) output pixel the source location is found with
+/// the radial and tangential model of its plane, in coordinates relative to
+/// the optical centre normalized by the distance to the farthest corner.
+#[derive(Debug, Clone)]
+pub struct WarpRectilinear {
+    info: OpcodeInfo,
+    /// One entry per plane, or a single entry used for all of them
+    pub planes: Vec<WarpPlane>,
+    /// The optical centre in normalized (h, v) coordinates, 0.5 is the middle
+    pub center: Point<f64>,
+    /// WarpRectilinear2 only: the radial polynomial gives the reciprocal of
+    /// the scale factor
+    pub reciprocal_radial: bool,
+}
+
+impl WarpRectilinear {
+    pub fn parse(info: OpcodeInfo, stream: &mut Stream) -> crate::Result<Self> {
+        let radial_count = if info.id == OpcodeId::WarpRectilinear2 as u32 {
+            WARP_RECTILINEAR2_RADIAL_COUNT
+        } else {
+            WARP_RECTILINEAR_RADIAL_COUNT
+        };
+        let count = stream.get_u32()? as usize;
+        if count == 0 || count > MAX_COLOR_PLANES {
+            return Err(Error::new(ErrorKind::InvalidOpcode(info.id)));
+        }
+        let planes = (0..count)
+            .map(|_| {
+                let radial = (0..radial_count)
+                    .map(|_| stream.get_f64())
+                    .collect::<crate::Result<Vec<_>>>()?;
+                let tangential = [stream.get_f64()?, stream.get_f64()?];
+                Ok(WarpPlane { radial, tangential })
+            })
+            .collect::<crate::Result<Vec<_>>>()?;
+        let h = stream.get_f64()?;
+        let v = stream.get_f64()?;
+        let reciprocal_radial = if radial_count == WARP_RECTILINEAR2_RADIAL_COUNT {
+            stream.get_u32()? != 0
+        } else {
+            false
+        };
+        if !(0.0..=1.0).contains(&h) || !(0.0..=1.0).contains(&v) {
+            return Err(Error::new(ErrorKind::InvalidOpcode(info.id)));
+        }
+        Ok(Self {
+            info,
+            planes,
+            center: Point::new(v, h),
+            reciprocal_radial,
+        })
+    }
+
+    fn plane(&self, plane: u32) -> &WarpPlane {
+        &self.planes[(plane as usize).min(self.planes.len() - 1)]
+    }
+}
+
+impl Opcode for WarpRectilinear {
+    fn info(&self) -> &OpcodeInfo {
+        &self.info
+    }
+
+    fn is_nop(&self) -> bool {
+        self.planes.iter().all(WarpPlane::is_nop)
+    }
+
+    fn apply(&self, _host: &Host, negative: &Negative, image: &mut Image) -> crate::Result<()> {
+        if self.planes.len() != 1 && self.planes.len() != image.planes() as usize {
+            return Err(Error::new(ErrorKind::InvalidOpcode(self.info.id)));
+        }
+        let bounds = image.bounds();
+        let mut source = image.clone();
+        if source.edge_option() == EdgeOption::None {
+            source.set_edge_option(EdgeOption::Repeat);
+        }
+
+        let geometry = WarpGeometry::new(self.center, bounds, negative);
+        if geometry.norm_radius <= 0.0 {
+            return Ok(());
+        }
+
+        for plane in 0..image.planes() {
+            let warp = self.plane(plane);
+            if warp.is_nop() {
+                continue;
+            }
+            let [kt0, kt1] = warp.tangential;
+            for row in bounds.top..bounds.bottom {
+                for col in bounds.left..bounds.right {
+                    let (dx, dy) = geometry.normalize(row, col);
+                    let r2 = dx * dx + dy * dy;
+                    let ratio = warp.radial_scale(r2, self.reciprocal_radial);
+                    let src_x = dx * ratio + 2.0 * kt0 * dx * dy + kt1 * (r2 + 2.0 * dx * dx);
+                    let src_y = dy * ratio + kt0 * (r2 + 2.0 * dy * dy) + 2.0 * kt1 * dx * dy;
+                    let (src_row, src_col) = geometry.denormalize(src_x, src_y);
+                    image.set(
+                        row,
+                        col,
+                        plane,
+                        source.get_bilinear(src_row, src_col, plane),
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Fisheye lens distortion correction (WarpFisheye), mapping a fisheye
+/// projection to a rectilinear one.
+///
+/// For every output pixel at normalized radius r from the optical centre,
+/// the source lies at radius kr0 * t + kr1 * t^3 + kr2 * t^5 + kr3 * t^7 in
+/// the same direction, with t = atan(r).
+#[derive(Debug, Clone)]
+pub struct WarpFisheye {
+    info: OpcodeInfo,
+    /// The radial coefficients of every plane, or of all of them
+    pub planes: Vec<[f64; 4]>,
+    /// The optical centre in normalized (h, v) coordinates, 0.5 is the middle
+    pub center: Point<f64>,
+}
+
+impl WarpFisheye {
+    pub fn parse(info: OpcodeInfo, stream: &mut Stream) -> crate::Result<Self> {
+        let count = stream.get_u32()? as usize;
+        if count == 0 || count > MAX_COLOR_PLANES {
+            return Err(Error::new(ErrorKind::InvalidOpcode(info.id)));
+        }
+        let planes = (0..count)
+            .map(|_| {
+                Ok([
+                    stream.get_f64()?,
+                    stream.get_f64()?,
+                    stream.get_f64()?,
+                    stream.get_f64()?,
+                ])
+            })
+            .collect::<crate::Result<Vec<_>>>()?;
+        let h = stream.get_f64()?;
+        let v = stream.get_f64()?;
+        if !(0.0..=1.0).contains(&h) || !(0.0..=1.0).contains(&v) {
+            return Err(Error::new(ErrorKind::InvalidOpcode(info.id)));
+        }
+        Ok(Self {
+            info,
+            planes,
+            center: Point::new(v, h),
+        })
+    }
+
+    /// The source radius of an output radius, both normalized
+    fn source_radius(coefficients: &[f64; 4], r: f64) -> f64 {
+        let t = r.atan();
+        let t2 = t * t;
+        t * coefficients.iter().rev().fold(0.0, |acc, k| acc * t2 + k)
+    }
+}
+
+impl Opcode for WarpFisheye {
+    fn info(&self) -> &OpcodeInfo {
+        &self.info
+    }
+
+    fn apply(&self, _host: &Host, negative: &Negative, image: &mut Image) -> crate::Result<()> {
+        if self.planes.len() != 1 && self.planes.len() != image.planes() as usize {
+            return Err(Error::new(ErrorKind::InvalidOpcode(self.info.id)));
+        }
+        let bounds = image.bounds();
+        let mut source = image.clone();
+        if source.edge_option() == EdgeOption::None {
+            source.set_edge_option(EdgeOption::Repeat);
+        }
+
+        let geometry = WarpGeometry::new(self.center, bounds, negative);
+        if geometry.norm_radius <= 0.0 {
+            return Ok(());
+        }
+
+        for plane in 0..image.planes() {
+            let coefficients = &self.planes[(plane as usize).min(self.planes.len() - 1)];
+            for row in bounds.top..bounds.bottom {
+                for col in bounds.left..bounds.right {
+                    let (dx, dy) = geometry.normalize(row, col);
+                    let r = dx.hypot(dy);
+                    let ratio = if r > 0.0 {
+                        Self::source_radius(coefficients, r) / r
+                    } else {
+                        coefficients[0]
+                    };
+                    let (src_row, src_col) = geometry.denormalize(dx * ratio, dy * ratio);
+                    image.set(
+                        row,
+                        col,
+                        plane,
+                        source.get_bilinear(src_row, src_col, plane),
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+fn warp_params(planes: &[([f64; 4], [f64; 2])], center: [f64; 2]) -> Vec<u8> {
+    let mut data = (planes.len() as u32).to_be_bytes().to_vec();
+    for (radial, tangential) in planes {
+        for value in radial.iter().chain(tangential) {
+            data.extend_from_slice(&value.to_be_bytes());
+        }
+    }
+    for value in center {
+        data.extend_from_slice(&value.to_be_bytes());
+    }
+    data
+}
+
+#[cfg(test)]
+fn warp_image(planes: u32) -> Image {
+    use crate::image::PixelType;
+    use crate::rect::Rect;
+
+    // Sampling a linear ramp bilinearly gives back the source position
+    let mut image = Image::new(Rect::new(0, 0, 11, 11).unwrap(), planes, PixelType::Float);
+    for row in 0..11 {
+        for col in 0..11 {
+            for plane in 0..planes {
+                image.set(row, col, plane, (10 * row + col) as f32);
+            }
+        }
+    }
+    image
+}
+
+#[test]
+fn test_warp_rectilinear() {
+    let info = OpcodeInfo {
+        id: OpcodeId::WarpRectilinear as u32,
+        min_version: 0,
+        flags: 0,
+        stage: 3,
+    };
+    // The centre of an 11x11 image is the pixel at (5, 5), and the
+    // normalization radius squared is 50
+    let data = warp_params(&[([1.0, 0.1, 0.0, 0.0], [0.01, 0.0])], [0.5, 0.5]);
+    let opcode = WarpRectilinear::parse(info, &mut Stream::big_endian(&data)).unwrap();
+    let mut image = warp_image(1);
+    opcode
+        .apply(&Host::default(), &Negative::new("test"), &mut image)
+        .unwrap();
+    // 3 pixels right of the centre: radially out by 3 * 0.1 * r^2 pixels,
+    // and tangentially down by kt0 * r^2 in normalized units
+    let norm = 50.0_f64.sqrt();
+    let radial = 3.0 * 0.1 * 9.0 / 50.0;
+    let tangential = 0.01 * 9.0 / norm;
+    let expected = 58.0 + radial + 10.0 * tangential;
+    assert!((image.get(5, 8, 0) - expected as f32).abs() < 1e-3);
+    // 3 pixels below the centre: radially down the same, and tangentially
+    // down by kt0 * 3 * dy^2
+    let tangential = 0.01 * 27.0 / norm;
+    let expected = 10.0 * (8.0 + radial + tangential) + 5.0;
+    assert!((image.get(8, 5, 0) - expected as f32).abs() < 1e-3);
+    // The centre does not move
+    assert_eq!(image.get(5, 5, 0), 55.0);
+}
+
+#[test]
+fn test_warp_rectilinear_planes() {
+    let info = OpcodeInfo {
+        id: OpcodeId::WarpRectilinear as u32,
+        min_version: 0,
+        flags: 0,
+        stage: 3,
+    };
+    // Lateral chromatic aberration: only the second plane is scaled
+    let identity = ([1.0, 0.0, 0.0, 0.0], [0.0, 0.0]);
+    let data = warp_params(&[identity, ([1.01, 0.0, 0.0, 0.0], [0.0, 0.0])], [0.5, 0.5]);
+    let opcode = WarpRectilinear::parse(info, &mut Stream::big_endian(&data)).unwrap();
+    let mut image = warp_image(2);
+    opcode
+        .apply(&Host::default(), &Negative::new("test"), &mut image)
+        .unwrap();
+    assert_eq!(image.get(5, 8, 0), 58.0);
+    assert!((image.get(5, 8, 1) - 58.03).abs() < 1e-4);
+
+    // The plane count must match the image
+    let mut image = warp_image(3);
+    assert!(opcode
+        .apply(&Host::default(), &Negative::new("test"), &mut image)
+        .is_err());
+}
+
+#[test]
+fn test_warp_fisheye() {
+    let info = OpcodeInfo {
+        id: OpcodeId::WarpFisheye as u32,
+        min_version: 0,
+        flags: 0,
+        stage: 3,
+    };
+    let mut data = 1_u32.to_be_bytes().to_vec();
+    for value in [1.0_f64, 0.0, 0.0, 0.0, 0.5, 0.5] {
+        data.extend_from_slice(&value.to_be_bytes());
+    }
+    let opcode = WarpFisheye::parse(info, &mut Stream::big_endian(&data)).unwrap();
+    let mut image = warp_image(1);
+    opcode
+        .apply(&Host::default(), &Negative::new("test"), &mut image)
+        .unwrap();
+    // With only kr0 the source radius is atan(r): 3 pixels right of the
+    // centre samples from atan(3 / norm) * norm pixels
+    let norm = 50.0_f64.sqrt();
+    let expected = 5.0 + (3.0 / norm).atan() * norm;
+    assert!((image.get(5, 8, 0) - (50.0 + expected) as f32).abs() < 1e-4);
+    assert_eq!(image.get(5, 5, 0), 55.0);
+}
+
+#[test]
+fn test_warp_rectilinear_geometry() {
+    use crate::types::rational::URational;
+
+    let info = OpcodeInfo {
+        id: OpcodeId::WarpRectilinear as u32,
+        min_version: 0,
+        flags: 0,
+        stage: 3,
+    };
+    // The farthest corner pixel is at radius 1.0, wherever the centre is
+    let bounds = Rect::new(0, 0, 11, 21).unwrap();
+    for (v, h) in [(0.5, 0.5), (0.2, 0.9)] {
+        let geometry = WarpGeometry::new(Point::new(v, h), bounds, &Negative::new("test"));
+        let corners = [(0, 0), (0, 20), (10, 0), (10, 20)].map(|(row, col)| {
+            let (dx, dy) = geometry.normalize(row, col);
+            dx.hypot(dy)
+        });
+        assert!((corners.iter().fold(0.0_f64, |a, b| a.max(*b)) - 1.0).abs() < 1e-12);
+    }
+
+    // A centre on the right edge is the last column, which does not move
+    let data = warp_params(&[([1.0, 0.1, 0.0, 0.0], [0.0, 0.0])], [1.0, 0.5]);
+    let opcode = WarpRectilinear::parse(info, &mut Stream::big_endian(&data)).unwrap();
+    let mut image = warp_image(1);
+    opcode
+        .apply(&Host::default(), &Negative::new("test"), &mut image)
+        .unwrap();
+    assert_eq!(image.get(5, 10, 0), 60.0);
+
+    // Pixels twice as wide as they are tall: horizontal distances double,
+    // so the normalization radius squared is 10^2 + 5^2 and 3 pixels right
+    // of the centre is at r^2 = 6^2 / 125
+    let data = warp_params(&[([1.0, 0.1, 0.0, 0.0], [0.0, 0.0])], [0.5, 0.5]);
+    let opcode = WarpRectilinear::parse(info, &mut Stream::big_endian(&data)).unwrap();
+    let mut negative = Negative::new("test");
+    negative.set_default_scale(URational::from(2.0), URational::from(1.0));
+    let mut image = warp_image(1);
+    opcode
+        .apply(&Host::default(), &negative, &mut image)
+        .unwrap();
+    let expected = 58.0 + 3.0 * 0.1 * 36.0 / 125.0;
+    assert!((image.get(5, 8, 0) - expected as f32).abs() < 1e-4);
+}
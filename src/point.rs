@@ -3,7 +3,7 @@ use std::ops::{Add, Mul};
 
 use crate::traits::Hypotenuse;
 
-#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+#[derive(Eq, PartialEq, Hash, Debug, Clone, Copy)]
 pub struct Point<T> {
     pub v: T,
     pub h: T,
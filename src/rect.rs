@@ -16,7 +16,7 @@ pub trait Height<T> {
 
 use crate::point::Point;
 pub type IRect = Rect<i32>;
-#[derive(Default, Eq, PartialEq, PartialOrd, Ord)]
+#[derive(Default, Debug, Clone, Copy, Eq, PartialEq, PartialOrd, Ord)]
 pub struct Rect<T> {
     pub top: T,
     pub left: T,
@@ -41,6 +41,19 @@ impl Contains<Point<i32>> for IRect {
     }
 }
 
+impl IRect {
+    /// The overlap of two rectangles, or None when they don't overlap
+    pub fn intersection(&self, other: &IRect) -> Option<IRect> {
+        let rect = Self {
+            top: self.top.max(other.top),
+            left: self.left.max(other.left),
+            bottom: self.bottom.min(other.bottom),
+            right: self.right.min(other.right),
+        };
+        (rect.top < rect.bottom && rect.left < rect.right).then_some(rect)
+    }
+}
+
 impl TryFrom<(u32, u32)> for IRect {
     type Error = <i32 as TryFrom<u32>>::Error;
     fn try_from((width, height): (u32, u32)) -> Result<Self, Self::Error> {
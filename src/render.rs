@@ -0,0 +1,530 @@
+//! Rendering of a negative to an output referred RGB image, following the
+//! order of the DNG specification: the stage 3 image is mapped from camera
+//! space to linear ProPhoto RGB, exposed, adjusted by the tables of the
+//! profile, tone mapped and encoded in the final color space.
+
+use crate::color_space::Primaries;
+use crate::color_spec::ColorSpec;
+use crate::coord::Coord;
+use crate::errors::{Error, ErrorKind};
+use crate::image::{Image, ImageTrait, PixelType};
+use crate::matrix::{Matrix, Vector};
+use crate::negative::Negative;
+use crate::shared::{CameraProfileID, CameraProfileInfo};
+use crate::temparature::Temperature;
+use crate::traits::LinearFn;
+
+/// Black level of the Auto black render, as a fraction of white (the
+/// default shadows setting of 5 of the DNG SDK)
+const AUTO_BLACK: f64 = 0.005;
+
+/// The camera to PCS transform of a profile at a white balance
+struct CameraTransform {
+    camera_white: Vector<f64, 3>,
+    camera_to_pcs: Matrix<3>,
+    white_xy: Coord,
+}
+
+impl CameraTransform {
+    fn new<const ILML: usize>(
+        profile: &CameraProfileInfo,
+        negative: &Negative,
+        white_xy: Option<Coord>,
+    ) -> crate::Result<Self> {
+        let mut spec = ColorSpec::<ILML, 3>::new(profile, negative)?;
+        if let Some(white_xy) = white_xy {
+            spec.set_white_xy(white_xy)?;
+        }
+        Ok(Self {
+            camera_white: spec.camera_white,
+            camera_to_pcs: spec.camera_to_pcs,
+            white_xy: spec.white_xy(),
+        })
+    }
+
+    /// A monochrome negative has no color matrices: its gray is copied to
+    /// every ProPhoto RGB channel, so its camera space is ProPhoto RGB
+    fn monochrome(white_xy: Option<Coord>) -> crate::Result<Self> {
+        Ok(Self {
+            camera_white: Vector::new([1.0; 3]),
+            camera_to_pcs: Primaries::ProPhoto.to_pcs()?,
+            white_xy: white_xy.unwrap_or(Coord::D50_XY_COORD),
+        })
+    }
+}
+
+/// Apply a tone curve to a color while keeping its hue: the curve is applied
+/// to the largest and smallest channels, and the middle one keeps its
+/// relative position between them
+pub fn rgb_tone(curve: &impl LinearFn, rgb: [f32; 3]) -> [f32; 3] {
+    let mut order = [0, 1, 2];
+    order.sort_by(|a, b| rgb[*b].total_cmp(&rgb[*a]));
+    let [large, middle, small] = order;
+    let (max, mid, min) = (rgb[large], rgb[middle], rgb[small]);
+    let max_toned = curve.evaluate(max as f64) as f32;
+    let min_toned = curve.evaluate(min as f64) as f32;
+    let mid_toned = if max > min {
+        min_toned + (max_toned - min_toned) * (mid - min) / (max - min)
+    } else {
+        max_toned
+    };
+    let mut output = [0.0; 3];
+    output[large] = max_toned;
+    output[middle] = mid_toned;
+    output[small] = min_toned;
+    output
+}
+
+/// The white balance to render with
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum WhiteBalance {
+    /// The "As Shot" white balance of the negative
+    #[default]
+    AsShot,
+    Temperature {
+        temperature: f64,
+        tint: f64,
+    },
+    Xy {
+        x: f64,
+        y: f64,
+    },
+}
+
+impl WhiteBalance {
+    /// The white point, None for the "As Shot" white balance
+    pub fn white_xy(&self) -> Option<Coord> {
+        match *self {
+            Self::AsShot => None,
+            Self::Temperature { temperature, tint } => {
+                Some(Temperature { temperature, tint }.to_xy())
+            }
+            Self::Xy { x, y } => Some(Coord::new(x, y)),
+        }
+    }
+}
+
+/// How the black point of the render is chosen
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BlackRender {
+    /// Follow the DefaultBlackRender of the profile
+    #[default]
+    Profile,
+    /// Lift a small amount of black, to hide noise in the shadows
+    Auto,
+    /// Keep the black of the raw data
+    None,
+}
+
+/// What happens to colors where the raw data clips
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum HighlightMode {
+    /// Clip every channel at the camera white, so highlights turn neutral
+    /// where the first channel clips
+    #[default]
+    Clip,
+    /// Keep the color of the unclipped channels, blending towards the
+    /// neutral clipped color as more of the raw data clips
+    Blend,
+}
+
+/// The adjustments of a render, as set by a user for an image
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct RenderSettings {
+    /// Exposure compensation in stops, on top of the BaselineExposure of the
+    /// negative and the BaselineExposureOffset of the profile
+    pub exposure: f64,
+    pub white_balance: WhiteBalance,
+    pub black_render: BlackRender,
+    pub highlights: HighlightMode,
+}
+
+/// The exposure ramp of the DNG SDK: linear from `black` to `white`, with a
+/// quadratic toe around `black` so shadows do not clip abruptly
+struct ExposureRamp {
+    black: f64,
+    slope: f64,
+    radius: f64,
+    quadratic: f64,
+}
+
+impl ExposureRamp {
+    fn new(white: f64, black: f64) -> Self {
+        const MAX_CURVE_X: f64 = 0.5;
+        const MAX_CURVE_Y: f64 = 1.0 / 16.0;
+
+        let slope = 1.0 / (white - black);
+        let radius = (MAX_CURVE_X * black).min(MAX_CURVE_Y / slope);
+        let quadratic = if radius > 0.0 {
+            slope / (4.0 * radius)
+        } else {
+            0.0
+        };
+        Self {
+            black,
+            slope,
+            radius,
+            quadratic,
+        }
+    }
+
+    /// Unlike the SDK ramp, values past white are kept for the stages that
+    /// follow
+    fn evaluate(&self, x: f64) -> f64 {
+        if x <= self.black - self.radius {
+            0.0
+        } else if x >= self.black + self.radius {
+            (x - self.black) * self.slope
+        } else {
+            let y = x - (self.black - self.radius);
+            self.quadratic * y * y
+        }
+    }
+}
+
+/// Renders the stage 3 image of a negative
+pub struct Render<'r> {
+    negative: &'r Negative,
+    /// The data the table offsets of the profiles refer to
+    file: &'r [u8],
+    profile_id: CameraProfileID,
+    settings: RenderSettings,
+    final_space: Primaries,
+    final_pixel_type: PixelType,
+}
+
+impl<'r> Render<'r> {
+    /// A render of a negative with its default profile to 8 bit sRGB
+    pub fn new(negative: &'r Negative, file: &'r [u8]) -> Self {
+        Self {
+            negative,
+            file,
+            profile_id: CameraProfileID::default(),
+            settings: RenderSettings::default(),
+            final_space: Primaries::Srgb,
+            final_pixel_type: PixelType::Byte,
+        }
+    }
+
+    /// The profile to render with. Profiles are matched as by
+    /// [`Negative::profile_by_id`], falling back to the default profile.
+    pub fn set_profile_id(&mut self, id: CameraProfileID) {
+        self.profile_id = id;
+    }
+
+    pub fn settings(&self) -> &RenderSettings {
+        &self.settings
+    }
+
+    pub fn set_settings(&mut self, settings: RenderSettings) {
+        self.settings = settings;
+    }
+
+    /// Render with a custom white point, or the "As Shot" one for None
+    pub fn set_white_xy(&mut self, white_xy: Option<Coord>) {
+        self.settings.white_balance = match white_xy {
+            Some(white) => WhiteBalance::Xy {
+                x: white.x,
+                y: white.y,
+            },
+            None => WhiteBalance::AsShot,
+        };
+    }
+
+    pub fn set_exposure(&mut self, exposure: f64) {
+        self.settings.exposure = exposure;
+    }
+
+    pub fn set_final_space(&mut self, space: Primaries) {
+        self.final_space = space;
+    }
+
+    /// Byte, Short or Float
+    pub fn set_final_pixel_type(&mut self, pixel_type: PixelType) {
+        self.final_pixel_type = pixel_type;
+    }
+
+    /// Render the default crop of the stage 3 image at its default size, as
+    /// a 3 plane image in the final space and pixel type
+    pub fn render(&self) -> crate::Result<Image> {
+        if !matches!(
+            self.final_pixel_type,
+            PixelType::Byte | PixelType::Short | PixelType::Float
+        ) {
+            return Err(Error::new(ErrorKind::UnsupportedPixelType));
+        }
+        let negative = self.negative;
+        let mut stage3 = negative
+            .stage3_image()
+            .ok_or(Error::new(ErrorKind::MissingStage3Image))?
+            .clone();
+        stage3.trim(negative.final_crop_area())?;
+        let profile = negative
+            .profile_by_id(&self.profile_id, true)
+            .ok_or(Error::new(ErrorKind::MissingCameraProfile))?;
+
+        let settings = &self.settings;
+        let white_xy = settings.white_balance.white_xy();
+        let monochrome = negative.color_channels() == 1;
+        let transform = match profile.illuminant_count() {
+            _ if monochrome => CameraTransform::monochrome(white_xy)?,
+            3 => CameraTransform::new::<3>(profile, negative, white_xy)?,
+            _ => CameraTransform::new::<2>(profile, negative, white_xy)?,
+        };
+        // Skip the round trip through the PCS, so grays stay exactly neutral
+        let camera_to_rgb = if monochrome {
+            Matrix::identity()
+        } else {
+            Primaries::ProPhoto.from_pcs()? * transform.camera_to_pcs
+        };
+        let rgb_to_final = self.final_space.from_pcs()? * Primaries::ProPhoto.to_pcs()?;
+        let encoding = self.final_space.transfer_function();
+
+        let hue_sat_map = profile.hue_sat_map(self.file, &transform.white_xy)?;
+        let look_table = profile.look_table(self.file)?;
+        let rgb_tables = profile.rgb_tables(self.file)?;
+        let tone_curve = profile.tone_curve(self.file)?;
+        let gain_table_map = negative.profile_gain_table_map();
+        let exposure = negative.baseline_exposure()
+            + profile.baseline_exposure_offset.as_f64()
+            + settings.exposure;
+        // DefaultBlackRender is 0 for Auto and 1 for None
+        let black = match settings.black_render {
+            BlackRender::Profile if profile.default_black_render == 1 => 0.0,
+            BlackRender::Profile | BlackRender::Auto => AUTO_BLACK,
+            BlackRender::None => 0.0,
+        };
+        let white = 1.0 / 2.0_f64.powf(exposure);
+        let ramp = ExposureRamp::new(white, black.min(0.99 * white));
+        // How far the raw data can go past the camera white, for blending
+        let max_clip = 1.0 / transform.camera_white.min_entry().max(0.001);
+
+        // The gain table map spans the default crop
+        let crop = negative.default_crop_area();
+        let crop_height = (crop.bottom - crop.top).max(1) as f64;
+        let crop_width = (crop.right - crop.left).max(1) as f64;
+
+        let bounds = stage3.bounds();
+        let mut image = Image::new(bounds, 3, PixelType::Float);
+        for row in bounds.top..bounds.bottom {
+            for col in bounds.left..bounds.right {
+                let mut camera = Vector::new([0.0; 3]);
+                if monochrome {
+                    camera = Vector::new([stage3.get(row, col, 0) as f64; 3]);
+                } else {
+                    for plane in 0..stage3.planes().min(3) {
+                        camera[plane as usize] = stage3.get(row, col, plane) as f64;
+                    }
+                }
+                // Camera values are clipped at the camera white, so
+                // highlights stay neutral
+                let mut clipped = camera;
+                let mut clip = 0.0_f64;
+                for i in 0..3 {
+                    let white = transform.camera_white[i];
+                    clipped[i] = camera[i].min(white);
+                    clip = clip.max(camera[i] / white);
+                }
+                let mut linear = camera_to_rgb * clipped;
+                if settings.highlights == HighlightMode::Blend && clip > 1.0 && max_clip > 1.0 {
+                    let t = ((clip - 1.0) / (max_clip - 1.0)).clamp(0.0, 1.0);
+                    let t = t * t * (3.0 - 2.0 * t);
+                    let unclipped = camera_to_rgb * camera;
+                    for i in 0..3 {
+                        linear[i] = unclipped[i] * (1.0 - t) + linear[i] * t;
+                    }
+                }
+                // Only negatives are clipped here, blended highlights past 1.0
+                // are brought into range by the exposure ramp
+                let mut rgb: [f32; 3] = std::array::from_fn(|i| linear[i].max(0.0) as f32);
+
+                if let Some(map) = &hue_sat_map {
+                    rgb = map.apply(rgb);
+                }
+                rgb = rgb.map(|value| ramp.evaluate(value as f64) as f32);
+                if let Some(map) = gain_table_map {
+                    let v = (row - crop.top) as f64 / crop_height;
+                    let h = (col - crop.left) as f64 / crop_width;
+                    let gain = map.evaluate(v, h, rgb);
+                    rgb = rgb.map(|value| value * gain);
+                }
+                rgb = rgb.map(|value| value.clamp(0.0, 1.0));
+                if let Some(table) = &look_table {
+                    rgb = table.apply(rgb);
+                }
+                if let Some(tables) = &rgb_tables {
+                    rgb = tables.apply(rgb, 1.0);
+                }
+                rgb = rgb_tone(&tone_curve, rgb);
+
+                let output = rgb_to_final * Vector::new(rgb.map(|value| value as f64));
+                for plane in 0..3 {
+                    let value = encoding.encode(output[plane].clamp(0.0, 1.0));
+                    image.set(row, col, plane as u32, value as f32);
+                }
+            }
+        }
+        negative.apply_default_geometry(&mut image)?;
+
+        if self.final_pixel_type == PixelType::Float {
+            return Ok(image);
+        }
+        let mut output = Image::new(image.bounds(), 3, self.final_pixel_type);
+        let scale = self.final_pixel_type.max_value();
+        for (sample, value) in output.data_mut().iter_mut().zip(image.data()) {
+            *sample = self.final_pixel_type.clip(value * scale);
+        }
+        Ok(output)
+    }
+}
+
+#[test]
+fn test_render() {
+    use crate::rect::Rect;
+    use crate::types::rational::URational;
+    use std::num::NonZeroU32;
+
+    let mut negative = Negative::new("test");
+    negative.set_color_channels(3);
+    negative.set_active_area(Rect::new(0, 0, 2, 2).unwrap());
+    let one = NonZeroU32::MIN;
+    negative.set_default_crop_origin(URational::new(0_u32, one), URational::new(0_u32, one));
+    negative.set_default_crop_size(URational::new(2_u32, one), URational::new(2_u32, one));
+    negative.set_default_user_crop(
+        URational::new(0_u32, one),
+        URational::new(0_u32, one),
+        URational::new(1_u32, one),
+        URational::new(1_u32, one),
+    );
+    negative.set_camera_neutral(Some(Vector::new([1.0; 3])));
+    let mut stage3 = Image::new(Rect::new(0, 0, 2, 2).unwrap(), 3, PixelType::Float);
+    // Black, mid gray, white and saturated red
+    for (col, rgb) in [[0.0; 3], [0.18; 3]].into_iter().enumerate() {
+        for plane in 0..3 {
+            stage3.set(0, col as i32, plane, rgb[plane as usize]);
+        }
+    }
+    for (col, rgb) in [[1.0; 3], [0.5, 0.0, 0.0]].into_iter().enumerate() {
+        for plane in 0..3 {
+            stage3.set(1, col as i32, plane, rgb[plane as usize]);
+        }
+    }
+    negative.set_stage3_image(stage3);
+
+    // A camera that sees XYZ, adapted to D50
+    let profile = CameraProfileInfo {
+        color_planes: 3,
+        calibration_illuminant1: 21,
+        color_matrix1: Matrix::identity(),
+        profile_name: "Identity".into(),
+        ..Default::default()
+    };
+    negative.add_profile(profile, &[]).unwrap();
+
+    let render = Render::new(&negative, &[]);
+    let image = render.render().unwrap();
+    assert_eq!(image.size(), crate::point::Point::new(2, 2));
+    assert_eq!(image.pixel_type(), PixelType::Byte);
+    let pixel = |row, col| [0, 1, 2].map(|plane| image.get(row, col, plane));
+    assert_eq!(pixel(0, 0), [0.0; 3]);
+    assert_eq!(pixel(1, 0), [255.0; 3]);
+    // The white balance keeps grays neutral
+    let [r, g, b] = pixel(0, 1);
+    assert!(r > 100.0 && (r - g).abs() <= 1.0 && (g - b).abs() <= 1.0);
+    let [r, g, b] = pixel(1, 1);
+    assert!(r > g && r > b);
+
+    let mut render = Render::new(&negative, &[]);
+    render.set_final_space(Primaries::ProPhoto);
+    render.set_final_pixel_type(PixelType::Float);
+    let gray = render.render().unwrap().get(0, 1, 1);
+    render.set_exposure(-1.0);
+    assert!(render.render().unwrap().get(0, 1, 1) < gray);
+    render.set_final_pixel_type(PixelType::Long);
+    assert!(render.render().is_err());
+}
+
+#[test]
+fn test_render_settings() {
+    use crate::rect::Rect;
+
+    let white = WhiteBalance::Temperature {
+        temperature: 6504.0,
+        tint: 0.0,
+    };
+    let temperature = Temperature::from_coord(&white.white_xy().unwrap()).unwrap();
+    assert!((temperature.temperature - 6504.0).abs() < 1.0);
+    assert_eq!(WhiteBalance::AsShot.white_xy(), None);
+
+    let ramp = ExposureRamp::new(0.5, AUTO_BLACK);
+    assert_eq!(ramp.evaluate(0.0), 0.0);
+    assert!((ramp.evaluate(0.5) - 1.0).abs() < 1e-12);
+    assert!(ramp.evaluate(AUTO_BLACK) > 0.0);
+
+    // A camera whose green channel is twice as sensitive, with raw data past
+    // the white of the red channel
+    let mut negative = Negative::new("test");
+    negative.set_color_channels(3);
+    negative.set_active_area(Rect::new(0, 0, 1, 1).unwrap());
+    negative.set_camera_neutral(Some(Vector::new([0.5, 1.0, 0.5])));
+    let mut stage3 = Image::new(Rect::new(0, 0, 1, 1).unwrap(), 3, PixelType::Float);
+    stage3.data_mut().copy_from_slice(&[0.7, 1.0, 0.5]);
+    negative.set_stage3_image(stage3);
+    let profile = CameraProfileInfo {
+        color_planes: 3,
+        calibration_illuminant1: 21,
+        color_matrix1: Matrix::identity(),
+        ..Default::default()
+    };
+    negative.add_profile(profile, &[]).unwrap();
+
+    // Exposed down, so the highlights are not clipped by the output range
+    let mut render = Render::new(&negative, &[]);
+    render.set_exposure(-1.0);
+    let clipped = render.render().unwrap();
+    assert!(clipped
+        .data()
+        .iter()
+        .all(|value| *value == clipped.data()[0]));
+    render.set_settings(RenderSettings {
+        exposure: -1.0,
+        highlights: HighlightMode::Blend,
+        ..Default::default()
+    });
+    let blended = render.render().unwrap();
+    assert!(blended.get(0, 0, 0) > blended.get(0, 0, 2));
+    // The recovered red goes past the camera white, which Clip cannot reach
+    assert!(blended.get(0, 0, 0) > clipped.get(0, 0, 0) + 10.0);
+}
+
+#[test]
+fn test_render_monochrome() {
+    use crate::rect::Rect;
+
+    let mut negative = Negative::new("test");
+    negative.set_color_channels(1);
+    negative.set_active_area(Rect::new(0, 0, 1, 3).unwrap());
+    let mut stage3 = Image::new(Rect::new(0, 0, 1, 3).unwrap(), 1, PixelType::Float);
+    stage3.data_mut().copy_from_slice(&[0.0, 0.18, 1.0]);
+    negative.set_stage3_image(stage3);
+    // A monochrome profile has no color matrices
+    let profile = CameraProfileInfo {
+        color_planes: 1,
+        profile_name: "Gray".into(),
+        ..Default::default()
+    };
+    negative.add_profile(profile, &[]).unwrap();
+
+    let image = Render::new(&negative, &[]).render().unwrap();
+    assert_eq!(image.planes(), 3);
+    let pixel = |col| [0, 1, 2].map(|plane| image.get(0, col, plane));
+    assert_eq!(pixel(0), [0.0; 3]);
+    assert_eq!(pixel(2), [255.0; 3]);
+    let [r, g, b] = pixel(1);
+    assert!(r > 100.0 && r == g && g == b);
+}
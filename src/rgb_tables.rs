@@ -0,0 +1,291 @@
+//! RGBTables (DNG 1.6): RGB lookup tables that profiles use instead of, or
+//! on top of, a HueSatMap. Each table works in its own color space and
+//! encoding, so colors are converted from the rendering space into it, looked
+//! up, and converted back.
+
+use crate::color_space::{Primaries, TransferFunction};
+use crate::errors::{Error, ErrorKind};
+use crate::image::Image;
+use crate::matrix::{Matrix, Vector};
+use crate::stream::Stream;
+
+/// Version of the table layout we can read
+const RGB_TABLE_VERSION: u32 = 1;
+
+const MIN_DIVISIONS: usize = 2;
+const MAX_DIVISIONS_1D: usize = 4096;
+const MAX_DIVISIONS_3D: usize = 32;
+
+/// What happens to colors outside the 0.0..=1.0 range of the table
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum Gamut {
+    /// Clip them to the table range
+    #[default]
+    Clip = 0,
+    /// Look up the clipped color and add back what was clipped
+    Extend = 1,
+}
+
+impl Gamut {
+    pub fn from_tiff(value: u32) -> Option<Self> {
+        match value {
+            0 => Some(Self::Clip),
+            1 => Some(Self::Extend),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RgbTable {
+    /// 1 for a curve per channel, 3 for a 3D table
+    dimensions: usize,
+    /// Entries along every axis
+    divisions: usize,
+    pub primaries: Primaries,
+    pub transfer_function: TransferFunction,
+    pub gamut: Gamut,
+    /// Range of the amount the table can be applied with, 1.0 being the
+    /// table as is
+    pub min_amount: f64,
+    pub max_amount: f64,
+    /// RGB entries, with red varying slowest and blue fastest
+    samples: Vec<[f32; 3]>,
+    /// Between the rendering space (linear ProPhoto) and the table space
+    to_table: Matrix<3>,
+    from_table: Matrix<3>,
+}
+
+impl RgbTable {
+    /// An identity table
+    pub fn new(
+        dimensions: usize,
+        divisions: usize,
+        primaries: Primaries,
+        transfer_function: TransferFunction,
+    ) -> crate::Result<Self> {
+        let max_divisions = match dimensions {
+            1 => MAX_DIVISIONS_1D,
+            3 => MAX_DIVISIONS_3D,
+            _ => return Err(Error::new(ErrorKind::InvalidCameraProfile)),
+        };
+        if !(MIN_DIVISIONS..=max_divisions).contains(&divisions) {
+            return Err(Error::new(ErrorKind::InvalidCameraProfile));
+        }
+        let scale = 1.0 / (divisions - 1) as f32;
+        let samples = if dimensions == 1 {
+            (0..divisions).map(|i| [i as f32 * scale; 3]).collect()
+        } else {
+            (0..divisions.pow(3))
+                .map(|index| {
+                    let (r, g, b) = (
+                        index / (divisions * divisions),
+                        index / divisions % divisions,
+                        index % divisions,
+                    );
+                    [r as f32 * scale, g as f32 * scale, b as f32 * scale]
+                })
+                .collect()
+        };
+        let to_table = primaries.from_pcs()? * Primaries::ProPhoto.to_pcs()?;
+        Ok(Self {
+            dimensions,
+            divisions,
+            primaries,
+            transfer_function,
+            gamut: Gamut::Clip,
+            min_amount: 0.0,
+            max_amount: 2.0,
+            samples,
+            from_table: to_table.invert()?,
+            to_table,
+        })
+    }
+
+    /// Read one table: the version, dimensions and divisions, the
+    /// primaries, transfer function and gamut, the amount range, then the
+    /// entries as 16 bit differences from the identity table
+    pub fn parse(stream: &mut Stream) -> crate::Result<Self> {
+        let invalid = || Error::new(ErrorKind::InvalidCameraProfile);
+        if stream.get_u32()? != RGB_TABLE_VERSION {
+            return Err(invalid());
+        }
+        let dimensions = stream.get_u32()? as usize;
+        let divisions = stream.get_u32()? as usize;
+        let primaries = Primaries::from_tiff(stream.get_u32()?).ok_or_else(invalid)?;
+        let transfer_function =
+            TransferFunction::from_tiff(stream.get_u32()?).ok_or_else(invalid)?;
+        let gamut = Gamut::from_tiff(stream.get_u32()?).ok_or_else(invalid)?;
+        let min_amount = stream.get_f64()?;
+        let max_amount = stream.get_f64()?;
+        if !(0.0..=1.0).contains(&min_amount) || !(1.0..=2.0).contains(&max_amount) {
+            return Err(invalid());
+        }
+        let mut table = Self::new(dimensions, divisions, primaries, transfer_function)?;
+        table.gamut = gamut;
+        table.min_amount = min_amount;
+        table.max_amount = max_amount;
+        for sample in table.samples.iter_mut() {
+            for value in sample.iter_mut() {
+                let identity = (*value * u16::MAX as f32).round() as u16;
+                let delta = stream.get_u16()?;
+                *value = identity.wrapping_add(delta) as f32 / u16::MAX as f32;
+            }
+        }
+        Ok(table)
+    }
+
+    pub fn dimensions(&self) -> usize {
+        self.dimensions
+    }
+
+    pub fn divisions(&self) -> usize {
+        self.divisions
+    }
+
+    pub fn sample(&self, r: usize, g: usize, b: usize) -> [f32; 3] {
+        self.samples[(r * self.divisions + g) * self.divisions + b]
+    }
+
+    pub fn set_sample(&mut self, r: usize, g: usize, b: usize, rgb: [f32; 3]) {
+        let index = (r * self.divisions + g) * self.divisions + b;
+        self.samples[index] = rgb;
+    }
+
+    /// Look up an encoded color in 0.0..=1.0
+    fn lookup(&self, rgb: [f32; 3]) -> [f32; 3] {
+        let scale = (self.divisions - 1) as f32;
+        let cell = |value: f32| {
+            let scaled = value * scale;
+            let index = (scaled as usize).min(self.divisions - 2);
+            (index, scaled - index as f32)
+        };
+        if self.dimensions == 1 {
+            return std::array::from_fn(|channel| {
+                let (index, fract) = cell(rgb[channel]);
+                let low = self.samples[index][channel];
+                low + (self.samples[index + 1][channel] - low) * fract
+            });
+        }
+
+        // Tetrahedral interpolation: the cube around the color is split in
+        // six tetrahedra along its diagonal, and the color is a weighted sum
+        // of the corners of the one it falls in
+        let ((r, fr), (g, fg), (b, fb)) = (cell(rgb[0]), cell(rgb[1]), cell(rgb[2]));
+        let corner = |dr: usize, dg: usize, db: usize| self.sample(r + dr, g + dg, b + db);
+        let (c000, c111) = (corner(0, 0, 0), corner(1, 1, 1));
+        let (path, weights) = if fr > fg {
+            if fg > fb {
+                ([corner(1, 0, 0), corner(1, 1, 0)], [fr, fg, fb])
+            } else if fr > fb {
+                ([corner(1, 0, 0), corner(1, 0, 1)], [fr, fb, fg])
+            } else {
+                ([corner(0, 0, 1), corner(1, 0, 1)], [fb, fr, fg])
+            }
+        } else if fb > fg {
+            ([corner(0, 0, 1), corner(0, 1, 1)], [fb, fg, fr])
+        } else if fb > fr {
+            ([corner(0, 1, 0), corner(0, 1, 1)], [fg, fb, fr])
+        } else {
+            ([corner(0, 1, 0), corner(1, 1, 0)], [fg, fr, fb])
+        };
+        std::array::from_fn(|i| {
+            c000[i]
+                + weights[0] * (path[0][i] - c000[i])
+                + weights[1] * (path[1][i] - path[0][i])
+                + weights[2] * (c111[i] - path[1][i])
+        })
+    }
+
+    /// Adjust a linear ProPhoto RGB color, with the table effect scaled by
+    /// `amount` (clamped to the range of the table)
+    pub fn apply(&self, rgb: [f32; 3], amount: f64) -> [f32; 3] {
+        let amount = amount.clamp(self.min_amount, self.max_amount) as f32;
+        let table = self.to_table * Vector::new(rgb.map(|value| value as f64));
+        let encoded: [f32; 3] =
+            std::array::from_fn(|i| self.transfer_function.encode(table[i]) as f32);
+        let clipped = encoded.map(|value| value.clamp(0.0, 1.0));
+        let looked_up = self.lookup(clipped);
+        let output: [f32; 3] = std::array::from_fn(|i| {
+            let mut value = clipped[i] + (looked_up[i] - clipped[i]) * amount;
+            if self.gamut == Gamut::Extend {
+                value += encoded[i] - clipped[i];
+            }
+            value
+        });
+        let linear = Vector::new(output.map(|value| self.transfer_function.decode(value as f64)));
+        let rgb = self.from_table * linear;
+        [rgb[0] as f32, rgb[1] as f32, rgb[2] as f32]
+    }
+}
+
+/// The RGB tables of a profile, applied one after the other
+#[derive(Clone, Debug, PartialEq)]
+pub struct RgbTables {
+    pub tables: Vec<RgbTable>,
+}
+
+impl RgbTables {
+    /// Read the contents of an RGBTables tag, which is always big endian:
+    /// the number of tables (1 or 2), then the tables
+    pub fn parse(data: &[u8]) -> crate::Result<Self> {
+        let mut stream = Stream::big_endian(data);
+        let count = stream.get_u32()?;
+        if !(1..=2).contains(&count) {
+            return Err(Error::new(ErrorKind::InvalidCameraProfile));
+        }
+        let tables = (0..count)
+            .map(|_| RgbTable::parse(&mut stream))
+            .collect::<crate::Result<Vec<_>>>()?;
+        Ok(Self { tables })
+    }
+
+    pub fn apply(&self, rgb: [f32; 3], amount: f64) -> [f32; 3] {
+        self.tables
+            .iter()
+            .fold(rgb, |rgb, table| table.apply(rgb, amount))
+    }
+
+    /// Adjust every pixel of a 3 plane floating point image in linear
+    /// ProPhoto RGB
+    pub fn apply_image(&self, image: &mut Image, amount: f64) -> crate::Result<()> {
+        if image.planes() != 3 {
+            return Err(Error::new(ErrorKind::InvalidImageSize));
+        }
+        for pixel in image.data_mut().chunks_exact_mut(3) {
+            let rgb = self.apply([pixel[0], pixel[1], pixel[2]], amount);
+            pixel.copy_from_slice(&rgb);
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_rgb_table_tetrahedral() {
+    // An identity table reproduces colors exactly, in any space
+    let table = RgbTable::new(3, 5, Primaries::Srgb, TransferFunction::Srgb).unwrap();
+    let rgb = [0.3, 0.12, 0.05];
+    let output = table.apply(rgb, 1.0);
+    for i in 0..3 {
+        assert!((output[i] - rgb[i]).abs() < 1e-5);
+    }
+
+    // Tetrahedral interpolation is exact for a table holding a linear map
+    let mut table = RgbTable::new(3, 3, Primaries::ProPhoto, TransferFunction::Linear).unwrap();
+    let map = |[r, g, b]: [f32; 3]| [0.5 * r + 0.25 * g, g, 0.1 * r + 0.9 * b];
+    for r in 0..3 {
+        for g in 0..3 {
+            for b in 0..3 {
+                let rgb = [r as f32 * 0.5, g as f32 * 0.5, b as f32 * 0.5];
+                table.set_sample(r, g, b, map(rgb));
+            }
+        }
+    }
+    for rgb in [[0.9, 0.2, 0.4], [0.1, 0.7, 0.3], [0.45, 0.45, 0.8]] {
+        let output = table.apply(rgb, 1.0);
+        let expected = map(rgb);
+        for i in 0..3 {
+            assert!((output[i] - expected[i]).abs() < 1e-5);
+        }
+    }
+}
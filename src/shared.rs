@@ -1,7 +1,18 @@
+use crate::coord::Coord;
+use crate::errors::{Error, ErrorKind};
+use crate::hue_sat_map::{HueSatMap, ValueEncoding};
 use crate::illuminant::Illuminant;
 use crate::matrix::Matrix;
+use crate::rgb_tables::RgbTables;
+use crate::stream::Stream;
+use crate::tag::codes::ExifTags;
+use crate::tag::values::{LightSource, ProfileEmbedPolicy};
+use crate::temparature::Temperature;
+use crate::tone_curve::ToneCurve;
 use crate::types::rational::SRational;
+use std::num::NonZeroI32;
 
+#[derive(Debug, Clone)]
 pub struct CameraProfileInfo {
     pub big_endian: bool,
 
@@ -66,5 +77,449 @@ pub struct CameraProfileInfo {
     pub tone_curve_offset: u64,
     pub tone_curve_count: u32,
 
+    pub rgb_tables_offset: u64,
+    pub rgb_tables_count: u32,
+
     pub unique_camera_model: String,
 }
+
+impl Default for CameraProfileInfo {
+    fn default() -> Self {
+        Self {
+            big_endian: false,
+            color_planes: 1,
+            calibration_illuminant1: 0,
+            calibration_illuminant2: 0,
+            calibration_illuminant3: 0,
+            illuminant_data1: Illuminant::default(),
+            illuminant_data2: Illuminant::default(),
+            illuminant_data3: Illuminant::default(),
+            color_matrix1: Matrix::default(),
+            color_matrix2: Matrix::default(),
+            color_matrix3: Matrix::default(),
+            forward_matrix1: Matrix::default(),
+            forward_matrix2: Matrix::default(),
+            forward_matrix3: Matrix::default(),
+            reduction_matrix1: Matrix::default(),
+            reduction_matrix2: Matrix::default(),
+            reduction_matrix3: Matrix::default(),
+            profile_calibration_signature: String::new(),
+            profile_name: String::new(),
+            profile_copyright: String::new(),
+            embed_policy: 0,
+            profile_hues: 0,
+            profile_sats: 0,
+            profile_vals: 0,
+            hue_sat_deltas1_offset: 0,
+            hue_sat_deltas1_count: 0,
+            hue_sat_deltas2_offset: 0,
+            hue_sat_deltas2_count: 0,
+            hue_sat_deltas3_offset: 0,
+            hue_sat_deltas3_count: 0,
+            hue_sat_map_encoding: 0,
+            look_table_hues: 0,
+            look_table_sats: 0,
+            look_table_vals: 0,
+            look_table_offset: 0,
+            look_table_count: 0,
+            look_table_encoding: 0,
+            baseline_exposure_offset: SRational::new(0, NonZeroI32::new(1).unwrap()),
+            default_black_render: 0,
+            tone_curve_offset: 0,
+            tone_curve_count: 0,
+            rgb_tables_offset: 0,
+            rgb_tables_count: 0,
+            unique_camera_model: String::new(),
+        }
+    }
+}
+
+impl CameraProfileInfo {
+    /// Parse one of the IlluminantData1..3 tags (DNG 1.6), which describe the
+    /// calibration illuminants set to Other. Returns false for any other tag.
+    pub fn parse_illuminant_data(&mut self, tag_code: u32, data: &[u8]) -> crate::Result<bool> {
+        let illuminant = match tag_code {
+            code if code == ExifTags::IlluminantData1 as u32 => &mut self.illuminant_data1,
+            code if code == ExifTags::IlluminantData2 as u32 => &mut self.illuminant_data2,
+            code if code == ExifTags::IlluminantData3 as u32 => &mut self.illuminant_data3,
+            _ => return Ok(false),
+        };
+        *illuminant = Illuminant::parse(&mut Stream::new(data, self.big_endian))?;
+        Ok(true)
+    }
+
+    /// Number of calibration illuminants, from the color matrices present
+    pub fn illuminant_count(&self) -> usize {
+        if self.color_matrix2.is_empty() {
+            1
+        } else if self.color_matrix3.is_empty() {
+            2
+        } else {
+            3
+        }
+    }
+
+    /// The illuminant of each calibration illuminant
+    fn illuminants(&self) -> [Illuminant; 3] {
+        let lights = [
+            (self.calibration_illuminant1, &self.illuminant_data1),
+            (self.calibration_illuminant2, &self.illuminant_data2),
+            (self.calibration_illuminant3, &self.illuminant_data3),
+        ];
+        lights.map(|(light, data)| match LightSource::from_tiff(light) {
+            Some(light) => Illuminant::illuminant_data(&light, data.clone()),
+            None => data.clone(),
+        })
+    }
+
+    /// How much each calibration illuminant contributes at a white balance,
+    /// for the tables that are interpolated between illuminants the way the
+    /// color matrices are
+    pub fn illuminant_weights(&self, white: &Coord) -> crate::Result<[f64; 3]> {
+        match self.illuminant_count() {
+            1 => Ok([1.0, 0.0, 0.0]),
+            2 => {
+                let lights = self.illuminants();
+                let temperature1 =
+                    Illuminant::calibration_temperature(self.calibration_illuminant1, &lights[0]);
+                let temperature2 =
+                    Illuminant::calibration_temperature(self.calibration_illuminant2, &lights[1]);
+                if temperature1 <= 0.0 || temperature2 <= 0.0 || temperature1 == temperature2 {
+                    return Ok([1.0, 0.0, 0.0]);
+                }
+                // Interpolate in inverse temperature between the lower and
+                // the higher temperature
+                let (low, high) = (
+                    temperature1.min(temperature2),
+                    temperature1.max(temperature2),
+                );
+                let g = match Temperature::from_coord(white)?.temperature {
+                    t if t <= low => 1.0,
+                    t if t >= high => 0.0,
+                    t => (1.0 / t - 1.0 / high) / (1.0 / low - 1.0 / high),
+                };
+                if temperature1 < temperature2 {
+                    Ok([g, 1.0 - g, 0.0])
+                } else {
+                    Ok([1.0 - g, g, 0.0])
+                }
+            }
+            _ => {
+                let weights =
+                    Illuminant::calculate_triple_illuminant_weights(white, &self.illuminants())?;
+                Ok([weights[0], weights[1], weights[2]])
+            }
+        }
+    }
+
+    /// The HueSatMap of a white balance, interpolated between the maps of the
+    /// calibration illuminants. `file` holds the data the offsets refer to.
+    /// None if the profile has no map.
+    pub fn hue_sat_map(&self, file: &[u8], white: &Coord) -> crate::Result<Option<HueSatMap>> {
+        let tables = [
+            (self.hue_sat_deltas1_offset, self.hue_sat_deltas1_count),
+            (self.hue_sat_deltas2_offset, self.hue_sat_deltas2_count),
+            (self.hue_sat_deltas3_offset, self.hue_sat_deltas3_count),
+        ];
+        let encoding = ValueEncoding::from_tiff(self.hue_sat_map_encoding)
+            .ok_or(Error::new(ErrorKind::InvalidCameraProfile))?;
+        let dims = (
+            self.profile_hues as usize,
+            self.profile_sats as usize,
+            self.profile_vals as usize,
+        );
+        let weights = self.illuminant_weights(white)?;
+        let mut maps = Vec::new();
+        for ((offset, count), weight) in tables.into_iter().zip(weights) {
+            if count == 0 || weight <= 0.0 {
+                continue;
+            }
+            let map = self.read_hue_sat_table(file, offset, count, dims, encoding)?;
+            maps.push((map, weight));
+        }
+        // Illuminants without a map of their own use the first one
+        if maps.is_empty() && self.hue_sat_deltas1_count > 0 {
+            return self
+                .read_hue_sat_table(
+                    file,
+                    self.hue_sat_deltas1_offset,
+                    self.hue_sat_deltas1_count,
+                    dims,
+                    encoding,
+                )
+                .map(Some);
+        }
+        let total: f64 = maps.iter().map(|(_, weight)| weight).sum();
+        let maps = maps
+            .iter()
+            .map(|(map, weight)| (map, weight / total))
+            .collect::<Vec<_>>();
+        HueSatMap::interpolate(&maps)
+    }
+
+    /// The ProfileLookTable, if the profile has one. Unlike the HueSatMap it
+    /// does not depend on the white balance, and it is applied later in the
+    /// rendering, to colors that already have the exposure adjustments but
+    /// not the tone curve.
+    pub fn look_table(&self, file: &[u8]) -> crate::Result<Option<HueSatMap>> {
+        if self.look_table_count == 0 {
+            return Ok(None);
+        }
+        let encoding = ValueEncoding::from_tiff(self.look_table_encoding)
+            .ok_or(Error::new(ErrorKind::InvalidCameraProfile))?;
+        let dims = (
+            self.look_table_hues as usize,
+            self.look_table_sats as usize,
+            self.look_table_vals as usize,
+        );
+        self.read_hue_sat_table(
+            file,
+            self.look_table_offset,
+            self.look_table_count,
+            dims,
+            encoding,
+        )
+        .map(Some)
+    }
+
+    /// The ProfileToneCurve, or the ACR3 default curve for profiles without
+    /// one
+    pub fn tone_curve(&self, file: &[u8]) -> crate::Result<ToneCurve> {
+        if self.tone_curve_count == 0 {
+            return Ok(ToneCurve::acr3_default());
+        }
+        let start = usize::try_from(self.tone_curve_offset)
+            .map_err(|_| Error::new(ErrorKind::EndOfStream))?;
+        let data = start
+            .checked_add(self.tone_curve_count as usize * 4)
+            .and_then(|end| file.get(start..end))
+            .ok_or(Error::new(ErrorKind::EndOfStream))?;
+        ToneCurve::parse(
+            &mut Stream::new(data, self.big_endian),
+            self.tone_curve_count as usize,
+        )
+    }
+
+    /// The RGBTables of the profile, if it has any
+    pub fn rgb_tables(&self, file: &[u8]) -> crate::Result<Option<RgbTables>> {
+        if self.rgb_tables_count == 0 {
+            return Ok(None);
+        }
+        let start = usize::try_from(self.rgb_tables_offset)
+            .map_err(|_| Error::new(ErrorKind::EndOfStream))?;
+        let data = start
+            .checked_add(self.rgb_tables_count as usize)
+            .and_then(|end| file.get(start..end))
+            .ok_or(Error::new(ErrorKind::EndOfStream))?;
+        RgbTables::parse(data).map(Some)
+    }
+
+    /// Read a table of FLOAT triplets of the given size
+    fn read_hue_sat_table(
+        &self,
+        file: &[u8],
+        offset: u64,
+        count: u32,
+        (hues, sats, vals): (usize, usize, usize),
+        encoding: ValueEncoding,
+    ) -> crate::Result<HueSatMap> {
+        // The value divisions may be stored as 0 for a 2.5D table
+        let vals = vals.max(1);
+        if count as usize != hues * sats * vals * 3 {
+            return Err(Error::new(ErrorKind::InvalidCameraProfile));
+        }
+        let start = usize::try_from(offset).map_err(|_| Error::new(ErrorKind::EndOfStream))?;
+        let data = start
+            .checked_add(count as usize * 4)
+            .and_then(|end| file.get(start..end))
+            .ok_or(Error::new(ErrorKind::EndOfStream))?;
+        HueSatMap::parse(
+            &mut Stream::new(data, self.big_endian),
+            hues,
+            sats,
+            vals,
+            encoding,
+        )
+    }
+
+    pub fn profile_embed_policy(&self) -> ProfileEmbedPolicy {
+        ProfileEmbedPolicy::from_tiff(self.embed_policy).unwrap_or_default()
+    }
+
+    /// Does the individual camera calibration with this signature apply to
+    /// the profile? Only profiles made for it use it.
+    pub fn uses_camera_calibration(&self, signature: &str) -> bool {
+        !self.profile_calibration_signature.is_empty()
+            && self.profile_calibration_signature == signature
+    }
+
+    /// A digest of the color content of the profile: everything but its
+    /// name, copyright and embed policy, independent of the byte order it
+    /// was stored with. Profiles without color matrices have no fingerprint.
+    pub fn fingerprint(&self, file: &[u8]) -> crate::Result<Option<Fingerprint>> {
+        if self.color_matrix1.is_empty() {
+            return Ok(None);
+        }
+        let mut data = Vec::new();
+        let integers = [
+            self.color_planes,
+            self.calibration_illuminant1,
+            self.calibration_illuminant2,
+            self.calibration_illuminant3,
+            self.profile_hues,
+            self.profile_sats,
+            self.profile_vals,
+            self.hue_sat_map_encoding,
+            self.look_table_hues,
+            self.look_table_sats,
+            self.look_table_vals,
+            self.look_table_encoding,
+            self.default_black_render,
+        ];
+        for value in integers {
+            data.extend_from_slice(&value.to_be_bytes());
+        }
+        for illuminant in [
+            &self.illuminant_data1,
+            &self.illuminant_data2,
+            &self.illuminant_data3,
+        ] {
+            data.extend_from_slice(&illuminant.write(true));
+        }
+        let matrices = [
+            &self.color_matrix1,
+            &self.color_matrix2,
+            &self.color_matrix3,
+            &self.forward_matrix1,
+            &self.forward_matrix2,
+            &self.forward_matrix3,
+            &self.reduction_matrix1,
+            &self.reduction_matrix2,
+            &self.reduction_matrix3,
+        ];
+        for value in matrices
+            .into_iter()
+            .flat_map(|matrix| matrix.data.iter().flatten())
+        {
+            data.extend_from_slice(&value.to_be_bytes());
+        }
+        let tables = [
+            (self.hue_sat_deltas1_offset, self.hue_sat_deltas1_count),
+            (self.hue_sat_deltas2_offset, self.hue_sat_deltas2_count),
+            (self.hue_sat_deltas3_offset, self.hue_sat_deltas3_count),
+            (self.look_table_offset, self.look_table_count),
+            (self.tone_curve_offset, self.tone_curve_count),
+        ];
+        for (offset, count) in tables {
+            let table = self.read_bytes(file, offset, count as usize * 4)?;
+            let mut stream = Stream::new(table, self.big_endian);
+            for _ in 0..count {
+                data.extend_from_slice(&stream.get_f32()?.to_be_bytes());
+            }
+        }
+        // RGB tables are always big endian
+        data.extend_from_slice(self.read_bytes(
+            file,
+            self.rgb_tables_offset,
+            self.rgb_tables_count as usize,
+        )?);
+        let offset = &self.baseline_exposure_offset;
+        data.extend_from_slice(&offset.numerator.to_be_bytes());
+        data.extend_from_slice(&offset.denominator.get().to_be_bytes());
+        data.extend_from_slice(self.profile_calibration_signature.as_bytes());
+        Ok(Some(Fingerprint(md5::compute(&data).0)))
+    }
+
+    fn read_bytes<'f>(&self, file: &'f [u8], offset: u64, count: usize) -> crate::Result<&'f [u8]> {
+        if count == 0 {
+            return Ok(&[]);
+        }
+        let start = usize::try_from(offset).map_err(|_| Error::new(ErrorKind::EndOfStream))?;
+        start
+            .checked_add(count)
+            .and_then(|end| file.get(start..end))
+            .ok_or(Error::new(ErrorKind::EndOfStream))
+    }
+
+    /// The ID of the profile read from `file`
+    pub fn id(&self, file: &[u8]) -> crate::Result<CameraProfileID> {
+        Ok(CameraProfileID {
+            name: self.profile_name.clone(),
+            fingerprint: self.fingerprint(file)?,
+        })
+    }
+}
+
+/// An MD5 digest identifying the color content of a profile
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Fingerprint(pub [u8; 16]);
+
+impl std::fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.iter().try_for_each(|byte| write!(f, "{byte:02X}"))
+    }
+}
+
+/// Identifies a camera profile by name and, optionally, by the fingerprint
+/// of its contents. The default ID picks the default profile of a negative.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct CameraProfileID {
+    pub name: String,
+    pub fingerprint: Option<Fingerprint>,
+}
+
+impl CameraProfileID {
+    pub fn new(name: impl Into<String>, fingerprint: Option<Fingerprint>) -> Self {
+        Self {
+            name: name.into(),
+            fingerprint,
+        }
+    }
+}
+
+#[test]
+fn test_look_table() {
+    use crate::hue_sat_map::HueSatDelta;
+
+    // A 2x2x2 table raising the value of bright colors, stored after 8
+    // bytes of something else
+    let mut file = vec![0_u8; 8];
+    for val in 0..2 {
+        for _ in 0..2 * 2 {
+            for value in [0.0_f32, 1.0, 1.0 + val as f32] {
+                file.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+    }
+    let profile = CameraProfileInfo {
+        look_table_hues: 2,
+        look_table_sats: 2,
+        look_table_vals: 2,
+        look_table_offset: 8,
+        look_table_count: 24,
+        look_table_encoding: ValueEncoding::Srgb as u32,
+        ..Default::default()
+    };
+    let table = profile.look_table(&file).unwrap().unwrap();
+    assert_eq!(table.encoding(), ValueEncoding::Srgb);
+    assert_eq!(
+        table.delta(1, 1, 1),
+        HueSatDelta {
+            hue_shift: 0.0,
+            sat_scale: 1.0,
+            val_scale: 2.0,
+        }
+    );
+    // Black stays black, white clips at white
+    assert_eq!(table.apply([0.0; 3]), [0.0; 3]);
+    assert!(table
+        .apply([1.0; 3])
+        .iter()
+        .all(|value| (value - 1.0).abs() < 1e-6));
+
+    let truncated = CameraProfileInfo {
+        look_table_count: 12,
+        ..profile
+    };
+    assert!(truncated.look_table(&file).is_err());
+}
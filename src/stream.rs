@@ -0,0 +1,114 @@
+use crate::errors::{Error, ErrorKind};
+use crate::Result;
+
+/// A read cursor over an in-memory block of TIFF/DNG data
+#[derive(Debug, Clone)]
+pub struct Stream<'s> {
+    data: &'s [u8],
+    position: usize,
+    big_endian: bool,
+}
+
+impl<'s> Stream<'s> {
+    pub const fn new(data: &'s [u8], big_endian: bool) -> Self {
+        Self {
+            data,
+            position: 0,
+            big_endian,
+        }
+    }
+
+    /// Opcode lists and some DNG 1.6 tags are always stored big endian,
+    /// regardless of the byte order of the file.
+    pub const fn big_endian(data: &'s [u8]) -> Self {
+        Self::new(data, true)
+    }
+
+    pub const fn is_big_endian(&self) -> bool {
+        self.big_endian
+    }
+
+    pub fn set_big_endian(&mut self, big_endian: bool) {
+        self.big_endian = big_endian;
+    }
+
+    pub const fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub const fn position(&self) -> usize {
+        self.position
+    }
+
+    pub fn set_position(&mut self, position: usize) -> Result<()> {
+        if position > self.data.len() {
+            return Err(Error::new(ErrorKind::EndOfStream));
+        }
+        self.position = position;
+        Ok(())
+    }
+
+    pub const fn remaining(&self) -> usize {
+        self.data.len() - self.position
+    }
+
+    pub fn skip(&mut self, count: usize) -> Result<()> {
+        self.get_bytes(count).map(|_| ())
+    }
+
+    pub fn get_bytes(&mut self, count: usize) -> Result<&'s [u8]> {
+        let end = self
+            .position
+            .checked_add(count)
+            .filter(|end| *end <= self.data.len())
+            .ok_or(Error::new(ErrorKind::EndOfStream))?;
+        let bytes = &self.data[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn get_array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.get_bytes(N)?);
+        if !self.big_endian {
+            array.reverse();
+        }
+        Ok(array)
+    }
+
+    pub fn get_u8(&mut self) -> Result<u8> {
+        Ok(self.get_bytes(1)?[0])
+    }
+
+    pub fn get_u16(&mut self) -> Result<u16> {
+        self.get_array().map(u16::from_be_bytes)
+    }
+
+    pub fn get_i16(&mut self) -> Result<i16> {
+        self.get_array().map(i16::from_be_bytes)
+    }
+
+    pub fn get_u32(&mut self) -> Result<u32> {
+        self.get_array().map(u32::from_be_bytes)
+    }
+
+    pub fn get_i32(&mut self) -> Result<i32> {
+        self.get_array().map(i32::from_be_bytes)
+    }
+
+    pub fn get_u64(&mut self) -> Result<u64> {
+        self.get_array().map(u64::from_be_bytes)
+    }
+
+    pub fn get_f32(&mut self) -> Result<f32> {
+        self.get_array().map(f32::from_be_bytes)
+    }
+
+    pub fn get_f64(&mut self) -> Result<f64> {
+        self.get_array().map(f64::from_be_bytes)
+    }
+}
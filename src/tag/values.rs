@@ -25,6 +25,57 @@ pub enum LightSource {
     Other = 255,
 }
 
+impl LightSource {
+    pub fn from_tiff(value: u32) -> Option<Self> {
+        Some(match value {
+            0 => Self::Unknown,
+            1 => Self::Daylight,
+            2 => Self::Fluorescent,
+            3 => Self::Tungsten,
+            4 => Self::Flash,
+            9 => Self::FineWeather,
+            10 => Self::CloudyWeather,
+            11 => Self::Shade,
+            12 => Self::DaylightFluorescent,
+            13 => Self::DayWhiteFluorescent,
+            14 => Self::CoolWhiteFluorescent,
+            15 => Self::WhiteFluorescent,
+            16 => Self::WarmWhiteFluorescent,
+            17 => Self::StandardLightA,
+            18 => Self::StandardLightB,
+            19 => Self::StandardLightC,
+            20 => Self::D55,
+            21 => Self::D65,
+            22 => Self::D75,
+            23 => Self::D50,
+            24 => Self::ISOStudioTungsten,
+            255 => Self::Other,
+            _ => return None,
+        })
+    }
+
+    /// The correlated color temperature of a standard light source, None for
+    /// unknown and custom (Other) light sources
+    pub fn temperature(&self) -> Option<f64> {
+        Some(match self {
+            Self::StandardLightA | Self::Tungsten => 2850.0,
+            Self::ISOStudioTungsten => 3200.0,
+            Self::D50 => 5000.0,
+            Self::D55 | Self::Daylight | Self::FineWeather | Self::Flash | Self::StandardLightB => {
+                5500.0
+            }
+            Self::D65 | Self::StandardLightC | Self::CloudyWeather => 6500.0,
+            Self::D75 | Self::Shade => 7500.0,
+            Self::DaylightFluorescent => (5700.0 + 7100.0) * 0.5,
+            Self::DayWhiteFluorescent => (4600.0 + 5500.0) * 0.5,
+            Self::CoolWhiteFluorescent | Self::Fluorescent => (3800.0 + 4500.0) * 0.5,
+            Self::WhiteFluorescent => (3250.0 + 3800.0) * 0.5,
+            Self::WarmWhiteFluorescent => (2600.0 + 3250.0) * 0.5,
+            Self::Unknown | Self::Other => return None,
+        })
+    }
+}
+
 #[repr(u32)]
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Magic {
@@ -36,3 +87,81 @@ pub enum Magic {
     OlympusA = 0x4F52,
     OlympusB = 0x5352,
 }
+
+pub const DNG_VERSION_1_0_0_0: u32 = 0x01000000;
+pub const DNG_VERSION_1_1_0_0: u32 = 0x01010000;
+pub const DNG_VERSION_1_2_0_0: u32 = 0x01020000;
+pub const DNG_VERSION_1_3_0_0: u32 = 0x01030000;
+pub const DNG_VERSION_1_4_0_0: u32 = 0x01040000;
+pub const DNG_VERSION_1_5_0_0: u32 = 0x01050000;
+pub const DNG_VERSION_1_6_0_0: u32 = 0x01060000;
+
+/// The newest DNG version this crate knows how to read
+pub const DNG_VERSION_CURRENT: u32 = DNG_VERSION_1_6_0_0;
+
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum PhotometricInterpretation {
+    WhiteIsZero = 0,
+    BlackIsZero = 1,
+    Rgb = 2,
+    RgbPalette = 3,
+    TransparencyMask = 4,
+    Cmyk = 5,
+    YCbCr = 6,
+    CieLab = 8,
+    IccLab = 9,
+    ItuLab = 10,
+    Cfa = 32803,
+    LinearRaw = 34892,
+    Depth = 51177,
+    PhotometricMask = 52527,
+}
+
+impl PhotometricInterpretation {
+    pub fn from_tiff(value: u32) -> Option<Self> {
+        Some(match value {
+            0 => Self::WhiteIsZero,
+            1 => Self::BlackIsZero,
+            2 => Self::Rgb,
+            3 => Self::RgbPalette,
+            4 => Self::TransparencyMask,
+            5 => Self::Cmyk,
+            6 => Self::YCbCr,
+            8 => Self::CieLab,
+            9 => Self::IccLab,
+            10 => Self::ItuLab,
+            32803 => Self::Cfa,
+            34892 => Self::LinearRaw,
+            51177 => Self::Depth,
+            52527 => Self::PhotometricMask,
+            _ => return None,
+        })
+    }
+}
+
+/// Whether a camera profile may be copied into other files
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ProfileEmbedPolicy {
+    /// Can be copied into any file
+    #[default]
+    AllowCopying = 0,
+    /// Only embedded in files rendered with it
+    EmbedIfUsed = 1,
+    /// Never embedded in other files
+    EmbedNever = 2,
+    NoRestrictions = 3,
+}
+
+impl ProfileEmbedPolicy {
+    pub fn from_tiff(value: u32) -> Option<Self> {
+        Some(match value {
+            0 => Self::AllowCopying,
+            1 => Self::EmbedIfUsed,
+            2 => Self::EmbedNever,
+            3 => Self::NoRestrictions,
+            _ => return None,
+        })
+    }
+}
@@ -20,7 +20,6 @@ impl RUVT {
         RUVT { r, u, v, t }
     }
 }
-#[allow(warnings)]
 const K_TINT_SCALE: f64 = -3000.0;
 
 const K_TEMP_TABLE: [RUVT; 31] = [
@@ -68,7 +67,7 @@ impl Temperature {
         let mut last_dv: f64 = 0.0;
         let mut last_du: f64 = 0.0;
 
-        for (index, ktable) in K_TEMP_TABLE.iter().skip(1).enumerate() {
+        for (index, ktable) in K_TEMP_TABLE.iter().enumerate().skip(1) {
             let du: f64 = 1.0;
             let dv: f64 = ktable.t;
             let len: f64 = (dv.powi(2) + 1.0).sqrt();
@@ -128,4 +127,61 @@ impl Temperature {
         }
         Err(Error::new(ErrorKind::InvalidTemperature))
     }
+
+    /// The white point of a temperature and tint, the inverse of
+    /// [`Temperature::from_coord`]
+    pub fn to_xy(&self) -> Coord {
+        // Find inverse temperature to use as index.
+        let r = 1.0e6 / self.temperature;
+        // Convert tint to offset in uv space.
+        let offset = self.tint * (1.0 / K_TINT_SCALE);
+
+        // Search for line pair containing coordinate, extrapolating from
+        // the last pair past the end of the table.
+        let last = K_TEMP_TABLE.len() - 2;
+        let index = (0..last)
+            .find(|index| r < K_TEMP_TABLE[index + 1].r)
+            .unwrap_or(last);
+        let (first, second) = (&K_TEMP_TABLE[index], &K_TEMP_TABLE[index + 1]);
+
+        // Find relative weight of first line.
+        let f = (second.r - r) / (second.r - first.r);
+
+        // Interpolate the black body coordinates.
+        let mut u = first.u * f + second.u * (1.0 - f);
+        let mut v = first.v * f + second.v * (1.0 - f);
+
+        // Find vectors along slope for each line.
+        let len1 = (1.0 + first.t * first.t).sqrt();
+        let len2 = (1.0 + second.t * second.t).sqrt();
+        let (uu1, vv1) = (1.0 / len1, first.t / len1);
+        let (uu2, vv2) = (1.0 / len2, second.t / len2);
+
+        // Find vector from black body point.
+        let uu3 = uu1 * f + uu2 * (1.0 - f);
+        let vv3 = vv1 * f + vv2 * (1.0 - f);
+        let len3 = (uu3 * uu3 + vv3 * vv3).sqrt();
+
+        // Adjust coordinate along this vector.
+        u += uu3 / len3 * offset;
+        v += vv3 / len3 * offset;
+
+        // Convert to xy coordinates.
+        Coord::new(1.5 * u / (u - 4.0 * v + 2.0), v / (u - 4.0 * v + 2.0))
+    }
+}
+
+#[test]
+fn test_to_xy_round_trip() {
+    for (temperature, tint) in [
+        (2850.0, 0.0),
+        (5003.0, 12.0),
+        (6504.0, -20.0),
+        (12000.0, 5.0),
+    ] {
+        let xy = Temperature { temperature, tint }.to_xy();
+        let back = Temperature::from_coord(&xy).unwrap();
+        assert!((back.temperature - temperature).abs() < temperature * 1e-3);
+        assert!((back.tint - tint).abs() < 0.1);
+    }
 }
@@ -77,6 +77,31 @@ impl LinearFn for Identity {
     }
 }
 
+/// The sRGB transfer function, encoding linear values to gamma values
+pub struct GammaSrgb;
+
+impl LinearFn for GammaSrgb {
+    fn is_identity() -> bool {
+        false
+    }
+
+    fn evaluate(&self, x: f64) -> f64 {
+        if x <= 0.0031308 {
+            x * 12.92
+        } else {
+            1.055 * x.powf(1.0 / 2.4) - 0.055
+        }
+    }
+
+    fn reverse_evaluate(&self, y: f64) -> f64 {
+        if y <= 0.0031308 * 12.92 {
+            y * (1.0 / 12.92)
+        } else {
+            ((y + 0.055) * (1.0 / 1.055)).powf(2.4)
+        }
+    }
+}
+
 pub trait Normalize: Sized {
     fn normalize(self) -> Self {
         let mut v = self;
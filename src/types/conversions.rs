@@ -28,8 +28,8 @@ impl From<Vector<f64, 3>> for Coord {
 }
 
 impl Vector<f64, 3> {
-    const fn from_xy(c: Coord) -> Self {
-        todo!()
+    fn from_xy(c: Coord) -> Self {
+        Coord::xy_to_xyz(c)
     }
 }
 
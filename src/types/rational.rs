@@ -1,6 +1,6 @@
 use std::num::{NonZeroI32, NonZeroU32};
 
-#[derive(Eq, PartialEq, Debug, Clone)]
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
 pub struct Rational<T, NZ> {
     pub numerator: T,
     pub denominator: NZ,